@@ -0,0 +1,288 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use zigbee_hello_world::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Unable to read {}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "Unable to parse configuration: {}", e),
+            ConfigError::Invalid(reason) => write!(f, "Invalid configuration: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub device: String,
+    pub baud_rate: u32,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            device:
+                "/dev/serial/by-id/usb-Texas_Instruments_CC2531_USB_Dongle_00124B000E896815-if00"
+                    .to_string(),
+            baud_rate: 115200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub channel: u16,
+    // Channels to pick the quietest one from, overriding channel, empty to disable scanning.
+    pub scan_channels: Vec<u16>,
+    pub pan_id: u16,
+    pub extended_pan_id: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            channel: 25,
+            scan_channels: Vec::new(),
+            pan_id: 0x1234,
+            extended_pan_id: 0x0012_4B00_0E89_6815,
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn pan_id(&self) -> PANID {
+        PANID(self.pan_id)
+    }
+
+    /**
+     * Zigbee beacon payload as described in 3.6.7 of the Zigbee specification (05-3474-21),
+     * advertising a ZigBee PRO stack with room for both routers and end devices.
+     */
+    pub fn beacon_payload(&self) -> Vec<u8> {
+        let mut payload = vec![
+            0x00, // Protocol ID
+            0x22, // Stack profile 2, protocol version 2
+            0x84, // Router capacity, device depth 0, end device capacity
+        ];
+        payload.extend_from_slice(&self.extended_pan_id.to_le_bytes());
+        payload.extend_from_slice(&[0xFF, 0xFF, 0xFF]); // TX offset
+        payload.push(0x00); // NWK update ID
+        payload
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub file: Option<PathBuf>, // pcap file to append all frames to, None to disable capturing
+    pub transmitted: bool,     // Whether to capture frames we send as well
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            file: Some(PathBuf::from("test.pcap")),
+            transmitted: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssociationConfig {
+    pub permit: bool,
+    // Extended addresses of devices allowed to join, empty to allow any device.
+    pub allowed_devices: Vec<u64>,
+    // Short addresses handed out to joining devices, in order.
+    pub first_short_address: u16,
+    pub last_short_address: u16,
+}
+
+impl Default for AssociationConfig {
+    fn default() -> Self {
+        Self {
+            permit: true,
+            allowed_devices: Vec::new(),
+            first_short_address: 0x4567,
+            last_short_address: 0xFFF7,
+        }
+    }
+}
+
+impl AssociationConfig {
+    pub fn allows(&self, device: ExtendedAddress) -> bool {
+        self.permit && (self.allowed_devices.is_empty() || self.allowed_devices.contains(&device.0))
+    }
+
+    pub fn short_addresses(&self) -> impl Iterator<Item = ShortAddress> {
+        (self.first_short_address..=self.last_short_address).map(ShortAddress)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub serial: SerialConfig,
+    pub network: NetworkConfig,
+    pub capture: CaptureConfig,
+    pub association: AssociationConfig,
+}
+
+fn parse_hex_u16(value: &str) -> Result<u16, std::num::ParseIntError> {
+    let value = value.trim_start_matches("0x");
+    u16::from_str_radix(value, 16)
+}
+
+fn parse_hex_u64(value: &str) -> Result<u64, std::num::ParseIntError> {
+    let value = value.trim_start_matches("0x");
+    u64::from_str_radix(value, 16)
+}
+
+/**
+ * Command-line options, anything given here overrides the configuration file.
+ */
+#[derive(Debug, StructOpt)]
+#[structopt(name = "zigbee_hello_world")]
+pub struct Options {
+    /// TOML configuration file
+    #[structopt(short, long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Serial device of the radio
+    #[structopt(long)]
+    pub device: Option<String>,
+    #[structopt(long)]
+    pub baud_rate: Option<u32>,
+    #[structopt(long)]
+    pub channel: Option<u16>,
+    /// Start the network on the quietest of all channels
+    #[structopt(long, conflicts_with = "channel")]
+    pub scan: bool,
+    /// PAN ID in hexadecimal
+    #[structopt(long, parse(try_from_str = parse_hex_u16))]
+    pub pan_id: Option<u16>,
+    /// Extended PAN ID in hexadecimal
+    #[structopt(long, parse(try_from_str = parse_hex_u64))]
+    pub extended_pan_id: Option<u64>,
+    /// pcap file to append captured frames to
+    #[structopt(long, parse(from_os_str))]
+    pub capture: Option<PathBuf>,
+    /// Disable capturing
+    #[structopt(long, conflicts_with = "capture")]
+    pub no_capture: bool,
+    /// Do not allow new devices to join
+    #[structopt(long)]
+    pub no_association: bool,
+}
+
+impl Config {
+    pub fn parse(data: &str) -> Result<Config, ConfigError> {
+        toml::from_str(data).map_err(ConfigError::Parse)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let data =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Config::parse(&data)
+    }
+
+    /**
+     * Loads the configuration file given in the options, if any, applies the overrides from the
+     * command line, and validates the result.
+     */
+    pub fn from_options(options: Options) -> Result<Config, ConfigError> {
+        let mut config = match &options.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.apply(options);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, options: Options) {
+        if let Some(device) = options.device {
+            self.serial.device = device;
+        }
+        if let Some(baud_rate) = options.baud_rate {
+            self.serial.baud_rate = baud_rate;
+        }
+        if let Some(channel) = options.channel {
+            self.network.channel = channel;
+            self.network.scan_channels.clear();
+        }
+        if options.scan {
+            self.network.scan_channels = (11..=26).collect();
+        }
+        if let Some(pan_id) = options.pan_id {
+            self.network.pan_id = pan_id;
+        }
+        if let Some(extended_pan_id) = options.extended_pan_id {
+            self.network.extended_pan_id = extended_pan_id;
+        }
+        if options.capture.is_some() {
+            self.capture.file = options.capture;
+        }
+        if options.no_capture {
+            self.capture.file = None;
+        }
+        if options.no_association {
+            self.association.permit = false;
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.serial.device.is_empty() {
+            return Err(ConfigError::Invalid("serial.device must be set"));
+        }
+        if self.serial.baud_rate == 0 {
+            return Err(ConfigError::Invalid("serial.baud_rate must be non-zero"));
+        }
+        // Only the 2.4GHz O-QPSK PHY is supported by the radio
+        if self.network.channel < 11 || self.network.channel > 26 {
+            return Err(ConfigError::Invalid(
+                "network.channel must be between 11 and 26",
+            ));
+        }
+        if self
+            .network
+            .scan_channels
+            .iter()
+            .any(|channel| *channel < 11 || *channel > 26)
+        {
+            return Err(ConfigError::Invalid(
+                "network.scan_channels must be between 11 and 26",
+            ));
+        }
+        if self.network.pan_id == PANID::broadcast().0 {
+            return Err(ConfigError::Invalid("network.pan_id can not be 0xFFFF"));
+        }
+        if self.network.extended_pan_id == 0 || self.network.extended_pan_id == u64::MAX {
+            return Err(ConfigError::Invalid(
+                "network.extended_pan_id can not be 0 or 0xFFFFFFFFFFFFFFFF",
+            ));
+        }
+        // 0x0000 is the coordinator itself, 0xFFF8 and up are reserved for broadcasts.
+        if self.association.first_short_address == 0
+            || self.association.last_short_address > 0xFFF7
+            || self.association.first_short_address > self.association.last_short_address
+        {
+            return Err(ConfigError::Invalid(
+                "association short addresses must be an ascending range within 0x0001-0xFFF7",
+            ));
+        }
+        Ok(())
+    }
+}
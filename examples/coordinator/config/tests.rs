@@ -0,0 +1,137 @@
+use crate::config::{Config, ConfigError, Options};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use zigbee_hello_world::ieee802154::{ExtendedAddress, ShortAddress};
+
+#[test]
+fn test_parse_config() {
+    let config = Config::parse(
+        r#"
+        [serial]
+        device = "/dev/ttyACM0"
+        baud_rate = 460800
+
+        [network]
+        channel = 15
+        scan_channels = [11, 15, 20, 25]
+        pan_id = 0xABCD
+        extended_pan_id = 0x0011223344556677
+
+        [capture]
+        file = "capture.pcap"
+        transmitted = false
+
+        [association]
+        allowed_devices = [0x00124B0000000002]
+        first_short_address = 0x0001
+        last_short_address = 0x0010
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.serial.device, "/dev/ttyACM0");
+    assert_eq!(config.serial.baud_rate, 460800);
+    assert_eq!(config.network.channel, 15);
+    assert_eq!(config.network.scan_channels, vec![11, 15, 20, 25]);
+    assert_eq!(config.network.pan_id, 0xABCD);
+    assert_eq!(
+        config.network.beacon_payload(),
+        vec![
+            0x00, 0x22, 0x84, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0xFF, 0xFF, 0xFF,
+            0x00
+        ]
+    );
+    assert_eq!(config.capture.file, Some(PathBuf::from("capture.pcap")));
+    assert!(!config.capture.transmitted);
+    assert!(config.association.permit);
+    assert!(config
+        .association
+        .allows(ExtendedAddress(0x00124B0000000002)));
+    assert!(!config
+        .association
+        .allows(ExtendedAddress(0x00124B0000000003)));
+    assert_eq!(config.association.short_addresses().count(), 16);
+    assert_eq!(
+        config.association.short_addresses().next(),
+        Some(ShortAddress(0x0001))
+    );
+}
+
+#[test]
+fn test_default_config() {
+    // Without a configuration file, the coordinator runs as it always did.
+    let config = Config::parse("").unwrap();
+    assert_eq!(config, Config::default());
+    let example = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("coordinator.example.toml");
+    assert_eq!(Config::load(&example).unwrap(), config);
+    config.validate().unwrap();
+    assert_eq!(
+        config.network.beacon_payload(),
+        vec![
+            0x00, 0x22, 0x84, 0x15, 0x68, 0x89, 0x0e, 0x00, 0x4b, 0x12, 0x00, 0xFF, 0xFF, 0xFF,
+            0x00,
+        ]
+    );
+}
+
+#[test]
+fn test_reject_invalid_config() {
+    assert!(matches!(
+        Config::parse("[network]\nchanel = 11"),
+        Err(ConfigError::Parse(_))
+    ));
+    for data in &[
+        "[serial]\nbaud_rate = 0",
+        "[network]\nchannel = 10",
+        "[network]\nchannel = 27",
+        "[network]\nscan_channels = [11, 30]",
+        "[network]\npan_id = 0xFFFF",
+        "[network]\nextended_pan_id = 0",
+        "[association]\nfirst_short_address = 0",
+        "[association]\nlast_short_address = 0xFFF8",
+        "[association]\nfirst_short_address = 0x10\nlast_short_address = 0x0F",
+    ] {
+        let config = Config::parse(data).unwrap();
+        assert!(
+            matches!(config.validate(), Err(ConfigError::Invalid(_))),
+            "{} should be rejected",
+            data
+        );
+    }
+}
+
+#[test]
+fn test_command_line_overrides() {
+    let options = Options::from_iter_safe(&[
+        "zigbee_hello_world",
+        "--device",
+        "/dev/ttyUSB1",
+        "--channel",
+        "11",
+        "--pan-id",
+        "0x1A62",
+        "--extended-pan-id",
+        "DDDDDDDDDDDDDDDD",
+        "--no-capture",
+        "--no-association",
+    ])
+    .unwrap();
+    let config = Config::from_options(options).unwrap();
+    assert_eq!(config.serial.device, "/dev/ttyUSB1");
+    assert_eq!(config.serial.baud_rate, 115200);
+    assert_eq!(config.network.channel, 11);
+    assert_eq!(config.network.pan_id, 0x1A62);
+    assert_eq!(config.network.extended_pan_id, 0xDDDDDDDDDDDDDDDD);
+    assert_eq!(config.capture.file, None);
+    assert!(!config.association.permit);
+
+    let options = Options::from_iter_safe(&["zigbee_hello_world", "--scan"]).unwrap();
+    let config = Config::from_options(options).unwrap();
+    assert_eq!(config.network.scan_channels.len(), 16);
+
+    let options = Options::from_iter_safe(&["zigbee_hello_world", "--channel", "30"]).unwrap();
+    assert!(matches!(
+        Config::from_options(options),
+        Err(ConfigError::Invalid(_))
+    ));
+}
@@ -0,0 +1,448 @@
+use async_std::task;
+use futures::channel::mpsc;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{Stream, StreamExt};
+use futures::task::SpawnExt;
+mod config;
+use config::{AssociationConfig, Config, Options};
+use futures::{future, select};
+use zigbee_hello_world::ieee802154::frame;
+use zigbee_hello_world::ieee802154::mac;
+use zigbee_hello_world::ieee802154::pib::PIBProperty;
+use zigbee_hello_world::ieee802154::services::{mcps, mlme};
+use zigbee_hello_world::ieee802154::{ExtendedAddress, ShortAddress};
+
+use std::collections::HashMap;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use structopt::StructOpt;
+use zigbee_hello_world::async_std_executor::AsyncStdExecutor;
+use zigbee_hello_world::radio::{self, RadioRequest, RadioResponse};
+
+async fn send_request(
+    mlme_input: &mut (dyn Sink<mlme::Input, Error = mpsc::SendError> + Unpin + Send),
+    request: mlme::Request,
+) {
+    mlme_input
+        .send(mlme::Input::Request(request))
+        .await
+        .unwrap();
+}
+async fn send_response(
+    mlme_input: &mut (dyn Sink<mlme::Input, Error = mpsc::SendError> + Unpin + Send),
+    response: mlme::Response,
+) {
+    mlme_input
+        .send(mlme::Input::Response(response))
+        .await
+        .unwrap();
+}
+async fn send_mcps_request(
+    mcps_input: &mut (dyn Sink<mcps::Input, Error = mpsc::SendError> + Unpin + Send),
+    request: mcps::Request,
+) {
+    mcps_input
+        .send(mcps::Input::Request(request))
+        .await
+        .unwrap();
+}
+
+/**
+ * Hands out short addresses to joining devices as allowed by the association policy.
+ * A device that joins again gets the same address as before.
+ */
+struct AddressAllocator {
+    policy: AssociationConfig,
+    assigned: HashMap<ExtendedAddress, ShortAddress>,
+    free: Box<dyn Iterator<Item = ShortAddress> + Send>,
+}
+
+impl AddressAllocator {
+    fn new(policy: AssociationConfig) -> Self {
+        let free = Box::new(policy.short_addresses());
+        Self {
+            policy,
+            assigned: HashMap::new(),
+            free,
+        }
+    }
+
+    fn allocate(
+        &mut self,
+        device: ExtendedAddress,
+    ) -> Result<ShortAddress, frame::AssociationError> {
+        if !self.policy.allows(device) {
+            return Err(frame::AssociationError::PANAccessDenied);
+        }
+        if let Some(address) = self.assigned.get(&device) {
+            return Ok(*address);
+        }
+        let address = self
+            .free
+            .next()
+            .ok_or(frame::AssociationError::PANAtCapacity)?;
+        self.assigned.insert(device, address);
+        Ok(address)
+    }
+
+    fn lookup(&self, device: ExtendedAddress) -> Option<ShortAddress> {
+        self.assigned.get(&device).copied()
+    }
+}
+
+#[derive(Debug)]
+enum MainloopInput {
+    Mlme(mlme::Output),
+    Mcps(mcps::Output),
+}
+
+/**
+ * Picks the channel with the least energy on it out of the configured scan channels,
+ * or the configured channel if scanning is disabled.
+ */
+async fn select_channel(
+    config: &Config,
+    mlme_input: &mut (dyn Sink<mlme::Input, Error = mpsc::SendError> + Unpin + Send),
+    mlme_output: &mut (dyn Stream<Item = mlme::Output> + Unpin + Send),
+) -> u16 {
+    if config.network.scan_channels.is_empty() {
+        return config.network.channel;
+    }
+    send_request(
+        mlme_input,
+        mlme::Request::Scan(mlme::ScanRequest {
+            scan_type: mlme::ScanType::EnergyDetect,
+            scan_channels: config.network.scan_channels.clone(),
+            scan_duration: 5,
+            channel_page: 0,
+        }),
+    )
+    .await;
+    while let Some(output) = mlme_output.next().await {
+        if let mlme::Output::Confirm(mlme::Confirm::Scan(confirm)) = output {
+            println!("Energy per channel: {:?}", confirm.energy_detect_list);
+            return config
+                .network
+                .scan_channels
+                .iter()
+                .zip(confirm.energy_detect_list.iter())
+                .min_by_key(|(_, energy)| **energy)
+                .map_or(config.network.channel, |(channel, _)| *channel);
+        }
+    }
+    config.network.channel
+}
+
+/**
+ * Normal startup described in 6.3.3.1 of 802.15.4-2015:
+ * - MLME-RESET with SetDefaultPIB = TRUE
+ * - MLME-START with PanCoordinator set to TRUE and CoordRealignment set to FALSE
+ */
+
+async fn mainloop(
+    config: Config,
+    mut mlme_input: Box<dyn Sink<mlme::Input, Error = mpsc::SendError> + Unpin + Send>,
+    mut mlme_output: Box<dyn Stream<Item = mlme::Output> + Unpin + Send>,
+    mut mcps_input: Box<dyn Sink<mcps::Input, Error = mpsc::SendError> + Unpin + Send>,
+    mcps_output: Box<dyn Stream<Item = mcps::Output> + Unpin + Send>,
+) {
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Reset(mlme::ResetRequest {
+            set_default_pib: true,
+        }),
+    )
+    .await;
+    let channel = select_channel(&config, mlme_input.as_mut(), mlme_output.as_mut()).await;
+    println!("Starting network on channel {}", channel);
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Set(mlme::SetRequest {
+            attribute: PIBProperty::PhyCurrentChannel,
+            value: channel.into(),
+        }),
+    )
+    .await;
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Set(mlme::SetRequest {
+            attribute: PIBProperty::MacAssociationPermit,
+            value: config.association.permit.into(),
+        }),
+    )
+    .await;
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Set(mlme::SetRequest {
+            attribute: PIBProperty::MacShortAddress,
+            value: ShortAddress(0x0000).into(),
+        }),
+    )
+    .await;
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Set(mlme::SetRequest {
+            attribute: PIBProperty::MacBeaconPayload,
+            value: config.network.beacon_payload().into(),
+        }),
+    )
+    .await;
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Set(mlme::SetRequest {
+            attribute: PIBProperty::MacBeaconAutoRespond,
+            value: true.into(),
+        }),
+    )
+    .await;
+    send_request(
+        mlme_input.as_mut(),
+        mlme::Request::Start(mlme::StartRequest {
+            pan_id: config.network.pan_id(),
+            channel_number: channel,
+            channel_page: 0,
+            start_time: 0,
+            beacon_order: 15,
+            superframe_order: 15,
+            pan_coordinator: true,
+            battery_life_extension: false,
+            coord_realignment: false,
+        }),
+    )
+    .await;
+    let mut allocator = AddressAllocator::new(config.association.clone());
+    let mut mlme_output = mlme_output.fuse();
+    let mut mcps_output = mcps_output.fuse();
+    while let Some(input) = select! {
+        x = mlme_output.next() => x.map(MainloopInput::Mlme),
+        x = mcps_output.next() => x.map(MainloopInput::Mcps),
+    } {
+        match input {
+            MainloopInput::Mlme(mlme::Output::Indication(mlme::Indication::BeaconRequest {
+                beacon_type,
+                src_addr: _,
+                dst_pan_id: _,
+                header_ie_list: _,
+                payload_ie_list: _,
+            })) => {
+                println!("Beacon request!");
+                let request = mlme::BeaconRequest {
+                    beacon_type,
+                    channel,
+                    channel_page: 0,
+                    superframe_order: 15,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                    dst_addr: None,
+                };
+                send_request(mlme_input.as_mut(), mlme::Request::Beacon(request)).await;
+            }
+            MainloopInput::Mlme(mlme::Output::Indication(mlme::Indication::Associate {
+                device_address,
+                capability_information,
+            })) => {
+                let address = match allocator.allocate(device_address) {
+                    Ok(address) => address,
+                    Err(e) => {
+                        println!("Refusing association of {:?}: {:?}", device_address, e);
+                        send_response(
+                            mlme_input.as_mut(),
+                            mlme::Response::Associate {
+                                device_address,
+                                fast_association: capability_information.fast_association,
+                                status: Err(e),
+                            },
+                        )
+                        .await;
+                        continue;
+                    }
+                };
+                let mut data = Vec::new();
+                let mut nwk_header = vec![0x48, 0x00]; // FCF
+                nwk_header.extend_from_slice(&address.0.to_le_bytes()); // Destination
+                nwk_header.extend_from_slice(&[
+                    0x00, 0x00, // Source
+                    0x1E, // Radius (30),
+                    0x28, // Sequence
+                ]);
+                let mut aps_header = vec![
+                    0x21, 0x06, 0x10, 0x01, 0x00, 0x00, 0x00, 0xe3, 0xbd, 0x18, 0x74, 0x09, 0x2c,
+                    0x2c, 0xa3, 0x58, 0x1d, 0x8a, 0x23, 0xb9, 0x6c, 0x3b, 0x80, 0xf0, 0xad, 0x27,
+                    0x1c, 0x59, 0x8a, 0xdf, 0x27, 0xbc, 0x21, 0xc7, 0x47, 0xf0, 0x31, 0x74, 0x80,
+                    0xbc, 0x8c, 0x53, 0x88, 0x11, 0x8f, 0x02,
+                ];
+                data.append(&mut nwk_header);
+                data.append(&mut aps_header);
+                send_response(
+                    mlme_input.as_mut(),
+                    mlme::Response::Associate {
+                        device_address,
+                        fast_association: capability_information.fast_association,
+                        status: Ok(Some(address)),
+                    },
+                )
+                .await;
+                send_mcps_request(
+                    mcps_input.as_mut(),
+                    mcps::Request::Data(mcps::DataRequest {
+                        source_addressing_mode: frame::AddressingMode::Short,
+                        destination: Some(frame::FullAddress {
+                            pan_id: config.network.pan_id(),
+                            address: address.into(),
+                        }),
+                        msdu: data,
+                        msdu_handle: mcps::MsduHandle::new(),
+                        ack_tx: true,
+                        indirect_tx: true,
+                        security_level: frame::SecurityLevel::None,
+                        key_identifier: frame::KeyIdentifier::Implicit(),
+                        seq_num_suppressed: false,
+                        header_ie_list: vec![],
+                        payload_ie_list: vec![],
+                    }),
+                )
+                .await
+            }
+            MainloopInput::Mlme(mlme::Output::Indication(mlme::Indication::Orphan {
+                orphan_address,
+            })) => {
+                // Only devices that joined since we started can be realigned.
+                let short_address = allocator.lookup(orphan_address);
+                send_response(
+                    mlme_input.as_mut(),
+                    mlme::Response::Orphan {
+                        orphan_address,
+                        short_address: short_address.unwrap_or_else(ShortAddress::broadcast),
+                        associated_member: short_address.is_some(),
+                    },
+                )
+                .await;
+            }
+            MainloopInput::Mlme(mlme::Output::Indication(mlme::Indication::CommStatus {
+                destination,
+                status,
+                ..
+            })) => {
+                if let Err(e) = status {
+                    println!("Response to {:?} was not delivered: {:?}", destination, e);
+                }
+            }
+            input => println!("Mainloop unhandled input: {:?}", input),
+        }
+    }
+}
+
+fn open_serial(
+    path: &str,
+    baud_rate: u32,
+) -> std::io::Result<(async_std::fs::File, async_std::fs::File)> {
+    let portin = serialport::TTYPort::open(&serialport::new(path, baud_rate))?;
+    let portout = portin.try_clone_native()?;
+    let portin = unsafe { async_std::fs::File::from_raw_fd(portin.into_raw_fd()) };
+    let portout = unsafe { async_std::fs::File::from_raw_fd(portout.into_raw_fd()) };
+    Ok((portin, portout))
+}
+
+fn capture_packet(capture: &Mutex<pcap::Capture<pcap::Dead>>, path: &Path, data: &[u8]) {
+    let header = pcap::PacketHeader {
+        ts: libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        },
+        caplen: data.len() as u32,
+        len: data.len() as u32,
+    };
+    let packet = pcap::Packet {
+        header: &header,
+        data,
+    };
+    match capture.lock().unwrap().savefile_append(path) {
+        Ok(mut savefile) => savefile.write(&packet),
+        Err(e) => println!("Unable to capture to {}: {:?}", path.display(), e),
+    }
+}
+
+fn main() {
+    println!("Hello world!");
+    let config = match Config::from_options(Options::from_args()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let exec = AsyncStdExecutor::new();
+    let serial = config.serial.clone();
+    let (radio_requests, radio_responses, radio_stats) =
+        radio::start_radio_reconnecting(exec.clone(), move || {
+            open_serial(&serial.device, serial.baud_rate)
+        });
+    exec.spawn(async move {
+        loop {
+            task::sleep(std::time::Duration::from_secs(60)).await;
+            println!(
+                "Radio link: {} frames received, {} dropped, {} bytes skipped",
+                radio_stats.frames_received(),
+                radio_stats.frames_dropped(),
+                radio_stats.bytes_skipped()
+            );
+        }
+    })
+    .unwrap();
+
+    let capture = pcap::Capture::dead(pcap::Linktype(195)).unwrap();
+    let capture = Arc::new(Mutex::new(capture));
+    let capture2 = capture.clone();
+
+    let capture_file = config.capture.file.clone();
+    let radio_responses = radio_responses.map(move |response| {
+        if let (RadioResponse::OnPacket(packet), Some(path)) = (&response, &capture_file) {
+            let mut packet_data = packet.data.clone();
+            packet_data.push(packet.rssi);
+            packet_data.push(packet.link_quality | 0x80);
+            capture_packet(&capture, path, &packet_data);
+        }
+        response
+    });
+    let capture = capture2;
+
+    let capture_file = config
+        .capture
+        .file
+        .clone()
+        .filter(|_| config.capture.transmitted);
+    let radio_requests = radio_requests.with(move |request| {
+        if let (RadioRequest::SendPacket(_token, packet), Some(path)) = (&request, &capture_file) {
+            let mut packet_data = packet.clone();
+            packet_data.push(0);
+            packet_data.push(0x80);
+            capture_packet(&capture, path, &packet_data);
+        }
+        future::ready(Ok(request))
+    });
+
+    let (mlme_input_in, mlme_input_out) = mpsc::unbounded();
+    let (mlme_output_in, mlme_output_out) = mpsc::unbounded();
+    let (mcps_input_in, mcps_input_out) = mpsc::unbounded();
+    let (mcps_output_in, mcps_output_out) = mpsc::unbounded();
+    println!("Done?");
+    exec.spawn(mac::service::start(
+        Box::pin(radio_requests),
+        Box::pin(radio_responses),
+        Box::pin(mlme_input_out),
+        Box::pin(mlme_output_in),
+        Box::pin(mcps_input_out),
+        Box::pin(mcps_output_in),
+    ))
+    .unwrap();
+    exec.spawn(mainloop(
+        config,
+        Box::new(mlme_input_in),
+        Box::new(mlme_output_out),
+        Box::new(mcps_input_in),
+        Box::new(mcps_output_out),
+    ))
+    .unwrap();
+    task::block_on(exec);
+}
@@ -19,6 +19,12 @@ impl AsyncStdExecutor {
     }
 }
 
+impl Default for AsyncStdExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Spawn for AsyncStdExecutor {
     fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
         let join_handle = async_std::task::spawn(future);
@@ -0,0 +1,51 @@
+use async_std::task;
+use futures::stream::StreamExt;
+use futures::task::SpawnExt;
+use serialport::SerialPort;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use zigbee_hello_world::async_std_executor::AsyncStdExecutor;
+use zigbee_hello_world::radio::emulator::start_emulator;
+use zigbee_hello_world::radio::simulated::SimulatedRadioConfig;
+
+/**
+ * Emulates a CC2531 dongle running the serial bridge firmware on a pseudo-terminal.
+ * Usage: zpb_emulator [link], where link is an optional path at which a symlink to the
+ * pseudo-terminal is created, to be used in place of /dev/serial/by-id/...
+ */
+fn main() {
+    let (master, slave) = serialport::TTYPort::pair().unwrap();
+    let slave_name = slave.name().unwrap();
+    println!("Emulating ZPB radio on {}", slave_name);
+    if let Some(link) = std::env::args().nth(1) {
+        std::fs::remove_file(&link).unwrap_or(());
+        std::os::unix::fs::symlink(&slave_name, &link).unwrap();
+        println!("Linked {} to {}", link, slave_name);
+    }
+
+    let portout = master.try_clone_native().unwrap();
+    let portin = unsafe { async_std::fs::File::from_raw_fd(master.into_raw_fd()) };
+    let portout = unsafe { async_std::fs::File::from_raw_fd(portout.into_raw_fd()) };
+
+    let exec = AsyncStdExecutor::new();
+    let air = start_emulator(
+        exec.clone(),
+        portin,
+        portout,
+        SimulatedRadioConfig::default(),
+    );
+    let receptions = air.receptions;
+    exec.spawn(air.transmissions.for_each(move |transmission| {
+        // Keep the reception side open, such that the radio keeps running.
+        let _ = &receptions;
+        println!(
+            "Transmission on channel {}: {:02X?}",
+            transmission.channel, transmission.data
+        );
+        futures::future::ready(())
+    }))
+    .unwrap();
+    task::block_on(exec);
+    // Keep the slave side open until the end, otherwise reads on the master fail as soon as the
+    // coordinator disconnects.
+    drop(slave);
+}
@@ -1,4 +1,4 @@
-use crate::pack::{ExtEnum, Pack, PackError, PackTagged, PackTarget, UnpackError};
+use crate::pack::{ExtEnum, Pack, PackError, PackTagged, PackTarget, UnpackError, VecPackTarget};
 use bitfield::bitfield;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Pack)]
@@ -23,7 +23,6 @@ impl Into<u16> for ShortAddress {
 }
 
 impl ShortAddress {
-    #[allow(dead_code)]
     pub fn broadcast() -> Self {
         ShortAddress(0xFFFF)
     }
@@ -139,18 +138,31 @@ pub enum AddressingMode {
     Extended = 3,
 }
 
+// IEEE Std 802.15.4 - 2015: 7.2.1.9 - Table 7-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ExtEnum)]
+#[tag_type(u16)]
+pub enum FrameVersion {
+    Ieee2003 = 0,
+    Ieee2006 = 1,
+    Ieee2015 = 2,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Frame {
+    pub version: FrameVersion,
     pub frame_pending: bool,
     pub acknowledge_request: bool,
     pub sequence_number: Option<u8>,
     pub destination: Option<FullAddress>,
     pub source: Option<FullAddress>,
+    pub security: Option<AuxiliarySecurityHeader>,
+    pub header_ies: Vec<HeaderIE>,
+    pub payload_ies: Vec<PayloadIE>,
     pub frame_type: FrameType,
 }
 
 bitfield! {
-    #[derive(Pack)]
+    #[derive(Pack, Clone, Copy, PartialEq, Eq)]
     pub struct FrameControl(u16);
     impl Debug;
     pub frame_type, set_frame_type: 2, 0;
@@ -166,83 +178,229 @@ bitfield! {
     pub source_addressing_mode, set_source_addressing_mode: 15, 14;
 }
 
-impl Frame {
+impl FrameControl {
+    /**
+     * Whether the destination and source PAN IDs are present in the frame. Frames of version 2
+     * follow Table 7-2 of 802.15.4-2015, older frames only leave out the source PAN ID when PAN
+     * ID compression is set and a destination is present.
+     */
+    fn pan_ids_present(&self) -> (bool, bool) {
+        let destination = self.destination_addressing_mode() != AddressingMode::None.into_tag();
+        let source = self.source_addressing_mode() != AddressingMode::None.into_tag();
+        let compression = self.pan_id_compression() != 0;
+        if self.frame_version() < FrameVersion::Ieee2015.into_tag() {
+            return (destination, source && !(compression && destination));
+        }
+        let both_extended = self.destination_addressing_mode()
+            == AddressingMode::Extended.into_tag()
+            && self.source_addressing_mode() == AddressingMode::Extended.into_tag();
+        match (destination, source) {
+            (false, false) => (compression, false),
+            (true, false) => (!compression, false),
+            (false, true) => (false, !compression),
+            (true, true) if both_extended => (!compression, false),
+            (true, true) => (true, !compression),
+        }
+    }
+
+    /**
+     * Unpacks the addressing fields following the sequence number. PAN IDs left out of the frame
+     * are taken from the destination if possible, and are the broadcast PAN ID otherwise.
+     */
+    pub fn unpack_addresses<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<(Addresses, &'a [u8]), UnpackError> {
+        let (destination_pan_present, source_pan_present) = self.pan_ids_present();
+        let (destination_pan, data) = <Option<PANID>>::unpack_data(destination_pan_present, data)?;
+        let (destination, data) = Header::unpack_address(
+            AddressingMode::try_from_tag(self.destination_addressing_mode())?,
+            destination_pan.unwrap_or_else(PANID::broadcast),
+            data,
+        )?;
+        let (source_pan, data) = <Option<PANID>>::unpack_data(source_pan_present, data)?;
+        let (source, data) = Header::unpack_address(
+            AddressingMode::try_from_tag(self.source_addressing_mode())?,
+            source_pan
+                .or(destination_pan)
+                .unwrap_or_else(PANID::broadcast),
+            data,
+        )?;
+        Ok(((destination, source), data))
+    }
+}
+
+// Destination and source address of a frame
+pub type Addresses = (Option<FullAddress>, Option<FullAddress>);
+
+/**
+ * Everything in front of the payload of a frame, up to and including the header IEs. This is the
+ * part of a secured frame that is authenticated, but never encrypted.
+ */
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Header {
+    pub frame_control: FrameControl,
+    pub sequence_number: Option<u8>,
+    pub destination: Option<FullAddress>,
+    pub source: Option<FullAddress>,
+    pub security: Option<AuxiliarySecurityHeader>,
+    pub header_ies: Vec<HeaderIE>,
+    pub header_termination: Option<HeaderTermination>,
+}
+
+// The header IEs of a frame, and how they were terminated
+type HeaderIEs = (Vec<HeaderIE>, Option<HeaderTermination>);
+
+impl Header {
     fn unpack_address(
         mode: AddressingMode,
-        previous_pan: Option<PANID>,
+        pan_id: PANID,
         data: &[u8],
     ) -> Result<(Option<FullAddress>, &[u8]), UnpackError> {
         if mode == AddressingMode::None {
             Ok((None, data))
-        } else if let Some(pan_id) = previous_pan {
+        } else {
             let (address, data) = Address::unpack_data(mode, data)?;
             Ok((Some(FullAddress { pan_id, address }), data))
-        } else {
-            let (address, data) = FullAddress::unpack_data(mode, data)?;
-            Ok((Some(address), data))
         }
     }
+
+    fn unpack_header_ies(mut data: &[u8]) -> Result<(HeaderIEs, &[u8]), UnpackError> {
+        let mut header_ies = vec![];
+        while !data.is_empty() {
+            let (descriptor, rest) = HeaderIEDescriptor::unpack(data)?;
+            let termination = match descriptor.element_id() {
+                HEADER_TERMINATION_1_IE => HeaderTermination::PayloadIEs,
+                HEADER_TERMINATION_2_IE => HeaderTermination::Payload,
+                _ => {
+                    let (ie, rest) = HeaderIE::unpack(data)?;
+                    header_ies.push(ie);
+                    data = rest;
+                    continue;
+                }
+            };
+            let (_, rest) = split_content(rest, descriptor.length() as usize)?;
+            return Ok(((header_ies, Some(termination)), rest));
+        }
+        // Without a termination IE, the frame ends after the header IEs.
+        Ok(((header_ies, None), data))
+    }
 }
 
-impl Pack for Frame {
+impl Pack for Header {
     fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
-        let (fc, data) = FrameControl::unpack(data)?;
-        let frame_pending = fc.frame_pending() != 0;
-        let acknowledge_request = fc.acknowledge_request() != 0;
-        let (sequence_number, data) =
-            <Option<u8>>::unpack_data(fc.sequence_number_supression() == 0, data)?;
-        let (destination, data) = <Frame>::unpack_address(
-            AddressingMode::try_from_tag(fc.destination_addressing_mode())?,
-            None,
-            data,
-        )?;
-        let (source, data) = <Frame>::unpack_address(
-            AddressingMode::try_from_tag(fc.source_addressing_mode())?,
-            if fc.pan_id_compression() != 0 {
-                destination.map(|d| d.pan_id)
-            } else {
-                None
-            },
-            data,
-        )?;
-        if fc.information_elements_present() != 0 {
-            return Err(UnpackError::Unimplemented(Some(
-                "Information elements not implemented",
+        let (frame_control, data) = FrameControl::unpack(data)?;
+        let version = FrameVersion::try_from_tag(frame_control.frame_version())?;
+        if version < FrameVersion::Ieee2015
+            && (frame_control.sequence_number_supression() != 0
+                || frame_control.information_elements_present() != 0)
+        {
+            return Err(UnpackError::Unsupported(Some(
+                "Reserved frame control bits set",
             )));
         }
-        if fc.security_enabled() != 0 {
-            return Err(UnpackError::Unimplemented(Some(
-                "Secured frames not yet supported",
+        if version == FrameVersion::Ieee2003 && frame_control.security_enabled() != 0 {
+            return Err(UnpackError::Unsupported(Some(
+                "Security of 802.15.4-2003 not supported",
             )));
         }
-        let (frame_type, data) = FrameType::unpack_data(fc.frame_type() as u8, data)?;
+        let (sequence_number, data) =
+            <Option<u8>>::unpack_data(frame_control.sequence_number_supression() == 0, data)?;
+        let ((destination, source), data) = frame_control.unpack_addresses(data)?;
+        let (security, data) = <Option<AuxiliarySecurityHeader>>::unpack_data(
+            frame_control.security_enabled() != 0,
+            data,
+        )?;
+        let ((header_ies, header_termination), data) =
+            if frame_control.information_elements_present() != 0 {
+                Header::unpack_header_ies(data)?
+            } else {
+                ((vec![], None), data)
+            };
         Ok((
-            Frame {
-                frame_pending,
-                acknowledge_request,
+            Header {
+                frame_control,
                 sequence_number,
                 destination,
                 source,
-                frame_type,
+                security,
+                header_ies,
+                header_termination,
             },
             data,
         ))
     }
 
     fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let target = self.frame_control.pack(target)?;
+        let target = self.sequence_number.pack_data(target)?;
+        let (destination_pan_present, source_pan_present) = self.frame_control.pan_ids_present();
+        let target = match self.destination {
+            Some(destination) if destination_pan_present => destination.pack_data(target)?,
+            Some(destination) => destination.address.pack_data(target)?,
+            None => target,
+        };
+        let target = match self.source {
+            Some(source) if source_pan_present => source.pack_data(target)?,
+            Some(source) => {
+                if destination_pan_present
+                    && self.destination.map(|d| d.pan_id) != Some(source.pan_id)
+                {
+                    return Err(PackError::NotAllowed(Some(
+                        "Source PAN ID can not be left out",
+                    )));
+                }
+                source.address.pack_data(target)?
+            }
+            None => target,
+        };
+        let mut target = self.security.pack_data(target)?;
+        for ie in &self.header_ies {
+            target = ie.pack(target)?;
+        }
+        let element_id = match self.header_termination {
+            Some(HeaderTermination::PayloadIEs) => HEADER_TERMINATION_1_IE,
+            Some(HeaderTermination::Payload) => HEADER_TERMINATION_2_IE,
+            None => return Ok(target),
+        };
+        let mut descriptor = HeaderIEDescriptor(0);
+        descriptor.set_element_id(element_id);
+        descriptor.pack(target)
+    }
+}
+
+impl Frame {
+    fn header(&self) -> Header {
         let mut fc = FrameControl(0);
         fc.set_frame_type(self.frame_type.get_tag() as u16);
-        fc.set_security_enabled(0);
+        fc.set_security_enabled(self.security.is_some().into());
         fc.set_frame_pending(self.frame_pending.into());
         fc.set_acknowledge_request(self.acknowledge_request.into());
         let destination_pan_id = self.destination.map(|a| a.pan_id);
         let source_pan_id = self.source.map(|a| a.pan_id);
-        let pan_id_compression =
-            destination_pan_id.is_some() && destination_pan_id == source_pan_id;
+        let both_extended = matches!(
+            (self.destination, self.source),
+            (
+                Some(FullAddress {
+                    address: Address::Extended(_),
+                    ..
+                }),
+                Some(FullAddress {
+                    address: Address::Extended(_),
+                    ..
+                })
+            )
+        );
+        // Version 2 frames between extended addresses never carry the source PAN ID.
+        let pan_id_compression = destination_pan_id.is_some()
+            && destination_pan_id == source_pan_id
+            && !(self.version == FrameVersion::Ieee2015 && both_extended);
         fc.set_pan_id_compression(pan_id_compression.into());
         fc.set_reserved(0);
         fc.set_sequence_number_supression(self.sequence_number.is_none().into());
-        fc.set_information_elements_present(0);
+        let information_elements_present =
+            !self.header_ies.is_empty() || !self.payload_ies.is_empty();
+        fc.set_information_elements_present(information_elements_present.into());
         fc.set_destination_addressing_mode(
             self.destination
                 .map(|a| a.get_tag())
@@ -255,25 +413,582 @@ impl Pack for Frame {
                 .unwrap_or(AddressingMode::None)
                 .into_tag(),
         );
-        fc.set_frame_version(0);
-        let target = fc.pack(target)?;
-        let target = self.sequence_number.pack_data(target)?;
-        let target = if let Some(destination) = self.destination {
-            destination.pack_data(target)?
+        fc.set_frame_version(self.version.into_tag());
+        // Payload IEs need the header to be terminated, even without header IEs.
+        let header_termination = if !self.payload_ies.is_empty() {
+            Some(HeaderTermination::PayloadIEs)
+        } else if !self.header_ies.is_empty() && self.frame_type.has_payload() {
+            Some(HeaderTermination::Payload)
         } else {
-            target
+            None
         };
-        let target = if let Some(source) = self.source {
-            if pan_id_compression {
-                source.address.pack_data(target)?
-            } else {
-                source.pack_data(target)?
+        Header {
+            frame_control: fc,
+            sequence_number: self.sequence_number,
+            destination: self.destination,
+            source: self.source,
+            security: self.security,
+            header_ies: self.header_ies.clone(),
+            header_termination,
+        }
+    }
+
+    /**
+     * Oldest frame version able to hold the frame. The auxiliary security header was introduced
+     * in 802.15.4-2006, IEs, sequence number suppression and enhanced beacons in 802.15.4-2015.
+     */
+    pub fn minimum_version(&self) -> FrameVersion {
+        if !self.header_ies.is_empty()
+            || !self.payload_ies.is_empty()
+            || self.sequence_number.is_none()
+            || matches!(self.frame_type, FrameType::EnhancedBeacon(_))
+        {
+            FrameVersion::Ieee2015
+        } else if self.security.is_some() {
+            FrameVersion::Ieee2006
+        } else {
+            FrameVersion::Ieee2003
+        }
+    }
+
+    fn unpack_payload_ies(mut data: &[u8]) -> Result<(Vec<PayloadIE>, &[u8]), UnpackError> {
+        let mut payload_ies = vec![];
+        while !data.is_empty() {
+            let (descriptor, rest) = PayloadIEDescriptor::unpack(data)?;
+            if descriptor.group_id() == PAYLOAD_TERMINATION_IE {
+                let (_, rest) = split_content(rest, descriptor.length() as usize)?;
+                return Ok((payload_ies, rest));
             }
+            let (ie, rest) = PayloadIE::unpack(data)?;
+            payload_ies.push(ie);
+            data = rest;
+        }
+        Ok((payload_ies, data))
+    }
+}
+
+impl Pack for Frame {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (header, data) = Header::unpack(data)?;
+        let fc = header.frame_control;
+        // For secured frames, the payload is expected to have been unsecured already.
+        let (payload_ies, data) = match header.header_termination {
+            Some(HeaderTermination::PayloadIEs) => Frame::unpack_payload_ies(data)?,
+            _ => (vec![], data),
+        };
+        let version = FrameVersion::try_from_tag(fc.frame_version())?;
+        let (frame_type, data) = if version == FrameVersion::Ieee2015 && fc.frame_type() == 0 {
+            let (payload, data) = Payload::unpack(data)?;
+            (FrameType::EnhancedBeacon(payload), data)
         } else {
-            target
+            FrameType::unpack_data(fc.frame_type() as u8, data)?
         };
-        let target = self.frame_type.pack_data(target)?;
-        Ok(target)
+        Ok((
+            Frame {
+                version,
+                frame_pending: fc.frame_pending() != 0,
+                acknowledge_request: fc.acknowledge_request() != 0,
+                sequence_number: header.sequence_number,
+                destination: header.destination,
+                source: header.source,
+                security: header.security,
+                header_ies: header.header_ies,
+                payload_ies,
+                frame_type,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.version < self.minimum_version() {
+            return Err(PackError::NotAllowed(Some("Frame version too old")));
+        }
+        if self.version == FrameVersion::Ieee2015 && matches!(self.frame_type, FrameType::Beacon(_))
+        {
+            return Err(PackError::NotAllowed(Some(
+                "Beacons of version 2 should be enhanced beacons",
+            )));
+        }
+        let mut target = self.header().pack(target)?;
+        for ie in &self.payload_ies {
+            target = ie.pack(target)?;
+        }
+        if !self.payload_ies.is_empty() && self.frame_type.has_payload() {
+            let mut descriptor = PayloadIEDescriptor(0);
+            descriptor.set_group_id(PAYLOAD_TERMINATION_IE);
+            descriptor.set_ie_type(1);
+            target = descriptor.pack(target)?;
+        }
+        self.frame_type.pack_data(target)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 9.4.2.2 - Table 9-6
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ExtEnum)]
+#[tag_type(u8)]
+pub enum SecurityLevel {
+    None = 0,
+    Mic32 = 1,
+    Mic64 = 2,
+    Mic128 = 3,
+    Enc = 4,
+    EncMic32 = 5,
+    EncMic64 = 6,
+    EncMic128 = 7,
+}
+
+impl SecurityLevel {
+    /**
+     * Length of the message integrity code appended to the payload.
+     */
+    pub fn mic_length(&self) -> usize {
+        match self {
+            SecurityLevel::None | SecurityLevel::Enc => 0,
+            SecurityLevel::Mic32 | SecurityLevel::EncMic32 => 4,
+            SecurityLevel::Mic64 | SecurityLevel::EncMic64 => 8,
+            SecurityLevel::Mic128 | SecurityLevel::EncMic128 => 16,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.into_tag() >= SecurityLevel::Enc.into_tag()
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 9.4.4 - Table 9-7
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PackTagged)]
+#[tag_type(u8)]
+pub enum KeyIdentifier {
+    #[tag(0)]
+    Implicit(), // Key is determined from the originator and recipient
+    #[tag(1)]
+    Index(u8), // Key source is macDefaultKeySource
+    #[tag(2)]
+    Source4 { source: u32, index: u8 },
+    #[tag(3)]
+    Source8 { source: u64, index: u8 },
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct SecurityControl(u8);
+    impl Debug;
+    pub security_level, set_security_level: 2, 0;
+    pub key_identifier_mode, set_key_identifier_mode: 4, 3;
+    pub frame_counter_suppression, set_frame_counter_suppression: 5, 5;
+    pub asn_in_nonce, set_asn_in_nonce: 6, 6;
+    pub reserved, set_reserved: 7, 7;
+}
+
+// IEEE Std 802.15.4 - 2015: 9.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuxiliarySecurityHeader {
+    pub security_level: SecurityLevel,
+    pub key_identifier: KeyIdentifier,
+    pub frame_counter: u32,
+}
+
+impl Pack for AuxiliarySecurityHeader {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (sc, data) = SecurityControl::unpack(data)?;
+        if sc.frame_counter_suppression() != 0 || sc.asn_in_nonce() != 0 {
+            return Err(UnpackError::Unimplemented(Some(
+                "Frame counter suppression not implemented",
+            )));
+        }
+        let security_level = SecurityLevel::try_from_tag(sc.security_level())?;
+        let (frame_counter, data) = u32::unpack(data)?;
+        let (key_identifier, data) = KeyIdentifier::unpack_data(sc.key_identifier_mode(), data)?;
+        Ok((
+            AuxiliarySecurityHeader {
+                security_level,
+                key_identifier,
+                frame_counter,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let mut sc = SecurityControl(0);
+        sc.set_security_level(self.security_level.into_tag());
+        sc.set_key_identifier_mode(self.key_identifier.get_tag());
+        let target = (sc, self.frame_counter).pack(target)?;
+        self.key_identifier.pack_data(target)
+    }
+}
+
+fn split_content(data: &[u8], length: usize) -> Result<(&[u8], &[u8]), UnpackError> {
+    if data.len() < length {
+        Err(UnpackError::NotEnoughData)
+    } else {
+        Ok(data.split_at(length))
+    }
+}
+
+fn pack_content<T: PackTarget>(
+    target: T,
+    vendor_oui: Option<&[u8; 3]>,
+    content: &[u8],
+) -> Result<T, PackError<T::Error>> {
+    let target = match vendor_oui {
+        Some(vendor_oui) => target.append(vendor_oui),
+        None => Ok(target),
+    };
+    target
+        .and_then(|target| target.append(content))
+        .map_err(PackError::TargetError)
+}
+
+fn unpack_vendor_oui(content: &[u8]) -> Result<([u8; 3], Vec<u8>), UnpackError> {
+    let (vendor_oui, content) = split_content(content, 3)?;
+    Ok((
+        [vendor_oui[0], vendor_oui[1], vendor_oui[2]],
+        content.to_vec(),
+    ))
+}
+
+// IEEE Std 802.15.4 - 2015: 7.4.2.1 - Table 7-7
+const VENDOR_SPECIFIC_HEADER_IE: u16 = 0x00;
+const HEADER_TERMINATION_1_IE: u16 = 0x7E;
+const HEADER_TERMINATION_2_IE: u16 = 0x7F;
+
+// IEEE Std 802.15.4 - 2015: 7.4.3.1 - Table 7-15
+const MLME_PAYLOAD_IE: u16 = 0x1;
+const VENDOR_SPECIFIC_PAYLOAD_IE: u16 = 0x2;
+const PAYLOAD_TERMINATION_IE: u16 = 0xF;
+
+bitfield! {
+    #[derive(Pack)]
+    struct HeaderIEDescriptor(u16);
+    impl Debug;
+    pub length, set_length: 6, 0;
+    pub element_id, set_element_id: 14, 7;
+    pub ie_type, set_ie_type: 15, 15;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct PayloadIEDescriptor(u16);
+    impl Debug;
+    pub length, set_length: 10, 0;
+    pub group_id, set_group_id: 14, 11;
+    pub ie_type, set_ie_type: 15, 15;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct ShortNestedIEDescriptor(u16);
+    impl Debug;
+    pub length, set_length: 7, 0;
+    pub sub_id, set_sub_id: 14, 8;
+    pub ie_type, set_ie_type: 15, 15;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct LongNestedIEDescriptor(u16);
+    impl Debug;
+    pub length, set_length: 10, 0;
+    pub sub_id, set_sub_id: 14, 11;
+    pub ie_type, set_ie_type: 15, 15;
+}
+
+/**
+ * The termination IE that ended the header IEs, as described in 7.4.1 of 802.15.4-2015. When
+ * packing a frame, these are added as needed, they never show up in the lists of IEs.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderTermination {
+    PayloadIEs, // Header Termination 1, payload IEs follow
+    Payload,    // Header Termination 2, the MAC payload follows
+}
+
+// IEEE Std 802.15.4 - 2015: 7.4.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderIE {
+    VendorSpecific {
+        vendor_oui: [u8; 3],
+        content: Vec<u8>,
+    },
+    Other {
+        element_id: u8,
+        content: Vec<u8>,
+    }, // Not interpreted by the MAC, passed on as is
+}
+
+impl Pack for HeaderIE {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (descriptor, data) = HeaderIEDescriptor::unpack(data)?;
+        if descriptor.ie_type() != 0 {
+            return Err(UnpackError::Unsupported(Some("Not a header IE")));
+        }
+        let (content, data) = split_content(data, descriptor.length() as usize)?;
+        let ie = match descriptor.element_id() {
+            VENDOR_SPECIFIC_HEADER_IE => {
+                let (vendor_oui, content) = unpack_vendor_oui(content)?;
+                HeaderIE::VendorSpecific {
+                    vendor_oui,
+                    content,
+                }
+            }
+            element_id => HeaderIE::Other {
+                element_id: element_id as u8,
+                content: content.to_vec(),
+            },
+        };
+        Ok((ie, data))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let (element_id, vendor_oui, content) = match self {
+            HeaderIE::VendorSpecific {
+                vendor_oui,
+                content,
+            } => (VENDOR_SPECIFIC_HEADER_IE, Some(vendor_oui), content),
+            HeaderIE::Other {
+                element_id,
+                content,
+            } => (*element_id as u16, None, content),
+        };
+        if element_id == HEADER_TERMINATION_1_IE || element_id == HEADER_TERMINATION_2_IE {
+            // Terminations are added while packing the frame, see HeaderTermination.
+            return Err(PackError::NotAllowed(Some(
+                "Header IE element ID reserved for termination",
+            )));
+        }
+        let length = vendor_oui.map_or(0, |oui| oui.len()) + content.len();
+        if length > 0x7F {
+            return Err(PackError::NotAllowed(Some("Header IE content too long")));
+        }
+        let mut descriptor = HeaderIEDescriptor(0);
+        descriptor.set_length(length as u16);
+        descriptor.set_element_id(element_id);
+        let target = descriptor.pack(target)?;
+        pack_content(target, vendor_oui, content)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.4.3
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadIE {
+    Mlme(Vec<NestedIE>),
+    VendorSpecific {
+        vendor_oui: [u8; 3],
+        content: Vec<u8>,
+    },
+    Other {
+        group_id: u8,
+        content: Vec<u8>,
+    }, // Not interpreted by the MAC, passed on as is
+}
+
+impl Pack for PayloadIE {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (descriptor, data) = PayloadIEDescriptor::unpack(data)?;
+        if descriptor.ie_type() != 1 {
+            return Err(UnpackError::Unsupported(Some("Not a payload IE")));
+        }
+        let (content, data) = split_content(data, descriptor.length() as usize)?;
+        let ie = match descriptor.group_id() {
+            MLME_PAYLOAD_IE => {
+                let mut nested = vec![];
+                let mut content = content;
+                while !content.is_empty() {
+                    let (ie, rest) = NestedIE::unpack(content)?;
+                    nested.push(ie);
+                    content = rest;
+                }
+                PayloadIE::Mlme(nested)
+            }
+            VENDOR_SPECIFIC_PAYLOAD_IE => {
+                let (vendor_oui, content) = unpack_vendor_oui(content)?;
+                PayloadIE::VendorSpecific {
+                    vendor_oui,
+                    content,
+                }
+            }
+            group_id => PayloadIE::Other {
+                group_id: group_id as u8,
+                content: content.to_vec(),
+            },
+        };
+        Ok((ie, data))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let nested_content: Vec<u8>;
+        let (group_id, vendor_oui, content) = match self {
+            PayloadIE::Mlme(nested) => {
+                let mut nested_target = VecPackTarget::new();
+                for ie in nested {
+                    nested_target = ie.pack(nested_target).map_err(|e| match e {
+                        PackError::NotAllowed(reason) => PackError::NotAllowed(reason),
+                        PackError::TargetError(e) => match e {},
+                    })?;
+                }
+                nested_content = nested_target.into();
+                (MLME_PAYLOAD_IE, None, &nested_content)
+            }
+            PayloadIE::VendorSpecific {
+                vendor_oui,
+                content,
+            } => (VENDOR_SPECIFIC_PAYLOAD_IE, Some(vendor_oui), content),
+            PayloadIE::Other { group_id, content } => (*group_id as u16, None, content),
+        };
+        let length = vendor_oui.map_or(0, |oui| oui.len()) + content.len();
+        if group_id > 0xF {
+            return Err(PackError::NotAllowed(Some(
+                "Payload IE group ID out of range",
+            )));
+        }
+        if length > 0x7FF {
+            return Err(PackError::NotAllowed(Some("Payload IE content too long")));
+        }
+        let mut descriptor = PayloadIEDescriptor(0);
+        descriptor.set_length(length as u16);
+        descriptor.set_group_id(group_id);
+        descriptor.set_ie_type(1);
+        let target = descriptor.pack(target)?;
+        pack_content(target, vendor_oui, content)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.4.4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NestedIE {
+    Short { sub_id: u8, content: Vec<u8> }, // Sub-ID of 7 bits, up to 255 bytes of content
+    Long { sub_id: u8, content: Vec<u8> },  // Sub-ID of 4 bits, up to 2047 bytes of content
+}
+
+impl Pack for NestedIE {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (descriptor, _) = u16::unpack(data)?;
+        if descriptor & 0x8000 == 0 {
+            let (descriptor, data) = ShortNestedIEDescriptor::unpack(data)?;
+            let (content, data) = split_content(data, descriptor.length() as usize)?;
+            Ok((
+                NestedIE::Short {
+                    sub_id: descriptor.sub_id() as u8,
+                    content: content.to_vec(),
+                },
+                data,
+            ))
+        } else {
+            let (descriptor, data) = LongNestedIEDescriptor::unpack(data)?;
+            let (content, data) = split_content(data, descriptor.length() as usize)?;
+            Ok((
+                NestedIE::Long {
+                    sub_id: descriptor.sub_id() as u8,
+                    content: content.to_vec(),
+                },
+                data,
+            ))
+        }
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let (target, content) = match self {
+            NestedIE::Short { sub_id, content } => {
+                if *sub_id > 0x7F {
+                    return Err(PackError::NotAllowed(Some("Nested IE sub-ID out of range")));
+                }
+                if content.len() > 0xFF {
+                    return Err(PackError::NotAllowed(Some("Nested IE content too long")));
+                }
+                let mut descriptor = ShortNestedIEDescriptor(0);
+                descriptor.set_length(content.len() as u16);
+                descriptor.set_sub_id(*sub_id as u16);
+                (descriptor.pack(target)?, content)
+            }
+            NestedIE::Long { sub_id, content } => {
+                if *sub_id > 0xF {
+                    return Err(PackError::NotAllowed(Some("Nested IE sub-ID out of range")));
+                }
+                if content.len() > 0x7FF {
+                    return Err(PackError::NotAllowed(Some("Nested IE content too long")));
+                }
+                let mut descriptor = LongNestedIEDescriptor(0);
+                descriptor.set_length(content.len() as u16);
+                descriptor.set_sub_id(*sub_id as u16);
+                descriptor.set_ie_type(1);
+                (descriptor.pack(target)?, content)
+            }
+        };
+        pack_content(target, None, content)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.4.4
+pub const EB_FILTER_IE: u8 = 0x1E;
+
+/**
+ * Content of the EB Filter IE, a short MLME nested IE. Carried in an Enhanced Beacon Request,
+ * it limits which coordinators respond, and what they include in their Enhanced Beacon.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnhancedBeaconFilter {
+    pub permit_joining_on: bool, // Only coordinators permitting association respond
+    pub link_quality: Option<u8>, // Minimum link quality of the request to respond to
+    pub percent_filter: Option<u8>, // Chance of responding, in percent
+    pub pib_identifiers: Vec<u8>, // PIB attributes to report, at most 3
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct EnhancedBeaconFilterDescriptor(u8);
+    impl Debug;
+    pub permit_joining_on, set_permit_joining_on: 0, 0;
+    pub include_link_quality, set_include_link_quality: 1, 1;
+    pub include_percent_filter, set_include_percent_filter: 2, 2;
+    pub pib_identifier_count, set_pib_identifier_count: 4, 3;
+    pub reserved, set_reserved: 7, 5;
+}
+
+impl Pack for EnhancedBeaconFilter {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (descriptor, data) = EnhancedBeaconFilterDescriptor::unpack(data)?;
+        let (link_quality, data) = if descriptor.include_link_quality() != 0 {
+            let (link_quality, data) = u8::unpack(data)?;
+            (Some(link_quality), data)
+        } else {
+            (None, data)
+        };
+        let (percent_filter, data) = if descriptor.include_percent_filter() != 0 {
+            let (percent_filter, data) = u8::unpack(data)?;
+            (Some(percent_filter), data)
+        } else {
+            (None, data)
+        };
+        let (pib_identifiers, data) =
+            unpack_list(descriptor.pib_identifier_count() as usize, data)?;
+        Ok((
+            EnhancedBeaconFilter {
+                permit_joining_on: descriptor.permit_joining_on() != 0,
+                link_quality,
+                percent_filter,
+                pib_identifiers,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.pib_identifiers.len() > 3 {
+            return Err(PackError::NotAllowed(Some("Too many PIB identifiers")));
+        }
+        let mut descriptor = EnhancedBeaconFilterDescriptor(0);
+        descriptor.set_permit_joining_on(self.permit_joining_on as u8);
+        descriptor.set_include_link_quality(self.link_quality.is_some() as u8);
+        descriptor.set_include_percent_filter(self.percent_filter.is_some() as u8);
+        descriptor.set_pib_identifier_count(self.pib_identifiers.len() as u8);
+        descriptor.set_reserved(0);
+        let mut target = descriptor.pack(target)?;
+        for value in [self.link_quality, self.percent_filter].iter().flatten() {
+            target = value.pack(target)?;
+        }
+        pack_content(target, None, &self.pib_identifiers)
     }
 }
 
@@ -298,9 +1013,28 @@ pub struct Beacon {
     pub battery_life_extension: bool,
     pub pan_coordinator: bool,
     pub association_permit: bool,
+    pub gts_permit: bool,
+    pub gts_descriptors: Vec<GtsDescriptor>, // At most 7
+    // Devices with data pending at the coordinator, at most 7 addresses in total
+    pub pending_short_addresses: Vec<ShortAddress>,
+    pub pending_extended_addresses: Vec<ExtendedAddress>,
     pub payload: Payload,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GtsDirection {
+    Transmit, // From the device to the coordinator
+    Receive,  // From the coordinator to the device
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GtsDescriptor {
+    pub short_address: ShortAddress,
+    pub starting_slot: usize,
+    pub length: usize,
+    pub direction: GtsDirection,
+}
+
 bitfield! {
     #[derive(Pack)]
     struct SuperframeSpecification(u16);
@@ -314,15 +1048,122 @@ bitfield! {
     pub association_permit, set_association_permit: 15, 15;
 }
 
+bitfield! {
+    #[derive(Pack)]
+    struct GtsSpecification(u8);
+    impl Debug;
+    pub descriptor_count, set_descriptor_count: 2, 0;
+    pub reserved, set_reserved: 6, 3;
+    pub gts_permit, set_gts_permit: 7, 7;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct GtsDirections(u8);
+    impl Debug;
+    pub mask, set_mask: 6, 0; // Bit i set if the i-th GTS is a receive GTS
+    pub reserved, set_reserved: 7, 7;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct GtsSlots(u8);
+    impl Debug;
+    pub starting_slot, set_starting_slot: 3, 0;
+    pub length, set_length: 7, 4;
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct PendingAddressSpecification(u8);
+    impl Debug;
+    pub short_count, set_short_count: 2, 0;
+    pub reserved1, set_reserved1: 3, 3;
+    pub extended_count, set_extended_count: 6, 4;
+    pub reserved2, set_reserved2: 7, 7;
+}
+
+/**
+ * Unpacks count items of the same type, as used for the lists in the beacon.
+ */
+fn unpack_list<T: Pack>(count: usize, mut data: &[u8]) -> Result<(Vec<T>, &[u8]), UnpackError> {
+    let mut list = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (item, rest) = T::unpack(data)?;
+        list.push(item);
+        data = rest;
+    }
+    Ok((list, data))
+}
+
+impl Beacon {
+    fn unpack_gts(data: &[u8]) -> Result<(bool, Vec<GtsDescriptor>, &[u8]), UnpackError> {
+        let (gts_spec, data) = GtsSpecification::unpack(data)?;
+        let count = gts_spec.descriptor_count() as usize;
+        if count == 0 {
+            // The directions and list are left out entirely.
+            return Ok((gts_spec.gts_permit() != 0, vec![], data));
+        }
+        let (directions, data) = GtsDirections::unpack(data)?;
+        let (list, data) = unpack_list::<(ShortAddress, GtsSlots)>(count, data)?;
+        let descriptors = list
+            .into_iter()
+            .enumerate()
+            .map(|(index, (short_address, slots))| GtsDescriptor {
+                short_address,
+                starting_slot: slots.starting_slot() as usize,
+                length: slots.length() as usize,
+                direction: if directions.mask() & (1 << index) != 0 {
+                    GtsDirection::Receive
+                } else {
+                    GtsDirection::Transmit
+                },
+            })
+            .collect();
+        Ok((gts_spec.gts_permit() != 0, descriptors, data))
+    }
+
+    fn pack_gts<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.gts_descriptors.len() > 7 {
+            return Err(PackError::NotAllowed(Some("Too many GTS descriptors")));
+        }
+        let mut gts_spec = GtsSpecification(0);
+        gts_spec.set_descriptor_count(self.gts_descriptors.len() as u8);
+        gts_spec.set_reserved(0);
+        gts_spec.set_gts_permit(self.gts_permit as u8);
+        let mut target = gts_spec.pack(target)?;
+        if self.gts_descriptors.is_empty() {
+            return Ok(target);
+        }
+        let mut directions = GtsDirections(0);
+        for (index, descriptor) in self.gts_descriptors.iter().enumerate() {
+            if descriptor.direction == GtsDirection::Receive {
+                directions.set_mask(directions.mask() | (1 << index));
+            }
+        }
+        target = directions.pack(target)?;
+        for descriptor in &self.gts_descriptors {
+            if descriptor.starting_slot > 0xF || descriptor.length > 0xF {
+                return Err(PackError::NotAllowed(Some("GTS slots out of range")));
+            }
+            let mut slots = GtsSlots(0);
+            slots.set_starting_slot(descriptor.starting_slot as u8);
+            slots.set_length(descriptor.length as u8);
+            target = (descriptor.short_address, slots).pack(target)?;
+        }
+        Ok(target)
+    }
+}
+
 impl Pack for Beacon {
     fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
-        let ((ss, gts, pending_addresses), data) =
-            <(SuperframeSpecification, u8, u8)>::unpack(data)?;
-        if gts != 0 || pending_addresses != 0 {
-            return Err(UnpackError::Unimplemented(Some(
-                "Non-zero GTS or pending-addresses not yet supported",
-            )));
-        }
+        let (ss, data) = SuperframeSpecification::unpack(data)?;
+        let (gts_permit, gts_descriptors, data) = Beacon::unpack_gts(data)?;
+        let (pending_spec, data) = PendingAddressSpecification::unpack(data)?;
+        let (pending_short_addresses, data) =
+            unpack_list(pending_spec.short_count() as usize, data)?;
+        let (pending_extended_addresses, data) =
+            unpack_list(pending_spec.extended_count() as usize, data)?;
         let (payload, data) = Payload::unpack(data)?;
         Ok((
             Beacon {
@@ -332,6 +1173,10 @@ impl Pack for Beacon {
                 battery_life_extension: ss.battery_life_extension() != 0,
                 pan_coordinator: ss.pan_coordinator() != 0,
                 association_permit: ss.association_permit() != 0,
+                gts_permit,
+                gts_descriptors,
+                pending_short_addresses,
+                pending_extended_addresses,
                 payload,
             },
             data,
@@ -361,33 +1206,117 @@ impl Pack for Beacon {
         ss.set_reserved(0);
         ss.set_pan_coordinator(self.pan_coordinator as u16);
         ss.set_association_permit(self.association_permit as u16);
-        let target = (ss, 0_u8, 0_u8).pack(target)?;
+        let target = self.pack_gts(ss.pack(target)?)?;
+        let short_count = self.pending_short_addresses.len();
+        let extended_count = self.pending_extended_addresses.len();
+        if short_count + extended_count > 7 {
+            return Err(PackError::NotAllowed(Some("Too many pending addresses")));
+        }
+        let mut pending_spec = PendingAddressSpecification(0);
+        pending_spec.set_short_count(short_count as u8);
+        pending_spec.set_reserved1(0);
+        pending_spec.set_extended_count(extended_count as u8);
+        pending_spec.set_reserved2(0);
+        let mut target = pending_spec.pack(target)?;
+        for address in &self.pending_short_addresses {
+            target = address.pack(target)?;
+        }
+        for address in &self.pending_extended_addresses {
+            target = address.pack(target)?;
+        }
         self.payload.pack(target)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, PackTagged)]
-#[tag_type(u8)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FrameType {
-    #[tag(0)]
     Beacon(Beacon),
-    #[tag(1)]
+    EnhancedBeacon(Payload), // Beacon frame of version 2, only IEs and the beacon payload
     Data(Payload),
-    #[tag(2)]
     Ack(Payload),
-    #[tag(3)]
     Command(Command),
-    #[tag(4)]
     Reserved(Payload),
-    #[tag(5)]
     Multipurpose(Payload),
-    #[tag(6)]
     Fragment(Payload),
-    #[tag(7)]
     Extended(Payload),
 }
 
-// IEEE Std 802.15.4 - 2015: 7.5.1
+/**
+ * Tagged with the frame type of the frame control field. Both kinds of beacon share a frame type,
+ * unpacking always results in a regular beacon, Frame tells them apart by the frame version.
+ */
+impl PackTagged for FrameType {
+    type Tag = u8;
+    fn get_tag(&self) -> Self::Tag {
+        match self {
+            FrameType::Beacon(_) | FrameType::EnhancedBeacon(_) => 0,
+            FrameType::Data(_) => 1,
+            FrameType::Ack(_) => 2,
+            FrameType::Command(_) => 3,
+            FrameType::Reserved(_) => 4,
+            FrameType::Multipurpose(_) => 5,
+            FrameType::Fragment(_) => 6,
+            FrameType::Extended(_) => 7,
+        }
+    }
+    fn unpack_data(tag: Self::Tag, data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        if tag == 0 {
+            let (beacon, data) = Beacon::unpack(data)?;
+            return Ok((FrameType::Beacon(beacon), data));
+        }
+        if tag == 3 {
+            let (command, data) = Command::unpack(data)?;
+            return Ok((FrameType::Command(command), data));
+        }
+        let variant = match tag {
+            1 => FrameType::Data,
+            2 => FrameType::Ack,
+            4 => FrameType::Reserved,
+            5 => FrameType::Multipurpose,
+            6 => FrameType::Fragment,
+            7 => FrameType::Extended,
+            _ => return Err(UnpackError::InvalidEnumTag),
+        };
+        let (payload, data) = Payload::unpack(data)?;
+        Ok((variant(payload), data))
+    }
+    fn pack_data<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        match self {
+            FrameType::Beacon(beacon) => beacon.pack(target),
+            FrameType::Command(command) => command.pack(target),
+            FrameType::EnhancedBeacon(payload)
+            | FrameType::Data(payload)
+            | FrameType::Ack(payload)
+            | FrameType::Reserved(payload)
+            | FrameType::Multipurpose(payload)
+            | FrameType::Fragment(payload)
+            | FrameType::Extended(payload) => payload.pack(target),
+        }
+    }
+}
+
+impl FrameType {
+    /**
+     * Whether anything follows the IEs of the frame, which decides on the termination IEs.
+     */
+    fn has_payload(&self) -> bool {
+        match self {
+            FrameType::Beacon(_) | FrameType::Command(_) => true,
+            FrameType::EnhancedBeacon(payload)
+            | FrameType::Data(payload)
+            | FrameType::Ack(payload)
+            | FrameType::Reserved(payload)
+            | FrameType::Multipurpose(payload)
+            | FrameType::Fragment(payload)
+            | FrameType::Extended(payload) => !payload.0.is_empty(),
+        }
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.1 - Table 7-49
+// Not implemented, frames carrying them fail to unpack: TRLE management, DSME information,
+// DSME beacon allocation and collision notification, DSME link report, DBS, RIT data response
+// and the SRM commands.
 #[derive(Debug, Clone, PartialEq, Eq, PackTagged, Pack)]
 #[tag_type(u8)]
 pub enum Command {
@@ -395,10 +1324,276 @@ pub enum Command {
     AssociationRequest(CapabilityInformation),
     #[tag(0x02)]
     AssociationResponse(AssociationResponse),
+    #[tag(0x03)]
+    DisassociationNotification(DisassociationReason),
     #[tag(0x04)]
     DataRequest(),
+    #[tag(0x05)]
+    PanIdConflictNotification(),
+    #[tag(0x06)]
+    OrphanNotification(),
     #[tag(0x07)]
-    BeaconRequest(),
+    BeaconRequest(), // An Enhanced Beacon Request if of frame version 2, see EnhancedBeaconFilter
+    #[tag(0x08)]
+    CoordinatorRealignment(CoordinatorRealignment),
+    #[tag(0x09)]
+    GtsRequest(GtsCharacteristics),
+    #[tag(0x13)]
+    DsmeAssociationRequest(DsmeAssociationRequest),
+    #[tag(0x14)]
+    DsmeAssociationResponse(DsmeAssociationResponse),
+    #[tag(0x15)]
+    DsmeGtsRequest(DsmeGtsRequest),
+    #[tag(0x16)]
+    DsmeGtsReply(DsmeGtsReply),
+    #[tag(0x17)]
+    DsmeGtsNotify(DsmeGtsReply), // Same fields as the reply, sent to the neighbours
+    #[tag(0x20)]
+    RitDataRequest(RitDataRequest),
+    #[tag(0x24)]
+    VendorSpecific(VendorSpecificCommand),
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.11
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GtsCharacteristics {
+    pub length: usize, // In superframe slots
+    pub direction: GtsDirection,
+    pub allocation: bool, // Deallocation if false
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct GtsCharacteristicsField(u8);
+    impl Debug;
+    pub length, set_length: 3, 0;
+    pub direction, set_direction: 4, 4;
+    pub characteristics_type, set_characteristics_type: 5, 5;
+    pub reserved, set_reserved: 7, 6;
+}
+
+impl Pack for GtsCharacteristics {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (field, data) = GtsCharacteristicsField::unpack(data)?;
+        Ok((
+            GtsCharacteristics {
+                length: field.length() as usize,
+                direction: if field.direction() != 0 {
+                    GtsDirection::Receive
+                } else {
+                    GtsDirection::Transmit
+                },
+                allocation: field.characteristics_type() != 0,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.length > 0xF {
+            return Err(PackError::NotAllowed(Some("GTS length out of range")));
+        }
+        let mut field = GtsCharacteristicsField(0);
+        field.set_length(self.length as u8);
+        field.set_direction((self.direction == GtsDirection::Receive) as u8);
+        field.set_characteristics_type(self.allocation as u8);
+        field.set_reserved(0);
+        field.pack(target)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.14
+#[derive(Debug, Clone, PartialEq, Eq, Pack)]
+pub struct DsmeAssociationRequest {
+    pub capability_information: CapabilityInformation,
+    pub hopping_sequence_id: u8,
+    pub channel_offset: u16,
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.15
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsmeAssociationResponse {
+    pub status: Result<ShortAddress, AssociationError>,
+    pub hopping_sequence: Vec<u8>, // Channel numbers, empty without channel hopping
+}
+
+impl Pack for DsmeAssociationResponse {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (short_address, data) = ShortAddress::unpack(data)?;
+        let (status, data) = match u8::unpack(data)? {
+            (0, data) => (Ok(short_address), data),
+            (x, data) => {
+                let (error, data) = AssociationError::unpack_data(x, data)?;
+                (Err(error), data)
+            }
+        };
+        let (length, data) = u8::unpack(data)?;
+        let (hopping_sequence, data) = split_content(data, length as usize)?;
+        Ok((
+            DsmeAssociationResponse {
+                status,
+                hopping_sequence: hopping_sequence.to_vec(),
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.hopping_sequence.len() > 0xFF {
+            return Err(PackError::NotAllowed(Some("Hopping sequence too long")));
+        }
+        let (address, status) = match &self.status {
+            Ok(address) => (*address, 0),
+            Err(x) => (ShortAddress::invalid(), x.get_tag()),
+        };
+        let target = address.pack(target)?;
+        let target = status.pack(target)?;
+        let target = (self.hopping_sequence.len() as u8).pack(target)?;
+        pack_content(target, None, &self.hopping_sequence)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.16 - Table 7-55
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ExtEnum, PackTagged)]
+#[tag_type(u8)]
+pub enum DsmeGtsManagementType {
+    Deallocation = 0,
+    Allocation = 1,
+    DuplicatedAllocationNotification = 2,
+    Reduce = 3,
+    Restart = 4,
+    Expiration = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DsmeGtsManagement {
+    pub management_type: DsmeGtsManagementType,
+    pub direction: GtsDirection,
+    pub prioritized_channel_access: bool,
+}
+
+bitfield! {
+    #[derive(Pack)]
+    struct DsmeGtsManagementField(u8);
+    impl Debug;
+    pub management_type, set_management_type: 2, 0;
+    pub direction, set_direction: 3, 3;
+    pub prioritized_channel_access, set_prioritized_channel_access: 4, 4;
+    pub reserved, set_reserved: 7, 5;
+}
+
+impl Pack for DsmeGtsManagement {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (field, data) = DsmeGtsManagementField::unpack(data)?;
+        let (management_type, data) =
+            DsmeGtsManagementType::unpack_data(field.management_type(), data)?;
+        Ok((
+            DsmeGtsManagement {
+                management_type,
+                direction: if field.direction() != 0 {
+                    GtsDirection::Receive
+                } else {
+                    GtsDirection::Transmit
+                },
+                prioritized_channel_access: field.prioritized_channel_access() != 0,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let mut field = DsmeGtsManagementField(0);
+        field.set_management_type(self.management_type.get_tag());
+        field.set_direction((self.direction == GtsDirection::Receive) as u8);
+        field.set_prioritized_channel_access(self.prioritized_channel_access as u8);
+        field.set_reserved(0);
+        field.pack(target)
+    }
+}
+
+/**
+ * The slots a DSME GTS command is about: a bitmap of the slots in one sub-block of the slot
+ * allocation bitmap, as described in 7.5.16 of 802.15.4-2015.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsmeSabSpecification {
+    pub sub_block_index: u16,
+    pub sub_block: Vec<u8>,
+}
+
+impl Pack for DsmeSabSpecification {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (length, data) = u8::unpack(data)?;
+        let (sub_block_index, data) = u16::unpack(data)?;
+        let (sub_block, data) = split_content(data, length as usize)?;
+        Ok((
+            DsmeSabSpecification {
+                sub_block_index,
+                sub_block: sub_block.to_vec(),
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        if self.sub_block.len() > 0xFF {
+            return Err(PackError::NotAllowed(Some("SAB sub-block too long")));
+        }
+        let target = (self.sub_block.len() as u8).pack(target)?;
+        let target = self.sub_block_index.pack(target)?;
+        pack_content(target, None, &self.sub_block)
+    }
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.16
+#[derive(Debug, Clone, PartialEq, Eq, Pack)]
+pub struct DsmeGtsRequest {
+    pub management: DsmeGtsManagement,
+    pub number_of_slots: u8,
+    pub preferred_superframe_id: u16,
+    pub preferred_slot_id: u8,
+    pub sab: DsmeSabSpecification,
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.17
+#[derive(Debug, Clone, PartialEq, Eq, Pack)]
+pub struct DsmeGtsReply {
+    pub management: DsmeGtsManagement,
+    pub destination_address: ShortAddress,
+    pub channel_offset: u16,
+    pub sab: DsmeSabSpecification,
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.24
+#[derive(Debug, Clone, PartialEq, Eq, Pack)]
+pub struct RitDataRequest {
+    pub time_to_first_listen: u8,
+    pub number_of_repeat_listen: u8,
+    pub repeat_listen_interval: u16,
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.27
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorSpecificCommand {
+    pub vendor_oui: [u8; 3],
+    pub content: Vec<u8>,
+}
+
+impl Pack for VendorSpecificCommand {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let (vendor_oui, content) = unpack_vendor_oui(data)?;
+        Ok((
+            VendorSpecificCommand {
+                vendor_oui,
+                content,
+            },
+            &data[data.len()..],
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        pack_content(target, Some(&self.vendor_oui), &self.content)
+    }
 }
 
 // IEEE Std 802.15.4 - 2015: 7.5.2
@@ -454,7 +1649,13 @@ impl Pack for CapabilityInformation {
     }
 
     fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
-        Ok(target)
+        let capability_information = (self.device_type.get_tag() << 1)
+            | (self.power_source.get_tag() << 2)
+            | ((self.receiver_on_when_idle as u8) << 3)
+            | ((self.fast_association as u8) << 4)
+            | ((self.security_capable as u8) << 6)
+            | ((self.allocate_address as u8) << 7);
+        capability_information.pack(target)
     }
 }
 
@@ -503,6 +1704,61 @@ impl Pack for AssociationResponse {
     }
 }
 
+// IEEE Std 802.15.4 - 2015: 7.5.4 - Table 7-51
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ExtEnum, Pack, PackTagged)]
+#[tag_type(u8)]
+pub enum DisassociationReason {
+    CoordinatorWishesDeviceToLeave = 1,
+    DeviceWishesToLeave = 2,
+}
+
+// IEEE Std 802.15.4 - 2015: 7.5.10
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordinatorRealignment {
+    pub pan_id: PANID,
+    pub coordinator_short_address: ShortAddress,
+    pub channel_number: u8,
+    pub short_address: ShortAddress, // Assigned to the orphaned device, 0xFFFF if broadcast
+    pub channel_page: Option<u8>,    // Only present in frames of version 1 and up
+}
+
+impl Pack for CoordinatorRealignment {
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), UnpackError> {
+        let ((pan_id, coordinator_short_address, channel_number, short_address), data) =
+            <(PANID, ShortAddress, u8, ShortAddress)>::unpack(data)?;
+        let (channel_page, data) = if data.is_empty() {
+            (None, data)
+        } else {
+            let (channel_page, data) = u8::unpack(data)?;
+            (Some(channel_page), data)
+        };
+        Ok((
+            CoordinatorRealignment {
+                pan_id,
+                coordinator_short_address,
+                channel_number,
+                short_address,
+                channel_page,
+            },
+            data,
+        ))
+    }
+
+    fn pack<T: PackTarget>(&self, target: T) -> Result<T, PackError<T::Error>> {
+        let target = (
+            self.pan_id,
+            self.coordinator_short_address,
+            self.channel_number,
+            self.short_address,
+        )
+            .pack(target)?;
+        match self.channel_page {
+            Some(channel_page) => channel_page.pack(target),
+            None => Ok(target),
+        }
+    }
+}
+
 // IEEE Std 802.15.4 - 2015: 7.5.3 - Table 7-50
 #[derive(Debug, Clone, PartialEq, Eq, PackTagged, ExtEnum)]
 #[tag_type(u8)]
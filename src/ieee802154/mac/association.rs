@@ -0,0 +1,183 @@
+use crate::ieee802154::frame;
+use crate::ieee802154::mac::data::{DataRequest, DataService};
+use crate::ieee802154::mac::datapoll::DataPoll;
+use crate::ieee802154::mac::devicequeue::{Acknowledgement, DeviceQueueError};
+use crate::ieee802154::mac::scan::RESPONSE_WAIT_TIME;
+use crate::ieee802154::pib::PIB;
+use crate::ieee802154::services::mlme;
+use crate::ieee802154::{ShortAddress, PANID};
+use crate::unique_key::UniqueKey;
+use futures::future::BoxFuture;
+use std::task::{Context, Poll};
+
+enum AssociationState {
+    Tuning,                          // Waiting for the radio to be on the coordinator's channel
+    Requesting(UniqueKey),           // Association request queued, waiting for its Ack
+    Waiting(BoxFuture<'static, ()>), // Giving the coordinator time to make a decision
+    Polling(DataPoll),               // Retrieving the association response
+}
+
+/**
+ * An MLME-ASSOCIATE in progress, as described in 6.4.1 of 802.15.4-2015.
+ * Sends an association request to the coordinator, and polls it for the response once
+ * macResponseWaitTime has passed.
+ */
+pub struct Association {
+    channel_number: u16,
+    coord_address: frame::FullAddress,
+    capability_information: frame::CapabilityInformation,
+    state: AssociationState,
+    status: Option<Result<ShortAddress, mlme::Error>>, // Set when the association ended
+}
+
+impl Association {
+    pub fn new(request: mlme::AssociateRequest) -> Result<Self, mlme::Error> {
+        if request.channel_page != 0
+            || request.channel_number < 11
+            || request.channel_number > 26
+            || request.coord_address.pan_id == PANID::broadcast()
+        {
+            return Err(mlme::Error::InvalidParameter);
+        }
+        Ok(Self {
+            channel_number: request.channel_number,
+            coord_address: request.coord_address,
+            capability_information: request.capability_information,
+            state: AssociationState::Tuning,
+            status: None,
+        })
+    }
+
+    /**
+     * Channel the radio should be tuned to, the coordinator's.
+     */
+    pub fn channel(&self) -> u16 {
+        self.channel_number
+    }
+
+    fn fail(&mut self, pib: &mut PIB, error: mlme::Error) {
+        pib.mac_pan_id = PANID::broadcast();
+        self.status = Some(Err(error));
+    }
+
+    /**
+     * Moves the association along, channel_ready tells whether the radio has been tuned to the
+     * channel returned by channel().
+     */
+    pub fn poll_action(
+        &mut self,
+        pib: &mut PIB,
+        data: &mut DataService,
+        channel_ready: bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<mlme::AssociateConfirm> {
+        loop {
+            if let Some(status) = self.status.take() {
+                return Poll::Ready(mlme::AssociateConfirm { status });
+            }
+            match &mut self.state {
+                AssociationState::Tuning => {
+                    if !channel_ready {
+                        return Poll::Pending;
+                    }
+                    // The PAN ID is still the broadcast one, as the request requires.
+                    let key = UniqueKey::new();
+                    let command =
+                        frame::Command::AssociationRequest(self.capability_information.clone());
+                    data.insert(
+                        pib,
+                        DataRequest {
+                            key,
+                            destination: Some(self.coord_address),
+                            source_mode: frame::AddressingMode::Extended,
+                            acknowledge_request: true,
+                            seq_num_suppressed: false,
+                            indirect: false,
+                            security: None,
+                            header_ies: vec![],
+                            payload_ies: vec![],
+                            content: frame::FrameType::Command(command),
+                        },
+                    );
+                    self.state = AssociationState::Requesting(key);
+                }
+                AssociationState::Waiting(timeout) => {
+                    if timeout.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    self.state =
+                        AssociationState::Polling(DataPoll::new(pib, data, self.coord_address));
+                }
+                AssociationState::Polling(poll) => match poll.poll_action(cx) {
+                    // Anything but the association response does not answer our request.
+                    Poll::Ready(Ok(())) => self.fail(pib, mlme::Error::NoData),
+                    Poll::Ready(Err(e)) => self.fail(pib, e),
+                    Poll::Pending => return Poll::Pending,
+                },
+                AssociationState::Requesting(_) => return Poll::Pending,
+            }
+        }
+    }
+
+    /**
+     * Processes the result of a command queued by the association, returns true if it was used.
+     */
+    pub fn process_command_result(
+        &mut self,
+        pib: &mut PIB,
+        key: UniqueKey,
+        result: &Result<Acknowledgement, DeviceQueueError>,
+    ) -> bool {
+        match &mut self.state {
+            AssociationState::Requesting(k) if *k == key => (),
+            AssociationState::Polling(poll) => return poll.process_command_result(key, result),
+            _ => return false,
+        }
+        match result {
+            Ok(_) => {
+                pib.mac_pan_id = self.coord_address.pan_id;
+                self.state =
+                    AssociationState::Waiting(Box::pin(async_std::task::sleep(RESPONSE_WAIT_TIME)));
+            }
+            Err(e) => self.fail(pib, (*e).into()),
+        }
+        true
+    }
+
+    /**
+     * Processes a frame received while associating, returns true if it was the response.
+     */
+    pub fn process_frame(&mut self, pib: &mut PIB, frame: &frame::Frame) -> bool {
+        // The response may overtake the Ack report of our data request.
+        if !matches!(self.state, AssociationState::Polling(_)) || self.status.is_some() {
+            return false;
+        }
+        let response = match &frame.frame_type {
+            frame::FrameType::Command(frame::Command::AssociationResponse(response)) => response,
+            _ => return false,
+        };
+        let coordinator = match frame.source {
+            Some(frame::FullAddress {
+                address: frame::Address::Extended(coordinator),
+                ..
+            }) => coordinator,
+            _ => return false,
+        };
+        if frame.destination != Some(pib.get_full_extended_address()) {
+            return false;
+        }
+        match response.status {
+            Ok(short_address) => {
+                let coordinator_short_address = match self.coord_address.address {
+                    frame::Address::Short(address) => address,
+                    frame::Address::Extended(_) => ShortAddress::none_assigned(),
+                };
+                pib.mac_short_address = short_address;
+                pib.mac_associated_pan_coord = Some((coordinator, coordinator_short_address));
+                self.status = Some(Ok(short_address));
+            }
+            Err(ref e) => self.fail(pib, mlme::Error::Association(e.clone())),
+        }
+        true
+    }
+}
@@ -1,4 +1,4 @@
-use crate::ieee802154::frame::{Address, FullAddress};
+use crate::ieee802154::mac::devicetable::DeviceAddress;
 use crate::ieee802154::mac::pendingtable::PendingTable;
 use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
 use crate::unique_key::UniqueKey;
@@ -45,13 +45,21 @@ impl CombinedPendingTable {
         }
     }
 
-    pub fn set(&mut self, address: &Option<FullAddress>, inserted: bool) {
+    /**
+     * Forgets what is in the table on the radio side,
+     * such that it will be initialized and filled again.
+     */
+    pub fn reset(&mut self) {
+        self.initializing = None;
+        self.is_initialized = false;
+        self.waker.wake();
+    }
+
+    pub fn set(&mut self, address: &DeviceAddress, inserted: bool) {
         match address {
-            None => self.none = inserted,
-            Some(FullAddress { pan_id, address }) => match address {
-                Address::Short(address) => self.short.set(&(*pan_id, *address), inserted),
-                Address::Extended(address) => self.extended.set(address, inserted),
-            },
+            DeviceAddress::None => self.none = inserted,
+            DeviceAddress::Short(pan_id, address) => self.short.set(&(*pan_id, *address), inserted),
+            DeviceAddress::Extended(address) => self.extended.set(address, inserted),
         }
     }
 
@@ -84,3 +92,9 @@ impl CombinedPendingTable {
         self.extended.report_update_result(key, success);
     }
 }
+
+impl Default for CombinedPendingTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,152 @@
+use crate::ieee802154::pib::PIB;
+use crate::radio::RadioError;
+use crate::unique_key::UniqueKey;
+use crate::waker_store::WakerStore;
+use futures::future::BoxFuture;
+use rand::Rng;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/**
+ * aUnitBackoffPeriod, 20 symbols of 16us each on the 2.4GHz O-QPSK PHY.
+ */
+const UNIT_BACKOFF_PERIOD: Duration = Duration::from_micros(320);
+
+enum CsmaState {
+    Idle, // Ready to start a backoff for the entry
+    Backoff {
+        // Waiting for a random number of backoff periods to pass
+        delay: BoxFuture<'static, ()>,
+    },
+    WaitingForCca {
+        // Waiting for the result of the clear channel assessment
+        cca_key: UniqueKey,
+    },
+    Clear,  // Channel found clear, the entry can be sent
+    Failed, // Channel found busy too often, the entry is reported as failure
+}
+
+struct CsmaEntry {
+    key: UniqueKey,
+    data: Vec<u8>,
+    nb: u8, // Number of backoffs so far
+    be: u8, // Backoff exponent
+}
+
+#[derive(Debug)]
+pub enum CsmaAction {
+    ChannelClear(UniqueKey),
+    SendPacket(UniqueKey, Vec<u8>),
+    Failure(UniqueKey),
+}
+
+/**
+ * Unslotted CSMA-CA as described in 6.2.5.1 of 802.15.4-2015, for a single frame at a time.
+ * The frame is transmitted after a random backoff and a clear channel assessment.
+ */
+pub struct Csma {
+    state: CsmaState,
+    entry: Option<CsmaEntry>,
+    waker: WakerStore,
+}
+
+impl Csma {
+    pub fn new() -> Self {
+        Self {
+            state: CsmaState::Idle,
+            entry: None,
+            waker: WakerStore::new(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.entry.is_none()
+    }
+
+    /**
+     * Starts channel access for a frame, only allowed when idle.
+     */
+    pub fn start(&mut self, pib: &PIB, key: UniqueKey, data: Vec<u8>) {
+        assert!(self.is_idle());
+        self.state = CsmaState::Idle;
+        self.entry = Some(CsmaEntry {
+            key,
+            data,
+            nb: 0,
+            be: pib.mac_min_be,
+        });
+        self.waker.wake();
+    }
+
+    pub fn process_channel_clear_result(
+        &mut self,
+        pib: &PIB,
+        key: UniqueKey,
+        result: Result<bool, RadioError>,
+    ) {
+        if let CsmaState::WaitingForCca { cca_key } = self.state {
+            if cca_key != key {
+                return;
+            }
+            let entry = self.entry.as_mut().unwrap();
+            if let Ok(true) = result {
+                self.state = CsmaState::Clear;
+            } else {
+                // A failed assessment is treated the same as a busy channel.
+                entry.nb += 1;
+                entry.be = pib.mac_max_be.min(entry.be + 1);
+                if entry.nb > pib.mac_max_csma_backoffs {
+                    self.state = CsmaState::Failed;
+                } else {
+                    self.state = CsmaState::Idle;
+                }
+            }
+            self.waker.wake();
+        }
+    }
+
+    pub fn poll_action(&mut self, cx: &mut Context<'_>) -> Poll<CsmaAction> {
+        loop {
+            let entry = match &self.entry {
+                Some(entry) => entry,
+                None => return self.waker.pend(cx),
+            };
+            match std::mem::replace(&mut self.state, CsmaState::Idle) {
+                CsmaState::Idle => {
+                    let periods = rand::thread_rng().gen_range(0, 1u32 << entry.be);
+                    self.state = CsmaState::Backoff {
+                        delay: Box::pin(async_std::task::sleep(UNIT_BACKOFF_PERIOD * periods)),
+                    };
+                    // Poll the delay right away, so it can wake us up.
+                }
+                CsmaState::Backoff { mut delay } => {
+                    if delay.as_mut().poll(cx).is_ready() {
+                        let cca_key = UniqueKey::new();
+                        self.state = CsmaState::WaitingForCca { cca_key };
+                        return Poll::Ready(CsmaAction::ChannelClear(cca_key));
+                    }
+                    self.state = CsmaState::Backoff { delay };
+                    return self.waker.pend(cx);
+                }
+                CsmaState::Clear => {
+                    let entry = self.entry.take().unwrap();
+                    return Poll::Ready(CsmaAction::SendPacket(entry.key, entry.data));
+                }
+                CsmaState::Failed => {
+                    let entry = self.entry.take().unwrap();
+                    return Poll::Ready(CsmaAction::Failure(entry.key));
+                }
+                s => {
+                    self.state = s;
+                    return self.waker.pend(cx);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Csma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,10 +3,16 @@ use crate::ieee802154::frame::{AddressingMode, FrameType, FullAddress};
 use crate::ieee802154::mac::combinedpendingtable::{
     CombinedPendingTable, CombinedPendingTableAction,
 };
-use crate::ieee802154::mac::devicequeue::{DeviceQueue, DeviceQueueAction, DeviceQueueError};
+use crate::ieee802154::mac::devicequeue::{
+    Acknowledgement, DeviceQueue, DeviceQueueAction, DeviceQueueError,
+};
+use crate::ieee802154::mac::devicetable::{DeviceAddress, DeviceTable};
+use crate::ieee802154::mac::duplicatefilter::DuplicateFilter;
 use crate::ieee802154::pib::PIB;
+use crate::ieee802154::security;
 use crate::ieee802154::services::mcps;
 use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use crate::radio::RadioPacket;
 use crate::unique_key::UniqueKey;
 use crate::waker_store::WakerStore;
 use bimap::BiMap;
@@ -18,8 +24,10 @@ pub enum DataServiceAction {
     InitPendingTable(UniqueKey),
     SetPendingShort(UniqueKey, usize, Option<(PANID, ShortAddress)>),
     SetPendingExtended(UniqueKey, usize, Option<ExtendedAddress>),
-    SendFrame(UniqueKey, frame::Frame),
+    SendFrame(UniqueKey, Box<frame::Frame>),
     Confirm(mcps::Confirm),
+    // Result of a frame queued by the MAC itself, rather than through MCPS-DATA
+    CommandResult(UniqueKey, Result<Acknowledgement, DeviceQueueError>),
 }
 
 impl From<CombinedPendingTableAction> for DataServiceAction {
@@ -41,14 +49,22 @@ pub struct DataRequest {
     pub destination: Option<FullAddress>,
     pub source_mode: AddressingMode,
     pub acknowledge_request: bool,
+    pub seq_num_suppressed: bool,
     pub indirect: bool,
+    pub security: Option<frame::AuxiliarySecurityHeader>, // Frame counter is set when sending
+    pub header_ies: Vec<frame::HeaderIE>,
+    pub payload_ies: Vec<frame::PayloadIE>,
     pub content: FrameType,
 }
 
 pub struct DataService {
-    queues: HashMap<Option<FullAddress>, DeviceQueue>,
+    queues: HashMap<DeviceAddress, DeviceQueue>,
     msdu_handles: BiMap<mcps::MsduHandle, UniqueKey>,
     pending_table: CombinedPendingTable,
+    devices: DeviceTable,
+    // Queued commands, addresses are only learned from them once they are delivered
+    undelivered_commands: HashMap<UniqueKey, (Option<FullAddress>, FrameType)>,
+    duplicates: DuplicateFilter,
     waker: WakerStore,
 }
 
@@ -58,23 +74,68 @@ impl DataService {
             queues: HashMap::new(),
             msdu_handles: BiMap::new(),
             pending_table: CombinedPendingTable::new(),
+            devices: DeviceTable::new(),
+            undelivered_commands: HashMap::new(),
+            duplicates: DuplicateFilter::new(),
             waker: WakerStore::new(),
         }
     }
     pub fn insert(&mut self, pib: &PIB, entry: DataRequest) {
-        if let Some(existing) = self.queues.get_mut(&entry.destination) {
-            existing.insert(pib, entry);
+        if let FrameType::Command(_) = entry.content {
+            self.undelivered_commands
+                .insert(entry.key, (entry.destination, entry.content.clone()));
+        }
+        // Frames for a device go into the queue it already has, whichever address it was
+        // created for, such that they go out in order.
+        let destination = self.devices.identify(entry.destination.into());
+        let existing = self
+            .devices
+            .aliases(destination)
+            .into_iter()
+            .find(|address| self.queues.contains_key(address));
+        if let Some(existing) = existing {
+            self.queues.get_mut(&existing).unwrap().insert(pib, entry);
         } else {
             let mut new_queue = DeviceQueue::new();
-            let destination = entry.destination;
             new_queue.insert(pib, entry);
             self.queues.insert(destination, new_queue);
             self.waker.wake();
         }
     }
 
+    fn learn_addresses(
+        &mut self,
+        source: Option<FullAddress>,
+        destination: Option<FullAddress>,
+        frame_type: &FrameType,
+    ) {
+        for address in self
+            .devices
+            .learn_from_frame(source, destination, frame_type)
+        {
+            self.update_pending(address);
+        }
+    }
+
+    /**
+     * Sets the pending bit for all addresses of a device, if any of its queues has a frame
+     * waiting for a data request.
+     */
+    fn update_pending(&mut self, address: DeviceAddress) {
+        let aliases = self.devices.aliases(address);
+        let pending = aliases.iter().any(|alias| {
+            self.queues
+                .get(alias)
+                .is_some_and(|queue| queue.is_pending())
+        });
+        for alias in aliases {
+            self.pending_table.set(&alias, pending);
+        }
+    }
+
     pub fn remove(&mut self, key: UniqueKey) -> bool {
         let mut removed = false;
+        self.undelivered_commands.remove(&key);
         for (_, queue) in self.queues.iter_mut() {
             removed = removed || queue.remove(key);
         }
@@ -94,32 +155,37 @@ impl DataService {
                             self.queues.remove(&destination);
                             continue 'retry;
                         }
-                        DeviceQueueAction::SetPending(pending) => {
-                            self.pending_table.set(destination, pending);
+                        DeviceQueueAction::SetPending(_) => {
+                            let destination = *destination;
+                            self.update_pending(destination);
                             continue 'retry;
                         }
                         DeviceQueueAction::SendFrame(key, frame) => {
                             return Poll::Ready(DataServiceAction::SendFrame(key, frame));
                         }
                         DeviceQueueAction::ReportResult(key, result) => {
+                            if let Some((destination, content)) =
+                                self.undelivered_commands.remove(&key)
+                            {
+                                if result.is_ok() {
+                                    self.learn_addresses(
+                                        Some(pib.get_full_extended_address()),
+                                        destination,
+                                        &content,
+                                    );
+                                }
+                            }
                             if let Some((handle, _)) = self.msdu_handles.remove_by_right(&key) {
                                 return Poll::Ready(DataServiceAction::Confirm(
                                     mcps::Confirm::Data(mcps::DataConfirm {
                                         msdu_handle: handle,
-                                        ack_payload: result.map_err(|e| match e {
-                                            DeviceQueueError::TransactionExpired => {
-                                                mcps::Error::TransactionExpired
-                                            }
-                                            DeviceQueueError::SendFailure => {
-                                                mcps::Error::ChannelAccessFailure
-                                            }
-                                            DeviceQueueError::NoAck => mcps::Error::NoAck,
-                                        }),
+                                        ack_payload: result
+                                            .map(|ack| ack.payload)
+                                            .map_err(mcps::Error::from),
                                     }),
                                 ));
                             } else {
-                                // Nothing to report to outside
-                                continue 'retry;
+                                return Poll::Ready(DataServiceAction::CommandResult(key, result));
                             }
                         }
                     }
@@ -130,6 +196,12 @@ impl DataService {
     }
 }
 
+impl Default for DataService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DataService {
     pub fn process_init_pending_table_result(&mut self, key: UniqueKey, success: bool) {
         self.pending_table.report_init_result(key, success)
@@ -137,28 +209,188 @@ impl DataService {
     pub fn process_set_pending_result(&mut self, key: UniqueKey, success: bool) {
         self.pending_table.report_update_result(key, success)
     }
+    pub fn process_radio_reconnected(&mut self) {
+        self.pending_table.reset();
+        self.waker.wake();
+    }
     pub fn process_send_result(&mut self, key: UniqueKey, success: bool) {
         for (_destination, queue) in self.queues.iter_mut() {
             queue.process_send_result(key, success);
         }
     }
+
+    pub fn process_channel_access_failure(&mut self, key: UniqueKey) {
+        for (_destination, queue) in self.queues.iter_mut() {
+            queue.process_channel_access_failure(key);
+        }
+    }
+
+    /**
+     * Addresses of the devices with indirect data waiting, to be advertised in a beacon.
+     * A beacon has room for seven addresses, short addresses are listed first.
+     */
+    pub fn pending_addresses(&self) -> (Vec<ShortAddress>, Vec<ExtendedAddress>) {
+        let mut short_addresses = vec![];
+        let mut extended_addresses = vec![];
+        for (destination, queue) in self.queues.iter() {
+            if !queue.is_pending() {
+                continue;
+            }
+            match destination {
+                DeviceAddress::Short(_, address) => short_addresses.push(*address),
+                DeviceAddress::Extended(address) => extended_addresses.push(*address),
+                DeviceAddress::None => (),
+            }
+        }
+        // Sorted, so beacons do not change with the order of the queues.
+        short_addresses.sort_by_key(|address| address.0);
+        extended_addresses.sort_by_key(|address| address.0);
+        short_addresses.truncate(7);
+        extended_addresses.truncate(7 - short_addresses.len());
+        (short_addresses, extended_addresses)
+    }
 }
 
 impl DataService {
-    pub fn process_frame(&mut self, pib: &PIB, frame: &frame::Frame) {
+    /**
+     * Checks whether a received frame is a retransmission of the previous frame from its
+     * source. It is still acknowledged, but should not be processed again.
+     */
+    pub fn is_duplicate(&mut self, frame: &frame::Frame) -> bool {
+        self.duplicates.is_duplicate(frame)
+    }
+
+    pub fn process_frame(
+        &mut self,
+        pib: &PIB,
+        frame: &frame::Frame,
+        packet: &RadioPacket,
+    ) -> Option<mcps::Indication> {
+        self.learn_addresses(frame.source, frame.destination, &frame.frame_type);
         match &frame.frame_type {
-            frame::FrameType::Ack(payload) => self.process_frame_ack(frame, &payload),
+            frame::FrameType::Ack(payload) => {
+                self.process_frame_ack(frame, payload);
+                None
+            }
             frame::FrameType::Command(frame::Command::DataRequest()) => {
-                self.process_frame_data_request(pib, frame)
+                self.process_frame_data_request(pib, frame);
+                None
             }
-            // TODO: Data frames!
-            _ => (),
+            frame::FrameType::Data(payload) => self.process_frame_data(pib, frame, payload, packet),
+            _ => None,
+        }
+    }
+
+    /**
+     * Checks whether a received frame was meant for us, as described in 6.7.2 of 802.15.4-2015.
+     * Frames without destination address are only accepted by the PAN coordinator,
+     * and only if they originate from within our PAN.
+     */
+    pub fn is_addressed_to_us(pib: &PIB, frame: &frame::Frame) -> bool {
+        match frame.destination {
+            Some(FullAddress { pan_id, address }) => {
+                (pan_id == pib.mac_pan_id || pan_id == PANID::broadcast())
+                    && (address == frame::Address::Short(pib.mac_short_address)
+                        || address == frame::Address::Short(ShortAddress::broadcast())
+                        || address == frame::Address::Extended(pib.mac_extended_address))
+            }
+            None => {
+                pib.mac_associated_pan_coord
+                    == Some((pib.mac_extended_address, pib.mac_short_address))
+                    && frame.source.map(|source| source.pan_id) == Some(pib.mac_pan_id)
+            }
+        }
+    }
+
+    /**
+     * Creates the Enh-Ack for a received frame, as described in 7.3.3 of 802.15.4-2015. Frames
+     * of older versions are acknowledged by the radio itself.
+     */
+    pub fn create_enhanced_ack(&self, pib: &PIB, frame: &frame::Frame) -> Option<frame::Frame> {
+        let broadcast = matches!(
+            frame.destination,
+            Some(FullAddress {
+                address: frame::Address::Short(address),
+                ..
+            }) if address == ShortAddress::broadcast()
+        );
+        if frame.version < frame::FrameVersion::Ieee2015
+            || !frame.acknowledge_request
+            || broadcast
+            || matches!(frame.frame_type, FrameType::Ack(_))
+            || !DataService::is_addressed_to_us(pib, frame)
+        {
+            return None;
         }
+        let frame_pending = matches!(
+            frame.frame_type,
+            FrameType::Command(frame::Command::DataRequest())
+        ) && self
+            .devices
+            .aliases(frame.source.into())
+            .iter()
+            .any(|address| {
+                self.queues
+                    .get(address)
+                    .is_some_and(|queue| queue.is_pending())
+            });
+        Some(frame::Frame {
+            version: frame::FrameVersion::Ieee2015,
+            frame_pending,
+            acknowledge_request: false,
+            sequence_number: frame.sequence_number,
+            destination: frame.source,
+            // The receiver looks up the key by source, without one it would assume the coordinator.
+            source: frame.security.map(|_| pib.get_full_extended_address()),
+            // Secured frames get an Ack secured the same way, with a fresh frame counter.
+            security: frame
+                .security
+                .map(|security| frame::AuxiliarySecurityHeader {
+                    frame_counter: 0,
+                    ..security
+                }),
+            header_ies: pib.mac_enh_ack_header_ie_list.clone(),
+            payload_ies: vec![],
+            frame_type: FrameType::Ack(frame::Payload(vec![])),
+        })
+    }
+
+    fn process_frame_data(
+        &mut self,
+        pib: &PIB,
+        frame: &frame::Frame,
+        payload: &frame::Payload,
+        packet: &RadioPacket,
+    ) -> Option<mcps::Indication> {
+        if !DataService::is_addressed_to_us(pib, frame) {
+            println!("Ignoring: Data frame not meant for me");
+            return None;
+        }
+        Some(mcps::Indication::Data(mcps::DataIndication {
+            source: frame.source,
+            destination: frame.destination,
+            msdu: payload.0.clone(),
+            mpdu_link_quality: packet.link_quality,
+            dsn: frame.sequence_number,
+            rssi: packet.rssi,
+            security_level: frame
+                .security
+                .map_or(frame::SecurityLevel::None, |security| {
+                    security.security_level
+                }),
+            key_identifier: frame
+                .security
+                .map_or(frame::KeyIdentifier::Implicit(), |security| {
+                    security.key_identifier
+                }),
+            header_ie_list: frame.header_ies.clone(),
+            payload_ie_list: frame.payload_ies.clone(),
+        }))
     }
 
     fn process_frame_ack(&mut self, frame: &frame::Frame, payload: &frame::Payload) {
         for (_destination, queue) in self.queues.iter_mut() {
-            queue.process_acknowledge(frame.sequence_number, &payload.0);
+            queue.process_acknowledge(frame.sequence_number, frame.frame_pending, &payload.0);
         }
     }
 
@@ -174,7 +406,18 @@ impl DataService {
             // TODO2:
             // If a DataRequest is received, the device should be promoted in the PendingTable,
             // such that if the pending bit was not set now, it will be on the second request.
-            if let Some(queue) = self.queues.get_mut(&frame.source) {
+            // A data request is answered with a single frame, from the first queue with one
+            // waiting.
+            let pending = self
+                .devices
+                .aliases(frame.source.into())
+                .into_iter()
+                .find(|address| {
+                    self.queues
+                        .get(address)
+                        .is_some_and(|queue| queue.is_pending())
+                });
+            if let Some(queue) = pending.and_then(|address| self.queues.get_mut(&address)) {
                 queue.process_datarequest();
             }
         }
@@ -198,13 +441,40 @@ impl DataService {
         pib: &PIB,
         request: mcps::DataRequest,
     ) -> Option<mcps::Confirm> {
+        // Without a sequence number, there is no way to match the Ack to the frame.
+        if request.seq_num_suppressed && request.ack_tx {
+            return Some(mcps::Confirm::Data(mcps::DataConfirm {
+                msdu_handle: request.msdu_handle,
+                ack_payload: Err(mcps::Error::InvalidParameter),
+            }));
+        }
+        let security = if request.security_level == frame::SecurityLevel::None {
+            None
+        } else if let Err(e) =
+            security::check_outgoing(pib, request.security_level, request.key_identifier)
+        {
+            return Some(mcps::Confirm::Data(mcps::DataConfirm {
+                msdu_handle: request.msdu_handle,
+                ack_payload: Err(e),
+            }));
+        } else {
+            Some(frame::AuxiliarySecurityHeader {
+                security_level: request.security_level,
+                key_identifier: request.key_identifier,
+                frame_counter: 0,
+            })
+        };
         let key = UniqueKey::new();
         let internal_request = DataRequest {
             key,
             destination: request.destination,
             source_mode: request.source_addressing_mode,
             acknowledge_request: request.ack_tx,
+            seq_num_suppressed: request.seq_num_suppressed,
             indirect: request.indirect_tx,
+            security,
+            header_ies: request.header_ie_list,
+            payload_ies: request.payload_ie_list,
             content: frame::FrameType::Data(frame::Payload(request.msdu)),
         };
         self.insert(pib, internal_request);
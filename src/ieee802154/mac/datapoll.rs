@@ -0,0 +1,151 @@
+use crate::ieee802154::frame;
+use crate::ieee802154::mac::data::{DataRequest, DataService};
+use crate::ieee802154::mac::devicequeue::{Acknowledgement, DeviceQueueError};
+use crate::ieee802154::pib::PIB;
+use crate::ieee802154::services::mlme;
+use crate::ieee802154::ShortAddress;
+use crate::unique_key::UniqueKey;
+use futures::future::BoxFuture;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/**
+ * macMaxFrameTotalWaitTime, the time to wait for pending data after the coordinator acknowledged
+ * our data request. Well above the 2.4GHz default, as a coordinator behind a serial bridge needs
+ * a round trip to its host before it can answer.
+ */
+const MAX_FRAME_TOTAL_WAIT_TIME: Duration = Duration::from_millis(100);
+
+enum DataPollState {
+    Requesting(UniqueKey),             // Data request queued, waiting for its Ack
+    Receiving(BoxFuture<'static, ()>), // Ack had frame pending set, waiting for the data
+}
+
+/**
+ * Retrieval of data pending at the coordinator, as described in 6.7.3 of 802.15.4-2015.
+ * Sends a data request, and waits for the coordinator to send a frame if the Ack says it has
+ * any. Our radio is never turned off, so there is no need to keep the receiver on explicitly.
+ */
+pub struct DataPoll {
+    coord_address: frame::FullAddress,
+    state: DataPollState,
+    status: Option<Result<(), mlme::Error>>, // Set when the poll ended
+}
+
+impl DataPoll {
+    /**
+     * Queues a data request for the coordinator, the ongoing poll is returned.
+     */
+    pub fn new(pib: &PIB, data: &mut DataService, coord_address: frame::FullAddress) -> Self {
+        let key = UniqueKey::new();
+        // Only use the short address if the coordinator actually assigned us one.
+        let source_mode = if pib.mac_short_address.0 < ShortAddress::none_assigned().0 {
+            frame::AddressingMode::Short
+        } else {
+            frame::AddressingMode::Extended
+        };
+        data.insert(
+            pib,
+            DataRequest {
+                key,
+                destination: Some(coord_address),
+                source_mode,
+                acknowledge_request: true,
+                seq_num_suppressed: false,
+                indirect: false,
+                security: None,
+                header_ies: vec![],
+                payload_ies: vec![],
+                content: frame::FrameType::Command(frame::Command::DataRequest()),
+            },
+        );
+        Self {
+            coord_address,
+            state: DataPollState::Requesting(key),
+            status: None,
+        }
+    }
+
+    pub fn poll_action(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), mlme::Error>> {
+        if let Some(status) = self.status.take() {
+            return Poll::Ready(status);
+        }
+        match &mut self.state {
+            DataPollState::Receiving(timeout) => {
+                if timeout.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(mlme::Error::NoData))
+            }
+            DataPollState::Requesting(_) => Poll::Pending,
+        }
+    }
+
+    /**
+     * Processes the result of a queued frame, returns true if it was our data request.
+     */
+    pub fn process_command_result(
+        &mut self,
+        key: UniqueKey,
+        result: &Result<Acknowledgement, DeviceQueueError>,
+    ) -> bool {
+        if !matches!(self.state, DataPollState::Requesting(k) if k == key) {
+            return false;
+        }
+        match result {
+            Ok(ack) if ack.frame_pending => {
+                self.state = DataPollState::Receiving(Box::pin(async_std::task::sleep(
+                    MAX_FRAME_TOTAL_WAIT_TIME,
+                )));
+            }
+            Ok(_) => self.status = self.status.take().or(Some(Err(mlme::Error::NoData))),
+            Err(e) => self.status = Some(Err((*e).into())),
+        }
+        true
+    }
+
+    /**
+     * Whether a frame was sent to us, rather than broadcast, by the coordinator we are polling.
+     */
+    fn is_from_coordinator(&self, pib: &PIB, frame: &frame::Frame) -> bool {
+        let source = match frame.source {
+            Some(source) => source.address,
+            None => return false,
+        };
+        let coordinator = pib.mac_associated_pan_coord;
+        let from_coordinator = source == self.coord_address.address
+            || matches!(coordinator, Some((extended, _)) if source == frame::Address::Extended(extended))
+            || matches!(coordinator, Some((_, short)) if source == frame::Address::Short(short));
+        let to_us = match frame.destination {
+            Some(frame::FullAddress { pan_id, address }) if pan_id == pib.mac_pan_id => {
+                address == frame::Address::Extended(pib.mac_extended_address)
+                    || (pib.mac_short_address.0 < ShortAddress::none_assigned().0
+                        && address == frame::Address::Short(pib.mac_short_address))
+            }
+            _ => false,
+        };
+        from_coordinator && to_us
+    }
+
+    /**
+     * Processes a data or command frame received while polling, returns true if it ended the
+     * poll.
+     */
+    pub fn process_frame(&mut self, pib: &PIB, frame: &frame::Frame) -> bool {
+        let empty = match &frame.frame_type {
+            frame::FrameType::Data(payload) => payload.0.is_empty(),
+            frame::FrameType::Command(_) => false,
+            _ => return false,
+        };
+        if self.status.is_some() || !self.is_from_coordinator(pib, frame) {
+            return false;
+        }
+        // The data may overtake the Ack report of our data request.
+        self.status = Some(if empty {
+            Err(mlme::Error::NoData)
+        } else {
+            Ok(())
+        });
+        true
+    }
+}
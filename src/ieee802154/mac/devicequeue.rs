@@ -1,6 +1,7 @@
 use crate::ieee802154::frame;
 use crate::ieee802154::mac::data::DataRequest;
 use crate::ieee802154::pib::PIB;
+use crate::ieee802154::services::mlme;
 use crate::unique_key::UniqueKey;
 use crate::waker_store::WakerStore;
 use futures::future::BoxFuture;
@@ -16,7 +17,7 @@ enum DeviceQueueState {
         // Sending out a frame, waiting for result.
         send_key: UniqueKey,
         ack_requested: Option<u8>,
-        ack_payload: Vec<u8>,
+        ack: Acknowledgement,
     },
     WaitingForAck {
         // Waiting for an Ack frame
@@ -24,7 +25,7 @@ enum DeviceQueueState {
         timeout: BoxFuture<'static, ()>,
     },
     HaveResult {
-        result: Result<Vec<u8>, DeviceQueueError>,
+        result: Result<Acknowledgement, DeviceQueueError>,
     }, // First entry in queue has result, ReportResult should be triggered.
 }
 struct DeviceQueueEntry {
@@ -39,18 +40,39 @@ pub struct DeviceQueue {
     waker: WakerStore,
 }
 
-#[derive(Debug)]
+/**
+ * Contents of the Ack frame received for a frame, empty if no Ack was requested.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Acknowledgement {
+    pub frame_pending: bool, // The receiver has more data for us
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum DeviceQueueError {
-    TransactionExpired, // Frame was not polled within the time allocated
-    SendFailure,        // After several tries
-    NoAck,              // After several tries
+    TransactionExpired,   // Frame was not polled within the time allocated
+    ChannelAccessFailure, // CSMA-CA found the channel busy
+    SendFailure,          // The radio failed to send, after several tries
+    NoAck,                // After several tries
+}
+
+impl From<DeviceQueueError> for mlme::Error {
+    fn from(error: DeviceQueueError) -> mlme::Error {
+        match error {
+            DeviceQueueError::TransactionExpired => mlme::Error::TransactionExpired,
+            DeviceQueueError::ChannelAccessFailure => mlme::Error::ChannelAccessFailure,
+            DeviceQueueError::SendFailure => mlme::Error::RadioFailure,
+            DeviceQueueError::NoAck => mlme::Error::NoAck,
+        }
+    }
 }
 
 pub enum DeviceQueueAction {
-    Empty(),                            // Device queue is empty, and should be discarded.
-    SetPending(bool),                   // Pending bit should be set.
-    SendFrame(UniqueKey, frame::Frame), // Frame should be sent out.
-    ReportResult(UniqueKey, Result<Vec<u8>, DeviceQueueError>), // Frame was fully sent.
+    Empty(),                                 // Device queue is empty, and should be discarded.
+    SetPending(bool),                        // Pending bit should be set.
+    SendFrame(UniqueKey, Box<frame::Frame>), // Frame should be sent out.
+    ReportResult(UniqueKey, Result<Acknowledgement, DeviceQueueError>), // Frame was fully sent.
 }
 
 impl DeviceQueue {
@@ -103,6 +125,15 @@ impl DeviceQueue {
         }
     }
 
+    /**
+     * Whether the frame at the front of the queue is waiting for a data request.
+     */
+    pub fn is_pending(&self) -> bool {
+        self.entries
+            .front()
+            .is_some_and(|entry| entry.data.indirect)
+    }
+
     pub fn process_datarequest(&mut self) {
         if let DeviceQueueState::Idle { .. } = self.state {
             self.state = DeviceQueueState::Idle { datarequest: true };
@@ -121,17 +152,27 @@ impl DeviceQueue {
             frame::AddressingMode::Short => Some(pib.get_full_short_address()),
             frame::AddressingMode::Extended => Some(pib.get_full_extended_address()),
         };
-        let sequence_nr = pib.next_data_sequence_nr();
-        let frame = frame::Frame {
+        let sequence_nr = if request.seq_num_suppressed {
+            None
+        } else {
+            Some(pib.next_data_sequence_nr())
+        };
+        let mut frame = frame::Frame {
+            version: frame::FrameVersion::Ieee2003,
             frame_pending: more_pending,
             acknowledge_request: request.acknowledge_request,
-            sequence_number: Some(sequence_nr),
+            sequence_number: sequence_nr,
             destination: request.destination,
             source,
+            security: request.security,
+            header_ies: request.header_ies.clone(),
+            payload_ies: request.payload_ies.clone(),
             frame_type: request.content.clone(),
         };
+        // Older devices may not understand newer frame versions, only use them when needed.
+        frame.version = frame.minimum_version();
         let ack_request = if request.acknowledge_request {
-            Some(sequence_nr)
+            sequence_nr
         } else {
             None
         };
@@ -142,7 +183,7 @@ impl DeviceQueue {
         if let DeviceQueueState::Sending {
             send_key,
             ack_requested,
-            ack_payload,
+            ack,
         } = &self.state
         {
             if *send_key == key {
@@ -156,7 +197,7 @@ impl DeviceQueue {
                         };
                     } else {
                         self.state = DeviceQueueState::HaveResult {
-                            result: Ok(ack_payload.clone()),
+                            result: Ok(ack.clone()),
                         };
                     }
                 } else if let Some(front_entry) = self.entries.front_mut() {
@@ -177,24 +218,45 @@ impl DeviceQueue {
         }
     }
 
-    pub fn process_acknowledge(&mut self, seq_nr: Option<u8>, payload: &[u8]) {
+    /**
+     * CSMA-CA already backed off several times before giving up, so a direct frame is not
+     * retried. An indirect frame stays queued until the next data request.
+     */
+    pub fn process_channel_access_failure(&mut self, key: UniqueKey) {
+        if let DeviceQueueState::Sending { send_key, .. } = &self.state {
+            if *send_key == key {
+                match self.entries.front() {
+                    Some(front_entry) if !front_entry.data.indirect => {
+                        self.state = DeviceQueueState::HaveResult {
+                            result: Err(DeviceQueueError::ChannelAccessFailure),
+                        };
+                    }
+                    _ => self.state = DeviceQueueState::Idle { datarequest: false },
+                }
+            }
+        }
+    }
+
+    pub fn process_acknowledge(&mut self, seq_nr: Option<u8>, frame_pending: bool, payload: &[u8]) {
+        let ack = Acknowledgement {
+            frame_pending,
+            payload: payload.into(),
+        };
         if let DeviceQueueState::WaitingForAck { ack_requested, .. } = self.state {
             if seq_nr == Some(ack_requested) {
-                self.state = DeviceQueueState::HaveResult {
-                    result: Ok(payload.into()),
-                }
+                self.state = DeviceQueueState::HaveResult { result: Ok(ack) }
             }
         } else if let DeviceQueueState::Sending {
             send_key,
             ack_requested,
-            ack_payload: _,
+            ack: _,
         } = self.state
         {
             if ack_requested.is_some() && ack_requested == seq_nr {
                 self.state = DeviceQueueState::Sending {
                     send_key,
                     ack_requested: None,
-                    ack_payload: payload.into(),
+                    ack,
                 };
             }
         }
@@ -205,10 +267,7 @@ impl DeviceQueue {
         pib: &mut PIB,
         cx: &mut Context<'_>,
     ) -> Poll<DeviceQueueAction> {
-        let should_be_pending = self
-            .entries
-            .front()
-            .map_or(false, |entry| entry.data.indirect);
+        let should_be_pending = self.is_pending();
         if self.last_pending_reported != Some(should_be_pending) {
             self.last_pending_reported = Some(should_be_pending);
             return Poll::Ready(DeviceQueueAction::SetPending(should_be_pending));
@@ -234,9 +293,12 @@ impl DeviceQueue {
                         self.state = DeviceQueueState::Sending {
                             send_key,
                             ack_requested,
-                            ack_payload: Vec::new(),
+                            ack: Acknowledgement::default(),
                         };
-                        return Poll::Ready(DeviceQueueAction::SendFrame(send_key, frame));
+                        return Poll::Ready(DeviceQueueAction::SendFrame(
+                            send_key,
+                            Box::new(frame),
+                        ));
                     }
                 }
                 DeviceQueueState::WaitingForAck {
@@ -252,6 +314,8 @@ impl DeviceQueue {
                             // Lower retries counter, and go back to idle to retry
                             let front_entry = self.entries.front_mut().unwrap();
                             front_entry.retries_left -= 1;
+                            // Nothing else will wake us up, make sure the retry goes out.
+                            cx.waker().wake_by_ref();
                         } else {
                             // Remove entry, report result as failed.
                             let key = front_entry.data.key;
@@ -295,3 +359,9 @@ impl DeviceQueue {
         }
     }
 }
+
+impl Default for DeviceQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,154 @@
+use crate::ieee802154::frame::{
+    Address, AssociationResponse, Command, CoordinatorRealignment, FrameType, FullAddress,
+};
+use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use bimap::BiMap;
+
+/**
+ * Address of a device, as used to look up the data waiting for it. Extended addresses are
+ * unique, so they identify a device regardless of the PAN ID they come with.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceAddress {
+    None, // Frames without destination address, for the PAN coordinator
+    Short(PANID, ShortAddress),
+    Extended(ExtendedAddress),
+}
+
+impl From<Option<FullAddress>> for DeviceAddress {
+    fn from(address: Option<FullAddress>) -> Self {
+        match address {
+            None => DeviceAddress::None,
+            Some(FullAddress {
+                pan_id,
+                address: Address::Short(address),
+            }) => DeviceAddress::Short(pan_id, address),
+            Some(FullAddress {
+                address: Address::Extended(address),
+                ..
+            }) => DeviceAddress::Extended(address),
+        }
+    }
+}
+
+/**
+ * Keeps track of which short address belongs to which extended address, such that a device can
+ * be recognized whichever addressing mode it uses.
+ */
+pub struct DeviceTable {
+    devices: BiMap<(PANID, ShortAddress), ExtendedAddress>,
+}
+
+impl DeviceTable {
+    pub fn new() -> Self {
+        Self {
+            devices: BiMap::new(),
+        }
+    }
+
+    /**
+     * All addresses a device is known by, starting with the given one.
+     */
+    pub fn aliases(&self, address: DeviceAddress) -> Vec<DeviceAddress> {
+        let alias = match address {
+            DeviceAddress::None => None,
+            DeviceAddress::Short(pan_id, short_address) => self
+                .devices
+                .get_by_left(&(pan_id, short_address))
+                .map(|extended_address| DeviceAddress::Extended(*extended_address)),
+            DeviceAddress::Extended(extended_address) => self
+                .devices
+                .get_by_right(&extended_address)
+                .map(|(pan_id, short_address)| DeviceAddress::Short(*pan_id, *short_address)),
+        };
+        std::iter::once(address).chain(alias).collect()
+    }
+
+    /**
+     * The extended address of a device if it is known, the given address otherwise.
+     */
+    pub fn identify(&self, address: DeviceAddress) -> DeviceAddress {
+        match address {
+            DeviceAddress::Short(pan_id, short_address) => self
+                .devices
+                .get_by_left(&(pan_id, short_address))
+                .map_or(address, |extended_address| {
+                    DeviceAddress::Extended(*extended_address)
+                }),
+            _ => address,
+        }
+    }
+
+    /**
+     * Records that a device uses a short address. Any earlier owner of the short address, or
+     * earlier short address of the device, is forgotten. Returns the addresses that changed
+     * meaning, including the old ones.
+     */
+    pub fn learn(
+        &mut self,
+        pan_id: PANID,
+        short_address: ShortAddress,
+        extended_address: ExtendedAddress,
+    ) -> Vec<DeviceAddress> {
+        if short_address == ShortAddress::broadcast()
+            || short_address == ShortAddress::none_assigned()
+            || self.devices.get_by_left(&(pan_id, short_address)) == Some(&extended_address)
+        {
+            return vec![];
+        }
+        let mut changed = self.aliases(DeviceAddress::Short(pan_id, short_address));
+        changed.extend(self.aliases(DeviceAddress::Extended(extended_address)));
+        self.devices
+            .insert((pan_id, short_address), extended_address);
+        changed
+    }
+
+    /**
+     * Learns addresses from frames that hand out short addresses, both sent and received.
+     * Returns the addresses that changed meaning, as for learn.
+     */
+    pub fn learn_from_frame(
+        &mut self,
+        source: Option<FullAddress>,
+        destination: Option<FullAddress>,
+        frame_type: &FrameType,
+    ) -> Vec<DeviceAddress> {
+        let mut changed = vec![];
+        match frame_type {
+            FrameType::Command(Command::AssociationResponse(AssociationResponse {
+                status: Ok(short_address),
+                ..
+            })) => {
+                if let Some(FullAddress {
+                    pan_id,
+                    address: Address::Extended(device),
+                }) = destination
+                {
+                    changed.extend(self.learn(pan_id, *short_address, device));
+                }
+            }
+            FrameType::Command(Command::CoordinatorRealignment(CoordinatorRealignment {
+                pan_id,
+                coordinator_short_address,
+                short_address,
+                ..
+            })) => {
+                if let DeviceAddress::Extended(coordinator) = source.into() {
+                    changed.extend(self.learn(*pan_id, *coordinator_short_address, coordinator));
+                }
+                // Only orphaned devices are given a short address.
+                if let DeviceAddress::Extended(device) = destination.into() {
+                    changed.extend(self.learn(*pan_id, *short_address, device));
+                }
+            }
+            _ => (),
+        }
+        changed
+    }
+}
+
+impl Default for DeviceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
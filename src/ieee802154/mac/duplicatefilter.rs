@@ -0,0 +1,72 @@
+use crate::ieee802154::frame;
+use crate::ieee802154::frame::{FrameType, FullAddress};
+use std::collections::VecDeque;
+
+/**
+ * Number of sources to remember the recent sequence numbers of. Once full, the source heard
+ * from least recently is forgotten.
+ */
+const DUPLICATE_FILTER_SIZE: usize = 16;
+
+/**
+ * Number of sequence numbers remembered per source. A sender can have frames for several
+ * destinations in flight, so a retransmission does not have to follow its original directly.
+ */
+const DUPLICATE_FILTER_WINDOW: usize = 4;
+
+/**
+ * Recognizes retransmissions of frames that were already received, but of which the sender
+ * did not see the Ack, by remembering the recent sequence numbers of each source.
+ */
+pub struct DuplicateFilter {
+    entries: VecDeque<(FullAddress, VecDeque<u8>)>, // Least recently heard from first
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(DUPLICATE_FILTER_SIZE),
+        }
+    }
+
+    /**
+     * Records the sequence number of a received frame, and returns whether it is one of the
+     * recent ones from this source. Only data and command frames with an Ack request are
+     * retransmitted, other frames are never considered duplicates.
+     */
+    pub fn is_duplicate(&mut self, frame: &frame::Frame) -> bool {
+        if !frame.acknowledge_request
+            || !matches!(frame.frame_type, FrameType::Data(_) | FrameType::Command(_))
+        {
+            return false;
+        }
+        let (source, sequence_number) = match (frame.source, frame.sequence_number) {
+            (Some(source), Some(sequence_number)) => (source, sequence_number),
+            _ => return false,
+        };
+        let recent = self
+            .entries
+            .iter()
+            .position(|(address, _)| *address == source)
+            .and_then(|index| self.entries.remove(index));
+        if recent.is_none() && self.entries.len() == DUPLICATE_FILTER_SIZE {
+            self.entries.pop_front();
+        }
+        let mut recent = recent.map_or_else(VecDeque::new, |(_, recent)| recent);
+        let duplicate = recent.contains(&sequence_number);
+        if !duplicate {
+            if recent.len() == DUPLICATE_FILTER_WINDOW {
+                recent.pop_front();
+            }
+            recent.push_back(sequence_number);
+        }
+        self.entries.push_back((source, recent));
+        duplicate
+    }
+}
+
+impl Default for DuplicateFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,15 @@
 use crate::ieee802154::frame;
+use crate::ieee802154::mac::association::Association;
 use crate::ieee802154::mac::data::{DataRequest, DataService};
+use crate::ieee802154::mac::datapoll::DataPoll;
+use crate::ieee802154::mac::devicequeue::{Acknowledgement, DeviceQueueError};
+use crate::ieee802154::mac::scan::{Scan, ScanAction};
 use crate::ieee802154::pib::{PIBValue, PIB};
 use crate::ieee802154::services::mlme;
 use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
-use crate::radio::{RadioParam, RadioParamValue, RadioRxMode};
+use crate::radio::{
+    RadioError, RadioPacket, RadioParam, RadioParamType, RadioParamValue, RadioRxMode,
+};
 use crate::unique_key::UniqueKey;
 use crate::waker_store::WakerStore;
 use std::collections::HashMap;
@@ -13,7 +19,9 @@ use std::task::{Context, Poll};
 #[derive(Debug)]
 pub enum ManagementServiceAction {
     SetParam(UniqueKey, RadioParam, RadioParamValue),
+    GetParam(UniqueKey, RadioParam, RadioParamType),
     SendFrame(frame::Frame),
+    Confirm(mlme::Confirm),
 }
 
 struct RadioParamInfo {
@@ -26,6 +34,15 @@ pub struct ManagementService {
     outgoing: VecDeque<frame::Frame>,
     waker: WakerStore,
     radio_params: HashMap<RadioParam, RadioParamInfo>,
+    scan: Option<Scan>,
+    association: Option<Association>,
+    data_poll: Option<DataPoll>,
+    // Disassociation notifications in flight, with the address to confirm and whether we leave
+    disassociations: HashMap<UniqueKey, (frame::FullAddress, bool)>,
+    // MLME-START waiting for its coordinator realignment to go out
+    realignment: Option<(UniqueKey, mlme::StartRequest)>,
+    // Commands sent on behalf of an MLME response, with their source and destination
+    comm_status: HashMap<UniqueKey, (frame::FullAddress, frame::FullAddress)>,
 }
 
 impl ManagementService {
@@ -34,6 +51,12 @@ impl ManagementService {
             outgoing: VecDeque::new(),
             waker: WakerStore::new(),
             radio_params: HashMap::new(),
+            scan: None,
+            association: None,
+            data_poll: None,
+            disassociations: HashMap::new(),
+            realignment: None,
+            comm_status: HashMap::new(),
         };
         ret.update_radio_parameters(pib);
         ret
@@ -64,7 +87,9 @@ impl ManagementService {
                 if !result {
                     info.dirty = true;
                 }
-                should_wake = info.dirty || should_wake;
+                // A scan or association waits for the radio to be tuned before sending.
+                should_wake =
+                    info.dirty || self.scan.is_some() || self.association.is_some() || should_wake;
             }
         }
         if should_wake {
@@ -72,16 +97,191 @@ impl ManagementService {
         }
     }
 
-    pub fn poll_action(&mut self, cx: &mut Context<'_>) -> Poll<ManagementServiceAction> {
+    /**
+     * The radio lost all of its state, so all parameters have to be sent again.
+     */
+    pub fn process_radio_reconnected(&mut self) {
+        for (_param, info) in self.radio_params.iter_mut() {
+            info.updating = None;
+            info.dirty = true;
+        }
+        self.waker.wake();
+    }
+
+    /**
+     * Whether all parameters the radio should have were accepted by it.
+     */
+    fn radio_params_settled(&self, params: &[RadioParam]) -> bool {
+        !params.iter().any(|param| {
+            matches!(self.radio_params.get(param), Some(info) if info.dirty || info.updating.is_some())
+        })
+    }
+
+    fn poll_scan(&mut self, pib: &mut PIB, cx: &mut Context<'_>) -> Poll<ManagementServiceAction> {
+        let channel_ready = self.radio_params_settled(&[RadioParam::Channel, RadioParam::PanId]);
+        let action = match &mut self.scan {
+            Some(scan) => scan.poll_action(pib, channel_ready, cx),
+            None => return Poll::Pending,
+        };
+        match action {
+            Poll::Ready(ScanAction::SetChannel(_)) => {
+                self.update_radio_parameters(pib);
+                self.poll_radio_param_update(cx)
+            }
+            Poll::Ready(ScanAction::SendFrame(frame)) => {
+                Poll::Ready(ManagementServiceAction::SendFrame(frame))
+            }
+            Poll::Ready(ScanAction::MeasureEnergy(key)) => Poll::Ready(
+                ManagementServiceAction::GetParam(key, RadioParam::Rssi, RadioParamType::U16),
+            ),
+            Poll::Ready(ScanAction::Done(confirm)) => {
+                self.scan = None;
+                self.update_radio_parameters(pib);
+                Poll::Ready(ManagementServiceAction::Confirm(mlme::Confirm::Scan(
+                    confirm,
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_association(
+        &mut self,
+        pib: &mut PIB,
+        data: &mut DataService,
+        cx: &mut Context<'_>,
+    ) -> Poll<ManagementServiceAction> {
+        let channel_ready = self.radio_params_settled(&[RadioParam::Channel]);
+        let confirm = match &mut self.association {
+            Some(association) => association.poll_action(pib, data, channel_ready, cx),
+            None => return Poll::Pending,
+        };
+        match confirm {
+            Poll::Ready(confirm) => {
+                self.association = None;
+                self.update_radio_parameters(pib);
+                Poll::Ready(ManagementServiceAction::Confirm(mlme::Confirm::Associate(
+                    confirm,
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_data_poll(&mut self, cx: &mut Context<'_>) -> Poll<ManagementServiceAction> {
+        let status = match &mut self.data_poll {
+            Some(data_poll) => data_poll.poll_action(cx),
+            None => return Poll::Pending,
+        };
+        status.map(|status| {
+            self.data_poll = None;
+            ManagementServiceAction::Confirm(mlme::Confirm::Poll(status))
+        })
+    }
+
+    pub fn poll_action(
+        &mut self,
+        pib: &mut PIB,
+        data: &mut DataService,
+        cx: &mut Context<'_>,
+    ) -> Poll<ManagementServiceAction> {
         if let Poll::Ready(action) = self.poll_radio_param_update(cx) {
             Poll::Ready(action)
         } else if let Some(outgoing) = self.outgoing.pop_front() {
             Poll::Ready(ManagementServiceAction::SendFrame(outgoing))
+        } else if let Poll::Ready(action) = self.poll_scan(pib, cx) {
+            Poll::Ready(action)
+        } else if let Poll::Ready(action) = self.poll_association(pib, data, cx) {
+            Poll::Ready(action)
+        } else if let Poll::Ready(action) = self.poll_data_poll(cx) {
+            Poll::Ready(action)
         } else {
             self.waker.pend(cx)
         }
     }
 
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    pub fn process_get_param_result(
+        &mut self,
+        key: UniqueKey,
+        result: Result<RadioParamValue, RadioError>,
+    ) {
+        let rssi = match result {
+            Ok(RadioParamValue::U16(value)) => Some(value as i16),
+            _ => None,
+        };
+        if let Some(scan) = &mut self.scan {
+            if scan.process_energy_measurement(key, rssi) {
+                self.waker.wake();
+            }
+        }
+    }
+
+    /**
+     * Handles the result of a frame the MAC queued itself, such as an association request.
+     */
+    pub fn process_command_result(
+        &mut self,
+        pib: &mut PIB,
+        key: UniqueKey,
+        result: Result<Acknowledgement, DeviceQueueError>,
+    ) -> Option<mlme::Output> {
+        if let Some((source, destination)) = self.comm_status.remove(&key) {
+            return Some(mlme::Output::Indication(mlme::Indication::CommStatus {
+                source,
+                destination,
+                status: result.map(|_| ()).map_err(|e| e.into()),
+            }));
+        }
+        if let Some(association) = &mut self.association {
+            if association.process_command_result(pib, key, &result) {
+                self.update_radio_parameters(pib);
+                self.waker.wake();
+            }
+        }
+        if let Some(data_poll) = &mut self.data_poll {
+            if data_poll.process_command_result(key, &result) {
+                self.waker.wake();
+            }
+        }
+        if matches!(self.realignment, Some((k, _)) if k == key) {
+            let (_, request) = self.realignment.take().unwrap();
+            // The PAN only changes once the children were told about it.
+            let status = match result {
+                Ok(_) => {
+                    self.apply_start_request(pib, request);
+                    Ok(())
+                }
+                Err(e) => Err(e.into()),
+            };
+            return Some(mlme::Output::Confirm(mlme::Confirm::Start(status)));
+        }
+        let (device_address, leaving) = self.disassociations.remove(&key)?;
+        // Even if the coordinator did not hear us, we are no longer part of the PAN.
+        if leaving {
+            self.leave_pan(pib);
+        }
+        Some(mlme::Output::Confirm(mlme::Confirm::Disassociate(
+            mlme::DisassociateConfirm {
+                status: result.map(|_| ()).map_err(|e| e.into()),
+                device_address,
+            },
+        )))
+    }
+
+    /**
+     * Forgets everything about the PAN we were associated with.
+     */
+    fn leave_pan(&mut self, pib: &mut PIB) {
+        pib.mac_pan_id = PANID::broadcast();
+        pib.mac_short_address = ShortAddress::broadcast();
+        pib.mac_associated_pan_coord = None;
+        self.update_radio_parameters(pib);
+    }
+
     fn update_radio_parameter(&mut self, parameter: RadioParam, value: RadioParamValue) {
         if let Some(current) = self.radio_params.get_mut(&parameter) {
             if current.requested_value != value {
@@ -103,8 +303,16 @@ impl ManagementService {
     }
 
     fn update_radio_parameters(&mut self, pib: &PIB) {
-        self.update_radio_parameter(RadioParam::Channel, pib.phy_current_channel.into());
-        self.update_radio_parameter(RadioParam::PanId, pib.mac_pan_id.0.into());
+        // While scanning, the radio hops channels without the PIB changing.
+        let scan = self.scan.as_ref();
+        let channel = scan
+            .and_then(|scan| scan.channel())
+            .unwrap_or(pib.phy_current_channel);
+        let pan_id = scan
+            .and_then(|scan| scan.pan_id())
+            .unwrap_or(pib.mac_pan_id);
+        self.update_radio_parameter(RadioParam::Channel, channel.into());
+        self.update_radio_parameter(RadioParam::PanId, pan_id.0.into());
         self.update_radio_parameter(RadioParam::ShortAddress, pib.mac_short_address.0.into());
         self.update_radio_parameter(
             RadioParam::RxMode,
@@ -125,6 +333,7 @@ impl ManagementService {
     pub fn process_mlme_beacon_request(
         &mut self,
         pib: &mut PIB,
+        data: &DataService,
         request: mlme::BeaconRequest,
     ) -> Result<(), mlme::Error> {
         if request.superframe_order != 15
@@ -133,23 +342,50 @@ impl ManagementService {
         {
             return Err(mlme::Error::InvalidParameter);
         }
-        let beacon = frame::Beacon {
-            beacon_order: 15,
-            superframe_order: request.superframe_order,
-            final_cap_slot: 15,
-            battery_life_extension: false,
-            pan_coordinator: pib.mac_associated_pan_coord
-                == Some((pib.mac_extended_address, pib.mac_short_address)),
-            association_permit: pib.mac_association_permit,
-            payload: frame::Payload(pib.mac_beacon_payload.clone()),
+        let payload = frame::Payload(pib.mac_beacon_payload.clone());
+        // Only enhanced beacons, of frame version 2, can carry IEs.
+        let (version, frame_type) = match request.beacon_type {
+            mlme::BeaconType::Beacon => {
+                if !request.header_ie_list.is_empty() || !request.payload_ie_list.is_empty() {
+                    return Err(mlme::Error::InvalidParameter);
+                }
+                let (pending_short_addresses, pending_extended_addresses) =
+                    data.pending_addresses();
+                let beacon = frame::Beacon {
+                    beacon_order: 15,
+                    superframe_order: request.superframe_order,
+                    final_cap_slot: 15,
+                    battery_life_extension: false,
+                    pan_coordinator: pib.mac_associated_pan_coord
+                        == Some((pib.mac_extended_address, pib.mac_short_address)),
+                    association_permit: pib.mac_association_permit,
+                    gts_permit: false,
+                    gts_descriptors: vec![],
+                    pending_short_addresses,
+                    pending_extended_addresses,
+                    payload,
+                };
+                (
+                    frame::FrameVersion::Ieee2003,
+                    frame::FrameType::Beacon(beacon),
+                )
+            }
+            mlme::BeaconType::EnhancedBeacon => (
+                frame::FrameVersion::Ieee2015,
+                frame::FrameType::EnhancedBeacon(payload),
+            ),
         };
         let frame = frame::Frame {
+            version,
             frame_pending: false,
             acknowledge_request: false,
             sequence_number: Some(pib.next_beacon_sequence_nr()),
             destination: None,
             source: Some(pib.get_full_short_address()),
-            frame_type: frame::FrameType::Beacon(beacon),
+            security: None,
+            header_ies: request.header_ie_list,
+            payload_ies: request.payload_ie_list,
+            frame_type,
         };
         self.outgoing.push_back(frame);
         Ok(())
@@ -172,6 +408,124 @@ impl ManagementService {
         Ok(())
     }
 
+    /**
+     * Handles an MLME-SCAN.request
+     * The confirm is sent out once the scan is done, unless the request was rejected.
+     */
+    pub fn process_mlme_scan_request(
+        &mut self,
+        pib: &PIB,
+        request: mlme::ScanRequest,
+    ) -> Result<(), mlme::ScanConfirm> {
+        let scan_type = request.scan_type;
+        let channel_page = request.channel_page;
+        let error = |status| mlme::ScanConfirm {
+            status: Err(status),
+            scan_type,
+            channel_page,
+            unscanned_channels: Vec::new(),
+            energy_detect_list: Vec::new(),
+            pan_descriptor_list: Vec::new(),
+        };
+        if self.scan.is_some() {
+            return Err(error(mlme::Error::ScanInProgress));
+        }
+        self.scan = Some(Scan::new(request).map_err(error)?);
+        self.update_radio_parameters(pib);
+        self.waker.wake();
+        Ok(())
+    }
+
+    /**
+     * Handles an MLME-ASSOCIATE.request
+     * The confirm is sent out once the coordinator answered, unless the request was rejected.
+     */
+    pub fn process_mlme_associate_request(
+        &mut self,
+        pib: &mut PIB,
+        request: mlme::AssociateRequest,
+    ) -> Result<(), mlme::AssociateConfirm> {
+        let error = |status| mlme::AssociateConfirm {
+            status: Err(status),
+        };
+        if self.scan.is_some() {
+            return Err(error(mlme::Error::ScanInProgress));
+        }
+        if self.association.is_some() {
+            return Err(error(mlme::Error::InvalidParameter));
+        }
+        let association = Association::new(request).map_err(error)?;
+        pib.phy_current_channel = association.channel();
+        self.association = Some(association);
+        self.update_radio_parameters(pib);
+        self.waker.wake();
+        Ok(())
+    }
+
+    /**
+     * Handles an MLME-DISASSOCIATE.request
+     * The confirm is sent out once the notification was delivered, or failed to be.
+     */
+    pub fn process_mlme_disassociate_request(
+        &mut self,
+        pib: &PIB,
+        data: &mut DataService,
+        request: mlme::DisassociateRequest,
+    ) -> Result<(), mlme::DisassociateConfirm> {
+        if request.device_address.pan_id != pib.mac_pan_id {
+            return Err(mlme::DisassociateConfirm {
+                status: Err(mlme::Error::InvalidParameter),
+                device_address: request.device_address,
+            });
+        }
+        let leaving = match (pib.mac_associated_pan_coord, request.device_address.address) {
+            (Some((coordinator, _)), _) if coordinator == pib.mac_extended_address => false,
+            (Some((coordinator, _)), frame::Address::Extended(address)) => address == coordinator,
+            (Some((_, coordinator)), frame::Address::Short(address)) => address == coordinator,
+            (None, _) => false,
+        };
+        let key = UniqueKey::new();
+        let entry = DataRequest {
+            key,
+            destination: Some(request.device_address),
+            source_mode: frame::AddressingMode::Extended,
+            acknowledge_request: true,
+            seq_num_suppressed: false,
+            indirect: request.tx_indirect && !leaving,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            content: frame::FrameType::Command(frame::Command::DisassociationNotification(
+                request.reason,
+            )),
+        };
+        data.insert(pib, entry);
+        self.disassociations
+            .insert(key, (request.device_address, leaving));
+        Ok(())
+    }
+
+    /**
+     * Handles an MLME-POLL.request
+     * The confirm is sent out once data was received or the coordinator had none for us.
+     */
+    pub fn process_mlme_poll_request(
+        &mut self,
+        pib: &PIB,
+        data: &mut DataService,
+        request: mlme::PollRequest,
+    ) -> Result<(), mlme::Error> {
+        if self.scan.is_some() {
+            return Err(mlme::Error::ScanInProgress);
+        }
+        if self.association.is_some() || self.data_poll.is_some() {
+            return Err(mlme::Error::InvalidParameter);
+        }
+        self.data_poll = Some(DataPoll::new(pib, data, request.coord_address));
+        self.waker.wake();
+        Ok(())
+    }
+
     /**
      * Handles an MLME-GET.request
      */
@@ -199,12 +553,52 @@ impl ManagementService {
 
     /**
      * Handles an MLME-START.request
+     * With a coordinator realignment, the confirm is sent once the realignment went out.
      */
     pub fn process_mlme_start_request(
         &mut self,
         pib: &mut PIB,
+        data: &mut DataService,
         request: mlme::StartRequest,
-    ) -> Result<(), mlme::Error> {
+    ) -> Option<Result<(), mlme::Error>> {
+        if let Err(e) = Self::validate_start_request(pib, &request) {
+            return Some(Err(e));
+        }
+        if !request.coord_realignment {
+            self.apply_start_request(pib, request);
+            return Some(Ok(()));
+        }
+        if self.realignment.is_some() {
+            return Some(Err(mlme::Error::InvalidParameter));
+        }
+        let key = UniqueKey::new();
+        let command = Self::realignment_command(
+            pib,
+            request.pan_id,
+            request.channel_number,
+            ShortAddress::broadcast(),
+        );
+        let entry = DataRequest {
+            key,
+            destination: Some(frame::FullAddress {
+                pan_id: PANID::broadcast(),
+                address: frame::Address::Short(ShortAddress::broadcast()),
+            }),
+            source_mode: frame::AddressingMode::Extended,
+            acknowledge_request: false,
+            seq_num_suppressed: false,
+            indirect: false,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            content: frame::FrameType::Command(command),
+        };
+        data.insert(pib, entry);
+        self.realignment = Some((key, request));
+        None
+    }
+
+    fn validate_start_request(pib: &PIB, request: &mlme::StartRequest) -> Result<(), mlme::Error> {
         if pib.mac_short_address == ShortAddress(0xFFFF) {
             return Err(mlme::Error::NoShortAddress);
         }
@@ -217,13 +611,34 @@ impl ManagementService {
         {
             return Err(mlme::Error::InvalidParameter);
         }
+        Ok(())
+    }
+
+    fn apply_start_request(&mut self, pib: &mut PIB, request: mlme::StartRequest) {
         pib.phy_current_channel = request.channel_number;
         pib.mac_pan_id = request.pan_id;
         if request.pan_coordinator {
             pib.mac_associated_pan_coord = Some((pib.mac_extended_address, pib.mac_short_address));
         }
         self.update_radio_parameters(pib);
-        Ok(())
+    }
+
+    /**
+     * Coordinator realignment telling a child, or all of them, where to find us.
+     */
+    fn realignment_command(
+        pib: &PIB,
+        pan_id: PANID,
+        channel_number: u16,
+        short_address: ShortAddress,
+    ) -> frame::Command {
+        frame::Command::CoordinatorRealignment(frame::CoordinatorRealignment {
+            pan_id,
+            coordinator_short_address: pib.mac_short_address,
+            channel_number: channel_number as u8,
+            short_address,
+            channel_page: None,
+        })
     }
 
     /**
@@ -237,14 +652,30 @@ impl ManagementService {
     ) -> Option<mlme::Confirm> {
         match request {
             mlme::Request::Beacon(request) => Some(mlme::Confirm::Beacon(
-                self.process_mlme_beacon_request(pib, request),
+                self.process_mlme_beacon_request(pib, data, request),
             )),
             mlme::Request::Reset(request) => Some(mlme::Confirm::Reset(
                 self.process_mlme_reset_request(data, pib, request),
             )),
-            mlme::Request::Start(request) => Some(mlme::Confirm::Start(
-                self.process_mlme_start_request(pib, request),
-            )),
+            mlme::Request::Start(request) => self
+                .process_mlme_start_request(pib, data, request)
+                .map(mlme::Confirm::Start),
+            mlme::Request::Scan(request) => self
+                .process_mlme_scan_request(pib, request)
+                .err()
+                .map(mlme::Confirm::Scan),
+            mlme::Request::Associate(request) => self
+                .process_mlme_associate_request(pib, request)
+                .err()
+                .map(mlme::Confirm::Associate),
+            mlme::Request::Disassociate(request) => self
+                .process_mlme_disassociate_request(pib, data, request)
+                .err()
+                .map(mlme::Confirm::Disassociate),
+            mlme::Request::Poll(request) => self
+                .process_mlme_poll_request(pib, data, request)
+                .err()
+                .map(|e| mlme::Confirm::Poll(Err(e))),
             mlme::Request::Get(request) => Some(mlme::Confirm::Get(
                 request.attribute,
                 self.process_mlme_get_request(pib, request),
@@ -276,7 +707,53 @@ impl ManagementService {
                 fast_association,
                 status,
             ),
+            mlme::Response::Orphan {
+                orphan_address,
+                short_address,
+                associated_member,
+            } => self.process_mlme_orphan_response(
+                data,
+                pib,
+                orphan_address,
+                short_address,
+                associated_member,
+            ),
+        }
+    }
+
+    fn process_mlme_orphan_response(
+        &mut self,
+        data: &mut DataService,
+        pib: &PIB,
+        orphan_address: ExtendedAddress,
+        short_address: ShortAddress,
+        associated_member: bool,
+    ) {
+        if !associated_member {
+            return;
         }
+        let command =
+            Self::realignment_command(pib, pib.mac_pan_id, pib.phy_current_channel, short_address);
+        let key = UniqueKey::new();
+        let destination = frame::FullAddress {
+            pan_id: PANID::broadcast(),
+            address: frame::Address::Extended(orphan_address),
+        };
+        self.comm_status
+            .insert(key, (pib.get_full_extended_address(), destination));
+        let entry = DataRequest {
+            key,
+            destination: Some(destination),
+            source_mode: frame::AddressingMode::Extended,
+            acknowledge_request: true,
+            seq_num_suppressed: false,
+            indirect: false,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            content: frame::FrameType::Command(command),
+        };
+        data.insert(pib, entry);
     }
 
     fn process_mlme_associate_response(
@@ -292,15 +769,23 @@ impl ManagementService {
             fast_association,
             status,
         });
+        let key = UniqueKey::new();
+        let destination = frame::FullAddress {
+            pan_id: pib.mac_pan_id,
+            address: frame::Address::Extended(device_address),
+        };
+        self.comm_status
+            .insert(key, (pib.get_full_extended_address(), destination));
         let entry = DataRequest {
-            key: UniqueKey::new(),
-            destination: Some(frame::FullAddress {
-                pan_id: pib.mac_pan_id,
-                address: frame::Address::Extended(device_address),
-            }),
+            key,
+            destination: Some(destination),
             source_mode: frame::AddressingMode::Extended,
             acknowledge_request: true,
+            seq_num_suppressed: false,
             indirect: !fast_association,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
             content: frame::FrameType::Command(command),
         };
         data.insert(pib, entry);
@@ -311,42 +796,177 @@ impl ManagementService {
     pub fn process_frame(
         &mut self,
         pib: &mut PIB,
+        data: &DataService,
         frame: &frame::Frame,
+        packet: &RadioPacket,
     ) -> Option<mlme::Indication> {
+        if let Some(scan) = &mut self.scan {
+            if scan.process_frame(pib, frame, packet) {
+                self.waker.wake();
+            }
+            // Anything else is discarded while scanning
+            return None;
+        }
+        if let Some(association) = &mut self.association {
+            if association.process_frame(pib, frame) {
+                self.waker.wake();
+                return None;
+            }
+        }
+        // Polled data is still delivered by the data service.
+        if let Some(data_poll) = &mut self.data_poll {
+            if data_poll.process_frame(pib, frame) {
+                self.waker.wake();
+            }
+        }
         match &frame.frame_type {
             frame::FrameType::Command(frame::Command::BeaconRequest()) => {
-                self.process_frame_beacon_request(pib, frame)
+                self.process_frame_beacon_request(pib, data, frame)
             }
             frame::FrameType::Command(frame::Command::AssociationRequest(req)) => {
                 self.process_frame_association_request(pib, frame, req)
             }
+            frame::FrameType::Command(frame::Command::DisassociationNotification(reason)) => {
+                self.process_frame_disassociation_notification(pib, frame, *reason)
+            }
+            frame::FrameType::Command(frame::Command::OrphanNotification()) => {
+                self.process_frame_orphan_notification(pib, frame)
+            }
+            frame::FrameType::Command(frame::Command::CoordinatorRealignment(realignment)) => {
+                self.process_frame_coordinator_realignment(pib, frame, realignment)
+            }
             _ => None,
         }
     }
 
+    pub fn process_frame_orphan_notification(
+        &self,
+        pib: &PIB,
+        frame: &frame::Frame,
+    ) -> Option<mlme::Indication> {
+        if pib.mac_associated_pan_coord != Some((pib.mac_extended_address, pib.mac_short_address)) {
+            println!("Ignoring: Orphan notification while not a PAN coordinator");
+            return None;
+        }
+        if let Some(frame::FullAddress {
+            address: frame::Address::Extended(orphan_address),
+            ..
+        }) = frame.source
+        {
+            Some(mlme::Indication::Orphan { orphan_address })
+        } else {
+            println!("Invalid source address in OrphanNotification");
+            None
+        }
+    }
+
+    /**
+     * Our coordinator moved the PAN, follow it.
+     */
+    pub fn process_frame_coordinator_realignment(
+        &mut self,
+        pib: &mut PIB,
+        frame: &frame::Frame,
+        realignment: &frame::CoordinatorRealignment,
+    ) -> Option<mlme::Indication> {
+        let coordinator = match (pib.mac_associated_pan_coord, frame.source) {
+            (
+                Some((coordinator, _)),
+                Some(frame::FullAddress {
+                    address: frame::Address::Extended(source),
+                    ..
+                }),
+            ) if coordinator == source && coordinator != pib.mac_extended_address => coordinator,
+            _ => {
+                println!("Ignoring: Coordinator realignment not from my coordinator");
+                return None;
+            }
+        };
+        if !DataService::is_addressed_to_us(pib, frame) {
+            println!("Ignoring: Coordinator realignment not meant for me");
+            return None;
+        }
+        pib.mac_pan_id = realignment.pan_id;
+        pib.mac_associated_pan_coord = Some((coordinator, realignment.coordinator_short_address));
+        pib.phy_current_channel = realignment.channel_number as u16;
+        // A broadcast realignment leaves our short address alone.
+        if realignment.short_address != ShortAddress::broadcast() {
+            pib.mac_short_address = realignment.short_address;
+        }
+        self.update_radio_parameters(pib);
+        Some(mlme::Indication::SyncLoss {
+            reason: mlme::SyncLossReason::Realignment,
+            pan_id: realignment.pan_id,
+            channel_number: pib.phy_current_channel,
+            channel_page: realignment.channel_page.unwrap_or(0) as u16,
+        })
+    }
+
+    pub fn process_frame_disassociation_notification(
+        &mut self,
+        pib: &mut PIB,
+        frame: &frame::Frame,
+        reason: frame::DisassociationReason,
+    ) -> Option<mlme::Indication> {
+        if !DataService::is_addressed_to_us(pib, frame) {
+            println!("Ignoring: Disassociation notification not meant for me");
+            return None;
+        }
+        let device_address = match frame.source {
+            Some(frame::FullAddress {
+                address: frame::Address::Extended(address),
+                ..
+            }) => address,
+            _ => {
+                println!("Invalid source address in DisassociationNotification");
+                return None;
+            }
+        };
+        if matches!(pib.mac_associated_pan_coord, Some((coordinator, _)) if coordinator == device_address)
+        {
+            // Our coordinator kicked us off the PAN.
+            self.leave_pan(pib);
+        }
+        Some(mlme::Indication::Disassociate {
+            device_address,
+            reason,
+        })
+    }
+
     pub fn process_frame_beacon_request(
         &mut self,
         pib: &mut PIB,
+        data: &DataService,
         frame: &frame::Frame,
     ) -> Option<mlme::Indication> {
-        let beacon_type = mlme::BeaconType::Beacon; // NOTE: Cheating, we should check the frame more carefully.
+        // A beacon request of frame version 2 is an Enhanced Beacon Request.
+        let beacon_type = if frame.version == frame::FrameVersion::Ieee2015 {
+            mlme::BeaconType::EnhancedBeacon
+        } else {
+            mlme::BeaconType::Beacon
+        };
         if pib.mac_beacon_auto_respond {
             let request = mlme::BeaconRequest {
                 beacon_type,
                 channel: pib.phy_current_channel,
                 channel_page: 0,
                 superframe_order: 15,
+                header_ie_list: vec![],
+                payload_ie_list: vec![],
                 dst_addr: frame.source,
             };
-            self.process_mlme_beacon_request(pib, request).unwrap_or(());
+            self.process_mlme_beacon_request(pib, data, request)
+                .unwrap_or(());
             None
         } else {
             Some(mlme::Indication::BeaconRequest {
-                beacon_type: mlme::BeaconType::Beacon,
+                beacon_type,
                 src_addr: frame.source,
                 dst_pan_id: frame
                     .destination
                     .map_or(PANID::broadcast(), |full_address| full_address.pan_id),
+                header_ie_list: frame.header_ies.clone(),
+                payload_ie_list: frame.payload_ies.clone(),
             })
         }
     }
@@ -1,6 +1,15 @@
+pub mod association;
 pub mod combinedpendingtable;
+pub mod csma;
 pub mod data;
+pub mod datapoll;
 pub mod devicequeue;
+pub mod devicetable;
+pub mod duplicatefilter;
 pub mod management;
 pub mod pendingtable;
+pub mod scan;
 pub mod service;
+#[cfg(test)]
+mod tests;
+pub mod txscheduler;
@@ -0,0 +1,343 @@
+use crate::ieee802154::frame;
+use crate::ieee802154::pib::PIB;
+use crate::ieee802154::services::mlme;
+use crate::ieee802154::{ShortAddress, PANID};
+use crate::radio::RadioPacket;
+use crate::unique_key::UniqueKey;
+use futures::future::BoxFuture;
+use std::collections::VecDeque;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/**
+ * aBaseSuperframeDuration (960 symbols) for the 2.4GHz O-QPSK PHY, at 16us per symbol.
+ */
+const BASE_SUPERFRAME_DURATION: Duration = Duration::from_micros(960 * 16);
+/**
+ * macResponseWaitTime, used as the time to wait for a realignment during an orphan scan, and
+ * before polling for an association response.
+ */
+pub const RESPONSE_WAIT_TIME: Duration = Duration::from_micros(32 * 960 * 16);
+/**
+ * Maximum number of PAN descriptors collected, after which the scan ends with LimitReached.
+ */
+const MAX_PAN_DESCRIPTORS: usize = 32;
+
+pub enum ScanAction {
+    SetChannel(u16), // Radio should be tuned to this channel
+    SendFrame(frame::Frame),
+    MeasureEnergy(UniqueKey),
+    Done(mlme::ScanConfirm),
+}
+
+enum ChannelState {
+    Tuning,
+    Listening {
+        timeout: BoxFuture<'static, ()>,
+        measuring: Option<UniqueKey>, // RSSI reading in flight
+        energy: Option<u8>,           // Highest ED value seen on this channel
+    },
+}
+
+/**
+ * An MLME-SCAN in progress, as described in 6.3 of 802.15.4-2015.
+ * Visits all requested channels in order, and collects energy measurements, beacons or a
+ * coordinator realignment depending on the scan type.
+ */
+pub struct Scan {
+    scan_type: mlme::ScanType,
+    channel_page: u16,
+    scan_duration: Duration,
+    remaining: VecDeque<u16>,
+    current: Option<(u16, ChannelState)>,
+    energy_detect_list: Vec<u8>,
+    pan_descriptor_list: Vec<mlme::PanDescriptor>,
+    status: Option<Result<(), mlme::Error>>, // Set when the scan ended early
+}
+
+/**
+ * Converts an RSSI reading in dBm to an ED value, 0x00 being -100dBm or less, 2 steps per dB.
+ */
+fn energy_level(rssi: i16) -> u8 {
+    ((i32::from(rssi) + 100).max(0) * 2).min(0xFF) as u8
+}
+
+impl Scan {
+    pub fn new(request: mlme::ScanRequest) -> Result<Self, mlme::Error> {
+        if request.channel_page != 0
+            || request.scan_duration > 14
+            || request
+                .scan_channels
+                .iter()
+                .any(|channel| *channel < 11 || *channel > 26)
+        {
+            return Err(mlme::Error::InvalidParameter);
+        }
+        let scan_duration = if request.scan_type == mlme::ScanType::Orphan {
+            RESPONSE_WAIT_TIME
+        } else {
+            BASE_SUPERFRAME_DURATION * ((1 << request.scan_duration) + 1)
+        };
+        Ok(Self {
+            scan_type: request.scan_type,
+            channel_page: request.channel_page,
+            scan_duration,
+            remaining: request.scan_channels.into(),
+            current: None,
+            energy_detect_list: Vec::new(),
+            pan_descriptor_list: Vec::new(),
+            status: None,
+        })
+    }
+
+    /**
+     * Channel the radio should be tuned to for the scan.
+     */
+    pub fn channel(&self) -> Option<u16> {
+        self.current.as_ref().map(|(channel, _)| *channel)
+    }
+
+    /**
+     * Active and passive scans need to hear beacons from any PAN.
+     */
+    pub fn pan_id(&self) -> Option<PANID> {
+        match self.scan_type {
+            mlme::ScanType::Active | mlme::ScanType::Passive | mlme::ScanType::EnhancedActive => {
+                Some(PANID::broadcast())
+            }
+            _ => None,
+        }
+    }
+
+    fn create_request_frame(&self, pib: &mut PIB) -> Option<frame::Frame> {
+        let broadcast = frame::FullAddress {
+            pan_id: PANID::broadcast(),
+            address: frame::Address::Short(ShortAddress::broadcast()),
+        };
+        let mut version = frame::FrameVersion::Ieee2003;
+        let (source, command) = match self.scan_type {
+            mlme::ScanType::Active => (None, frame::Command::BeaconRequest()),
+            mlme::ScanType::EnhancedActive => {
+                version = frame::FrameVersion::Ieee2015;
+                (None, frame::Command::BeaconRequest())
+            }
+            mlme::ScanType::Orphan => (
+                Some(frame::FullAddress {
+                    pan_id: PANID::broadcast(),
+                    address: frame::Address::Extended(pib.mac_extended_address),
+                }),
+                frame::Command::OrphanNotification(),
+            ),
+            _ => return None,
+        };
+        Some(frame::Frame {
+            version,
+            frame_pending: false,
+            acknowledge_request: false,
+            sequence_number: Some(pib.next_data_sequence_nr()),
+            destination: Some(broadcast),
+            source,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: frame::FrameType::Command(command),
+        })
+    }
+
+    fn finish(&mut self) -> mlme::ScanConfirm {
+        let status = self.status.take().unwrap_or(match self.scan_type {
+            mlme::ScanType::EnergyDetect => Ok(()),
+            mlme::ScanType::Active | mlme::ScanType::Passive | mlme::ScanType::EnhancedActive
+                if !self.pan_descriptor_list.is_empty() =>
+            {
+                Ok(())
+            }
+            _ => Err(mlme::Error::NoBeacon),
+        });
+        mlme::ScanConfirm {
+            status,
+            scan_type: self.scan_type,
+            channel_page: self.channel_page,
+            unscanned_channels: self.remaining.drain(..).collect(),
+            energy_detect_list: std::mem::take(&mut self.energy_detect_list),
+            pan_descriptor_list: std::mem::take(&mut self.pan_descriptor_list),
+        }
+    }
+
+    /**
+     * Moves the scan along, channel_ready tells whether the radio has been tuned to the channel
+     * returned by channel().
+     */
+    pub fn poll_action(
+        &mut self,
+        pib: &mut PIB,
+        channel_ready: bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<ScanAction> {
+        loop {
+            if self.status.is_some() {
+                return Poll::Ready(ScanAction::Done(self.finish()));
+            }
+            match &mut self.current {
+                None => {
+                    return Poll::Ready(if let Some(channel) = self.remaining.pop_front() {
+                        self.current = Some((channel, ChannelState::Tuning));
+                        ScanAction::SetChannel(channel)
+                    } else {
+                        ScanAction::Done(self.finish())
+                    });
+                }
+                Some((_, state @ ChannelState::Tuning)) => {
+                    if !channel_ready {
+                        return Poll::Pending;
+                    }
+                    *state = ChannelState::Listening {
+                        timeout: Box::pin(async_std::task::sleep(self.scan_duration)),
+                        measuring: None,
+                        energy: None,
+                    };
+                    if let Some(frame) = self.create_request_frame(pib) {
+                        return Poll::Ready(ScanAction::SendFrame(frame));
+                    }
+                }
+                Some((
+                    _,
+                    ChannelState::Listening {
+                        timeout,
+                        measuring,
+                        energy,
+                    },
+                )) => {
+                    if timeout.as_mut().poll(cx).is_ready() {
+                        if self.scan_type == mlme::ScanType::EnergyDetect {
+                            self.energy_detect_list.push(energy.unwrap_or(0));
+                        }
+                        self.current = None;
+                    } else if self.scan_type == mlme::ScanType::EnergyDetect && measuring.is_none()
+                    {
+                        let key = UniqueKey::new();
+                        *measuring = Some(key);
+                        return Poll::Ready(ScanAction::MeasureEnergy(key));
+                    } else {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Processes an RSSI reading requested with MeasureEnergy, returns true if it was used.
+     */
+    pub fn process_energy_measurement(&mut self, key: UniqueKey, rssi: Option<i16>) -> bool {
+        if let Some((
+            _,
+            ChannelState::Listening {
+                measuring, energy, ..
+            },
+        )) = &mut self.current
+        {
+            if *measuring == Some(key) {
+                *measuring = None;
+                if let Some(rssi) = rssi {
+                    *energy = (*energy).max(Some(energy_level(rssi)));
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /**
+     * Processes a frame received while scanning, returns true if it was used by the scan.
+     */
+    pub fn process_frame(
+        &mut self,
+        pib: &mut PIB,
+        frame: &frame::Frame,
+        packet: &RadioPacket,
+    ) -> bool {
+        let channel = match &self.current {
+            Some((channel, ChannelState::Listening { .. })) => *channel,
+            _ => return false,
+        };
+        match (&frame.frame_type, self.scan_type) {
+            (
+                frame::FrameType::Beacon(_) | frame::FrameType::EnhancedBeacon(_),
+                mlme::ScanType::Active | mlme::ScanType::Passive | mlme::ScanType::EnhancedActive,
+            ) => {
+                let coord_address = match frame.source {
+                    Some(address) => address,
+                    None => return false,
+                };
+                let beacon = match &frame.frame_type {
+                    frame::FrameType::Beacon(beacon) => beacon.clone(),
+                    // Enhanced beacons have no superframe specification.
+                    _ => frame::Beacon {
+                        beacon_order: 15,
+                        superframe_order: 15,
+                        final_cap_slot: 15,
+                        battery_life_extension: false,
+                        pan_coordinator: false,
+                        association_permit: false,
+                        gts_permit: false,
+                        gts_descriptors: vec![],
+                        pending_short_addresses: vec![],
+                        pending_extended_addresses: vec![],
+                        payload: match &frame.frame_type {
+                            frame::FrameType::EnhancedBeacon(payload) => payload.clone(),
+                            _ => frame::Payload(vec![]),
+                        },
+                    },
+                };
+                let descriptor = mlme::PanDescriptor {
+                    coord_address,
+                    channel_number: channel,
+                    channel_page: self.channel_page,
+                    beacon,
+                    link_quality: packet.link_quality,
+                    header_ie_list: frame.header_ies.clone(),
+                    payload_ie_list: frame.payload_ies.clone(),
+                };
+                if !self.pan_descriptor_list.iter().any(|known| {
+                    known.coord_address == descriptor.coord_address
+                        && known.channel_number == descriptor.channel_number
+                }) {
+                    self.pan_descriptor_list.push(descriptor);
+                    if self.pan_descriptor_list.len() >= MAX_PAN_DESCRIPTORS {
+                        self.status = Some(Err(mlme::Error::LimitReached));
+                    }
+                }
+                true
+            }
+            (
+                frame::FrameType::Command(frame::Command::CoordinatorRealignment(realignment)),
+                mlme::ScanType::Orphan,
+            ) => {
+                let coordinator = match frame.source {
+                    Some(frame::FullAddress {
+                        address: frame::Address::Extended(coordinator),
+                        ..
+                    }) => coordinator,
+                    _ => return false,
+                };
+                if frame.destination
+                    != Some(frame::FullAddress {
+                        pan_id: PANID::broadcast(),
+                        address: frame::Address::Extended(pib.mac_extended_address),
+                    })
+                {
+                    return false;
+                }
+                pib.mac_pan_id = realignment.pan_id;
+                pib.mac_short_address = realignment.short_address;
+                pib.mac_associated_pan_coord =
+                    Some((coordinator, realignment.coordinator_short_address));
+                pib.phy_current_channel = realignment.channel_number as u16;
+                self.status = Some(Ok(()));
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use crate::ieee802154::frame;
+use crate::ieee802154::mac::csma::CsmaAction;
 use crate::ieee802154::mac::data::{DataService, DataServiceAction};
 use crate::ieee802154::mac::management::{ManagementService, ManagementServiceAction};
+use crate::ieee802154::mac::txscheduler::{TxPriority, TxScheduler};
 use crate::ieee802154::pib::PIB;
+use crate::ieee802154::security;
 use crate::ieee802154::services::{mcps, mlme};
 use crate::ieee802154::ExtendedAddress;
-use crate::pack::{Pack, VecPackTarget};
+use crate::pack::Pack;
 use crate::radio::{
     RadioError, RadioPacket, RadioParam, RadioParamType, RadioParamValue, RadioRequest,
     RadioResponse, RadioRxMode,
@@ -14,6 +17,7 @@ use futures::channel::mpsc;
 use futures::future::Future;
 use futures::sink::{Sink, SinkExt};
 use futures::stream::{BoxStream, StreamExt};
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -54,14 +58,17 @@ impl SyncRadio {
         self.requests
             .send(RadioRequest::GetParam(token, param, param_type))
             .await
-            .unwrap_or(());
+            .map_err(|_| RadioError::Disconnected)?;
         loop {
-            if let Some(RadioResponse::GetParam(response_token, _, result)) =
-                self.responses.next().await
-            {
-                if token == response_token {
-                    return result;
+            match self.responses.next().await {
+                Some(RadioResponse::GetParam(response_token, _, result))
+                    if token == response_token =>
+                {
+                    return result
                 }
+                Some(_) => (),
+                // The radio service stopped, no response will ever come.
+                None => return Err(RadioError::Disconnected),
             }
         }
     }
@@ -83,15 +90,17 @@ impl SyncRadio {
         self.requests
             .send(RadioRequest::SetParam(token, param, value.into()))
             .await
-            .unwrap();
+            .map_err(|_| RadioError::Disconnected)?;
         loop {
-            if let Some(RadioResponse::SetParam(response_token, _, result)) =
-                self.responses.next().await
-            {
-                if token == response_token {
+            match self.responses.next().await {
+                Some(RadioResponse::SetParam(response_token, _, result))
+                    if token == response_token =>
+                {
                     result?;
                     return Ok(());
                 }
+                Some(_) => (),
+                None => return Err(RadioError::Disconnected),
             }
         }
     }
@@ -100,14 +109,16 @@ impl SyncRadio {
         self.requests
             .send(RadioRequest::SetPower(token, power))
             .await
-            .unwrap();
+            .map_err(|_| RadioError::Disconnected)?;
         loop {
-            if let Some(RadioResponse::SetPower(response_token, _, result)) =
-                self.responses.next().await
-            {
-                if token == response_token {
-                    return result;
+            match self.responses.next().await {
+                Some(RadioResponse::SetPower(response_token, _, result))
+                    if token == response_token =>
+                {
+                    return result
                 }
+                Some(_) => (),
+                None => return Err(RadioError::Disconnected),
             }
         }
     }
@@ -117,12 +128,15 @@ struct MacData {
     pib: PIB,
     radio_requests: BoxSink<'static, RadioRequest, mpsc::SendError>,
     radio_responses: BoxStream<'static, RadioResponse>,
+    // Requests the radio service never got, answered as failed.
+    radio_failures: VecDeque<RadioResponse>,
     mlme_output: BoxSink<'static, mlme::Output, mpsc::SendError>,
     mlme_input: BoxStream<'static, mlme::Input>,
     mcps_output: BoxSink<'static, mcps::Output, mpsc::SendError>,
     mcps_input: BoxStream<'static, mcps::Input>,
     management: ManagementService,
     data: DataService,
+    scheduler: TxScheduler,
 }
 
 struct MacDataPoller<'a>(&'a mut MacData);
@@ -144,6 +158,7 @@ enum MacInput {
     McpsResponse(mcps::Response),
     Management(ManagementServiceAction),
     Data(DataServiceAction),
+    Transmit(CsmaAction),
 }
 
 impl MacData {
@@ -154,14 +169,13 @@ impl MacData {
         mlme_output: BoxSink<'static, mlme::Output, mpsc::SendError>,
         mcps_input: BoxStream<'static, mcps::Input>,
         mcps_output: BoxSink<'static, mcps::Output, mpsc::SendError>,
-    ) -> MacData {
+    ) -> Result<MacData, RadioError> {
         println!("Initializing MAC");
         println!("Getting properties");
         let mut radio = SyncRadio::new(radio_requests, radio_responses);
-        let extended_address =
-            ExtendedAddress(radio.get_param_u64(RadioParam::LongAddress).await.unwrap());
-        let max_tx_power = radio.get_param_u16(RadioParam::TxPowerMax).await.unwrap();
-        let current_channel = radio.get_param_u16(RadioParam::Channel).await.unwrap();
+        let extended_address = ExtendedAddress(radio.get_param_u64(RadioParam::LongAddress).await?);
+        let max_tx_power = radio.get_param_u16(RadioParam::TxPowerMax).await?;
+        let current_channel = radio.get_param_u16(RadioParam::Channel).await?;
         println!("Setting RX Mode");
         radio
             .set_param(
@@ -172,33 +186,41 @@ impl MacData {
                     poll_mode: false,
                 },
             )
-            .await
-            .unwrap();
+            .await?;
         println!("Turning radio on");
-        radio.set_power(true).await.unwrap();
+        radio.set_power(true).await?;
         println!("Initialization of MAC complete");
         let pib = PIB::new(extended_address, current_channel, max_tx_power);
         let management = ManagementService::new(&pib);
         let data = DataService::new();
         let (radio_requests, radio_responses) = radio.destroy();
-        MacData {
+        Ok(MacData {
             pib,
             radio_requests,
             radio_responses,
+            radio_failures: VecDeque::new(),
             mlme_output,
             mlme_input,
             mcps_output,
             mcps_input,
             management,
             data,
-        }
+            scheduler: TxScheduler::new(),
+        })
     }
 
     fn poll_next_input(&mut self, cx: &mut Context<'_>) -> Poll<MacInput> {
-        if let Poll::Ready(x) = self.management.poll_action(cx) {
+        if let Poll::Ready(x) = self.scheduler.poll_action(&mut self.pib, cx) {
+            Poll::Ready(MacInput::Transmit(x))
+        } else if let Poll::Ready(x) =
+            self.management
+                .poll_action(&mut self.pib, &mut self.data, cx)
+        {
             Poll::Ready(MacInput::Management(x))
         } else if let Poll::Ready(x) = self.data.poll_action(&mut self.pib, cx) {
             Poll::Ready(MacInput::Data(x))
+        } else if let Some(x) = self.radio_failures.pop_front() {
+            Poll::Ready(MacInput::Radio(x))
         } else if let Poll::Ready(x) = self.radio_responses.poll_next_unpin(cx) {
             Poll::Ready(MacInput::Radio(x.unwrap()))
         } else if let Poll::Ready(x) = self.mlme_input.poll_next_unpin(cx) {
@@ -221,6 +243,7 @@ impl MacData {
             match MacDataPoller(&mut self).await {
                 MacInput::Management(x) => self.process_management(x).await,
                 MacInput::Data(x) => self.process_data(x).await,
+                MacInput::Transmit(x) => self.process_transmit(x).await,
                 MacInput::MlmeRequest(x) => self.process_mlme_request(x).await,
                 MacInput::MlmeResponse(x) => self.process_mlme_response(x).await,
                 MacInput::Radio(x) => self.process_radio_response(x).await,
@@ -230,56 +253,119 @@ impl MacData {
         }
     }
 
-    async fn send_frame(&mut self, key: UniqueKey, frame: frame::Frame) {
-        let data = frame.pack(VecPackTarget::new()).unwrap().into();
-        self.radio_requests
-            .send(RadioRequest::SendPacket(key, data))
-            .await
-            .unwrap();
+    /**
+     * Hand a request to the radio service. If it is gone, the request is answered as failed,
+     * such that whoever waits for the response learns about it.
+     */
+    async fn radio_request(&mut self, request: RadioRequest) {
+        let failure = request.failed(RadioError::Disconnected);
+        if self.radio_requests.send(request).await.is_err() {
+            println!("Radio service is gone, failing {:?}", failure);
+            self.radio_failures.push_back(failure);
+        }
+    }
+
+    async fn mlme_output(&mut self, output: mlme::Output) {
+        if let Err(e) = self.mlme_output.send(output).await {
+            println!("Unable to pass MLME output on: {:?}", e);
+        }
+    }
+
+    async fn mcps_output(&mut self, output: mcps::Output) {
+        if let Err(e) = self.mcps_output.send(output).await {
+            println!("Unable to pass MCPS output on: {:?}", e);
+        }
+    }
+
+    fn secure_frame(&mut self, key: UniqueKey, frame: frame::Frame) -> Option<Vec<u8>> {
+        match security::secure_frame(&mut self.pib, frame) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                // Keys may have been removed since the frame was queued.
+                println!("Unable to secure frame: {:?}", e);
+                self.data.process_send_result(key, false);
+                None
+            }
+        }
+    }
+
+    /**
+     * Queues a frame for transmission, acks are sent without CSMA-CA.
+     */
+    fn send_frame(&mut self, key: UniqueKey, frame: frame::Frame) {
+        let priority = TxPriority::of(&frame);
+        let destination = frame.destination;
+        if let Some(data) = self.secure_frame(key, frame) {
+            self.scheduler
+                .queue_packet(&mut self.pib, priority, destination, key, data);
+        }
+    }
+
+    async fn process_transmit(&mut self, action: CsmaAction) {
+        match action {
+            CsmaAction::ChannelClear(key) => {
+                self.radio_request(RadioRequest::ChannelClear(key)).await
+            }
+            CsmaAction::SendPacket(key, data) => {
+                self.radio_request(RadioRequest::SendPacket(key, data))
+                    .await
+            }
+            CsmaAction::Failure(key) => {
+                println!("Channel access failure");
+                self.data.process_channel_access_failure(key);
+            }
+        }
     }
 
     async fn process_management(&mut self, action: ManagementServiceAction) {
         match action {
-            ManagementServiceAction::SetParam(k, p, v) => self
-                .radio_requests
-                .send(RadioRequest::SetParam(k, p, v))
-                .await
-                .unwrap(),
-            ManagementServiceAction::SendFrame(f) => self.send_frame(UniqueKey::new(), f).await,
+            ManagementServiceAction::SetParam(k, p, v) => {
+                self.radio_request(RadioRequest::SetParam(k, p, v)).await
+            }
+            ManagementServiceAction::GetParam(k, p, t) => {
+                self.radio_request(RadioRequest::GetParam(k, p, t)).await
+            }
+            ManagementServiceAction::SendFrame(f) => self.send_frame(UniqueKey::new(), f),
+            ManagementServiceAction::Confirm(confirm) => {
+                self.mlme_output(mlme::Output::Confirm(confirm)).await
+            }
         }
     }
 
     async fn process_data(&mut self, action: DataServiceAction) {
         match action {
-            DataServiceAction::InitPendingTable(key) => self
-                .radio_requests
-                .send(RadioRequest::InitPendingDataTable(key))
-                .await
-                .unwrap(),
-            DataServiceAction::SetPendingShort(key, index, value) => self
-                .radio_requests
-                .send(RadioRequest::SetPendingShort(
+            DataServiceAction::InitPendingTable(key) => {
+                self.radio_request(RadioRequest::InitPendingDataTable(key))
+                    .await
+            }
+            DataServiceAction::SetPendingShort(key, index, value) => {
+                self.radio_request(RadioRequest::SetPendingShort(
                     key,
                     index,
                     value.map(|(pan_id, address)| (pan_id.0, address.0)),
                 ))
                 .await
-                .unwrap(),
-            DataServiceAction::SetPendingExtended(key, index, value) => self
-                .radio_requests
-                .send(RadioRequest::SetPendingExtended(
+            }
+            DataServiceAction::SetPendingExtended(key, index, value) => {
+                self.radio_request(RadioRequest::SetPendingExtended(
                     key,
                     index,
                     value.map(|x| x.0),
                 ))
                 .await
-                .unwrap(),
-            DataServiceAction::SendFrame(key, frame) => self.send_frame(key, frame).await,
-            DataServiceAction::Confirm(confirm) => self
-                .mcps_output
-                .send(mcps::Output::Confirm(confirm))
-                .await
-                .unwrap(),
+            }
+            DataServiceAction::SendFrame(key, frame) => self.send_frame(key, *frame),
+            DataServiceAction::Confirm(confirm) => {
+                self.mcps_output(mcps::Output::Confirm(confirm)).await
+            }
+            DataServiceAction::CommandResult(key, result) => {
+                if let Some(output) =
+                    self.management
+                        .process_command_result(&mut self.pib, key, result)
+                {
+                    self.mlme_output(output).await;
+                }
+            }
         }
     }
 
@@ -288,19 +374,13 @@ impl MacData {
             self.management
                 .process_mlme_request(&mut self.pib, &mut self.data, request)
         {
-            self.mlme_output
-                .send(mlme::Output::Confirm(confirm))
-                .await
-                .unwrap();
+            self.mlme_output(mlme::Output::Confirm(confirm)).await;
         }
     }
 
     async fn process_mcps_request(&mut self, request: mcps::Request) {
         if let Some(confirm) = self.data.process_mcps_request(&self.pib, request) {
-            self.mcps_output
-                .send(mcps::Output::Confirm(confirm))
-                .await
-                .unwrap();
+            self.mcps_output(mcps::Output::Confirm(confirm)).await;
         }
     }
 
@@ -327,24 +407,89 @@ impl MacData {
             RadioResponse::SetParam(k, _, r) => {
                 self.management.process_set_param_result(k, r.is_ok());
             }
+            RadioResponse::GetParam(k, _, r) => {
+                self.management.process_get_param_result(k, r);
+            }
             RadioResponse::OnPacket(packet) => self.process_radio_packet(packet).await,
             RadioResponse::SendPacket(k, r) => self.process_radio_send_result(k, r),
-            r => println!("Unhandled radio response: {:?}", r),
+            RadioResponse::ChannelClear(k, r) => {
+                self.scheduler.process_channel_clear_result(&self.pib, k, r);
+            }
+            RadioResponse::SetPower(_, _, r) => {
+                if let Err(e) = r {
+                    println!("Unable to turn on radio: {:?}", e);
+                }
+            }
+            RadioResponse::Reconnected => self.process_radio_reconnected().await,
         }
     }
 
+    /**
+     * The radio was reset, replay all state the MAC pushed to it.
+     */
+    async fn process_radio_reconnected(&mut self) {
+        self.management.process_radio_reconnected();
+        self.data.process_radio_reconnected();
+        self.radio_request(RadioRequest::SetPower(UniqueKey::new(), true))
+            .await;
+    }
+
     async fn process_radio_packet(&mut self, packet: RadioPacket) {
-        let (frame, _rest) = frame::Frame::unpack(&packet.data).unwrap();
-        if let Some(indication) = self.management.process_frame(&mut self.pib, &frame) {
-            self.mlme_output
-                .send(mlme::Output::Indication(indication))
-                .await
-                .unwrap();
+        // Other networks share the channel, anything that can not be parsed is dropped rather
+        // than taking down the MAC.
+        let secured = matches!(
+            frame::FrameControl::unpack(&packet.data),
+            Ok((fc, _)) if fc.security_enabled() != 0
+        );
+        let unsecured;
+        let data = if secured {
+            unsecured = match security::unsecure_frame(&mut self.pib, &packet.data) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.pib.mac_security_failure = self.pib.mac_security_failure.wrapping_add(1);
+                    println!("Dropping frame ({:?}): {:02X?}", e, packet.data);
+                    return;
+                }
+            };
+            &unsecured
+        } else {
+            &packet.data
+        };
+        let frame = match frame::Frame::unpack(data) {
+            Ok((frame, _rest)) => frame,
+            Err(e) => {
+                self.pib.mac_rx_drop_count = self.pib.mac_rx_drop_count.wrapping_add(1);
+                println!("Dropping frame ({:?}): {:02X?}", e, packet.data);
+                return;
+            }
+        };
+        self.pib.mac_rx_success_count = self.pib.mac_rx_success_count.wrapping_add(1);
+        // The Enh-Ack goes out first, the sender is waiting for it.
+        if let Some(ack) = self.data.create_enhanced_ack(&self.pib, &frame) {
+            self.send_frame(UniqueKey::new(), ack);
+        }
+        if self.data.is_duplicate(&frame) {
+            self.pib.mac_duplicate_frame_count = self.pib.mac_duplicate_frame_count.wrapping_add(1);
+            println!("Dropping duplicate frame: {:02X?}", packet.data);
+            return;
+        }
+        let scanning = self.management.is_scanning();
+        if let Some(indication) =
+            self.management
+                .process_frame(&mut self.pib, &self.data, &frame, &packet)
+        {
+            self.mlme_output(mlme::Output::Indication(indication)).await;
+        }
+        if scanning {
+            return;
+        }
+        if let Some(indication) = self.data.process_frame(&self.pib, &frame, &packet) {
+            self.mcps_output(mcps::Output::Indication(indication)).await;
         }
-        self.data.process_frame(&self.pib, &frame);
     }
 
     fn process_radio_send_result(&mut self, key: UniqueKey, result: Result<(), RadioError>) {
+        self.scheduler.process_send_result(key);
         self.data.process_send_result(key, result.is_ok())
     }
 }
@@ -358,7 +503,7 @@ pub async fn start(
     mcps_output: BoxSink<'static, mcps::Output, mpsc::SendError>,
 ) {
     let radio_responses = radio_responses;
-    let data = MacData::new(
+    match MacData::new(
         radio_requests,
         radio_responses,
         mlme_input,
@@ -366,6 +511,9 @@ pub async fn start(
         mcps_input,
         mcps_output,
     )
-    .await;
-    data.process().await
+    .await
+    {
+        Ok(data) => data.process().await,
+        Err(e) => println!("Unable to initialize MAC: {:?}", e),
+    }
 }
@@ -0,0 +1,2346 @@
+use crate::async_std_executor::AsyncStdExecutor;
+use crate::ieee802154::frame::{
+    Address, AddressingMode, AssociationError, AssociationResponse, CapabilityInformation, Command,
+    CoordinatorRealignment, DeviceType, DisassociationReason, Frame, FrameType, FrameVersion,
+    FullAddress, HeaderIE, KeyIdentifier, Payload, PowerSource, SecurityLevel,
+};
+use crate::ieee802154::mac::csma::CsmaAction;
+use crate::ieee802154::mac::data::DataService;
+use crate::ieee802154::mac::service::start;
+use crate::ieee802154::mac::txscheduler::{TxPriority, TxScheduler};
+use crate::ieee802154::pib::{DeviceDescriptor, KeyDescriptor, PIBProperty, PIBValue, PIB};
+use crate::ieee802154::services::{mcps, mlme};
+use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use crate::pack::{Pack, VecPackTarget};
+use crate::radio::emulator::start_emulator;
+use crate::radio::medium::{LinkConfig, VirtualMedium};
+use crate::radio::simulated::{
+    start_simulated_radio, SimulatedRadioConfig, SimulatedRadioWatch, SimulatedReception,
+};
+use crate::radio::{
+    start_radio_reconnecting, RadioError, RadioPacket, RadioParam, RadioRequest, RadioResponse,
+};
+use crate::unique_key::UniqueKey;
+use futures::channel::mpsc;
+use futures::future::FutureExt;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{BoxStream, Stream, StreamExt};
+use futures::task::SpawnExt;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::time::Duration;
+
+const COORDINATOR_ADDRESS: u64 = 0x00124B0000000001;
+const DEVICE_ADDRESS: u64 = 0x00124B0000000002;
+
+struct SimulatedMac {
+    mlme_input: mpsc::UnboundedSender<mlme::Input>,
+    mlme_output: mpsc::UnboundedReceiver<mlme::Output>,
+    mcps_input: mpsc::UnboundedSender<mcps::Input>,
+    mcps_output: mpsc::UnboundedReceiver<mcps::Output>,
+}
+
+fn start_mac<
+    RQ: Sink<RadioRequest, Error = mpsc::SendError> + Send + 'static,
+    RS: Stream<Item = RadioResponse> + Send + 'static,
+>(
+    executor: &AsyncStdExecutor,
+    radio_requests: RQ,
+    radio_responses: RS,
+) -> SimulatedMac {
+    let (mlme_input_in, mlme_input_out) = mpsc::unbounded();
+    let (mlme_output_in, mlme_output_out) = mpsc::unbounded();
+    let (mcps_input_in, mcps_input_out) = mpsc::unbounded();
+    let (mcps_output_in, mcps_output_out) = mpsc::unbounded();
+    executor
+        .spawn(start(
+            Box::pin(radio_requests),
+            Box::pin(radio_responses),
+            Box::pin(mlme_input_out),
+            Box::pin(mlme_output_in),
+            Box::pin(mcps_input_out),
+            Box::pin(mcps_output_in),
+        ))
+        .unwrap();
+    SimulatedMac {
+        mlme_input: mlme_input_in,
+        mlme_output: mlme_output_out,
+        mcps_input: mcps_input_in,
+        mcps_output: mcps_output_out,
+    }
+}
+
+impl SimulatedMac {
+    async fn request(&mut self, request: mlme::Request) -> mlme::Confirm {
+        self.mlme_input
+            .send(mlme::Input::Request(request))
+            .await
+            .unwrap();
+        loop {
+            if let mlme::Output::Confirm(confirm) = self.mlme_output.next().await.unwrap() {
+                return confirm;
+            }
+        }
+    }
+
+    async fn get(&mut self, attribute: PIBProperty) -> PIBValue {
+        match self
+            .request(mlme::Request::Get(mlme::GetRequest { attribute }))
+            .await
+        {
+            mlme::Confirm::Get(_, Ok(value)) => value,
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }
+
+    async fn set(&mut self, attribute: PIBProperty, value: PIBValue) {
+        match self
+            .request(mlme::Request::Set(mlme::SetRequest { attribute, value }))
+            .await
+        {
+            mlme::Confirm::Set(_, Ok(())) => (),
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }
+
+    /**
+     * Enables security with a single key, shared with a single other device.
+     */
+    async fn enable_security(&mut self, key: [u8; 16], other: DeviceDescriptor) {
+        self.set(PIBProperty::MacSecurityEnabled, true.into()).await;
+        self.set(
+            PIBProperty::MacKeyTable,
+            vec![KeyDescriptor {
+                key_identifier: KeyIdentifier::Index(1),
+                key,
+            }]
+            .into(),
+        )
+        .await;
+        self.set(PIBProperty::MacDeviceTable, vec![other].into())
+            .await;
+    }
+
+    async fn start_coordinator(&mut self, pan_id: PANID, channel: u16) {
+        let requests = vec![
+            mlme::Request::Reset(mlme::ResetRequest {
+                set_default_pib: true,
+            }),
+            mlme::Request::Set(mlme::SetRequest {
+                attribute: PIBProperty::MacShortAddress,
+                value: ShortAddress(0x0000).into(),
+            }),
+            mlme::Request::Set(mlme::SetRequest {
+                attribute: PIBProperty::MacBeaconAutoRespond,
+                value: true.into(),
+            }),
+            mlme::Request::Set(mlme::SetRequest {
+                attribute: PIBProperty::MacAssociationPermit,
+                value: true.into(),
+            }),
+            mlme::Request::Start(mlme::StartRequest {
+                pan_id,
+                channel_number: channel,
+                channel_page: 0,
+                start_time: 0,
+                beacon_order: 15,
+                superframe_order: 15,
+                pan_coordinator: true,
+                battery_life_extension: false,
+                coord_realignment: false,
+            }),
+        ];
+        for request in requests {
+            match self.request(request).await {
+                mlme::Confirm::Reset(Ok(()))
+                | mlme::Confirm::Set(_, Ok(()))
+                | mlme::Confirm::Start(Ok(())) => (),
+                confirm => panic!("Unexpected confirm: {:?}", confirm),
+            }
+        }
+    }
+}
+
+/**
+ * A coordinator and a device, each a MAC on its own simulated radio, sharing a medium.
+ */
+struct TwoNodeNetwork {
+    coordinator: SimulatedMac,
+    coordinator_radio: SimulatedRadioWatch,
+    device: SimulatedMac,
+}
+
+fn two_node_network(executor: &AsyncStdExecutor) -> TwoNodeNetwork {
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let start_node = |extended_address| {
+        let (radio_requests, radio_responses, node) = medium.start_node(
+            executor,
+            SimulatedRadioConfig {
+                extended_address,
+                ..Default::default()
+            },
+        );
+        (
+            start_mac(executor, radio_requests, radio_responses),
+            medium.watch(node),
+        )
+    };
+    let (coordinator, coordinator_radio) = start_node(COORDINATOR_ADDRESS);
+    let (device, _) = start_node(DEVICE_ADDRESS);
+    TwoNodeNetwork {
+        coordinator,
+        coordinator_radio,
+        device,
+    }
+}
+
+/**
+ * An end device driven directly through the radio interface, without a MAC,
+ * such that tests are in full control of what goes over the air.
+ */
+struct ScriptedDevice {
+    requests: Pin<Box<dyn Sink<RadioRequest, Error = mpsc::SendError> + Send>>,
+    responses: BoxStream<'static, RadioResponse>,
+    received: VecDeque<RadioPacket>,
+}
+
+impl ScriptedDevice {
+    async fn new<
+        RQ: Sink<RadioRequest, Error = mpsc::SendError> + Send + 'static,
+        RS: Stream<Item = RadioResponse> + Send + 'static,
+    >(
+        requests: RQ,
+        responses: RS,
+        pan_id: PANID,
+        short_address: ShortAddress,
+    ) -> Self {
+        let mut device = Self {
+            requests: Box::pin(requests),
+            responses: responses.boxed(),
+            received: VecDeque::new(),
+        };
+        device
+            .request(RadioRequest::SetParam(
+                UniqueKey::new(),
+                RadioParam::PanId,
+                pan_id.0.into(),
+            ))
+            .await;
+        device
+            .request(RadioRequest::SetParam(
+                UniqueKey::new(),
+                RadioParam::ShortAddress,
+                short_address.0.into(),
+            ))
+            .await;
+        device
+            .request(RadioRequest::SetPower(UniqueKey::new(), true))
+            .await;
+        device
+    }
+
+    async fn request(&mut self, request: RadioRequest) -> RadioResponse {
+        self.requests.send(request).await.unwrap();
+        loop {
+            match self.responses.next().await.unwrap() {
+                RadioResponse::OnPacket(packet) => self.received.push_back(packet),
+                response => return response,
+            }
+        }
+    }
+
+    async fn send(&mut self, data: Vec<u8>) {
+        match self
+            .request(RadioRequest::SendPacket(UniqueKey::new(), data))
+            .await
+        {
+            RadioResponse::SendPacket(_, Ok(())) => (),
+            response => panic!("Unable to send: {:?}", response),
+        }
+    }
+
+    async fn send_frame(&mut self, frame: &Frame) {
+        self.send(frame.pack(VecPackTarget::new()).unwrap().into())
+            .await
+    }
+
+    async fn receive(&mut self) -> Frame {
+        let packet = if let Some(packet) = self.received.pop_front() {
+            packet
+        } else {
+            loop {
+                if let RadioResponse::OnPacket(packet) = self.responses.next().await.unwrap() {
+                    break packet;
+                }
+            }
+        };
+        Frame::unpack(&packet.data).unwrap().0
+    }
+}
+
+/**
+ * Waits until the MAC has passed its addresses on to the radio, which only happens after the
+ * requests setting them are confirmed.
+ */
+async fn wait_for_addresses(
+    radio: &SimulatedRadioWatch,
+    pan_id: PANID,
+    short_address: ShortAddress,
+) {
+    radio
+        .wait_until(|radio| {
+            radio.value(RadioParam::PanId) == pan_id.0
+                && radio.value(RadioParam::ShortAddress) == short_address.0
+        })
+        .await;
+}
+
+#[test]
+fn test_beacon_auto_respond() {
+    let executor = AsyncStdExecutor::new();
+    let (radio_requests, radio_responses, mut air) =
+        start_simulated_radio(executor.clone(), SimulatedRadioConfig::default());
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        let beacon_request = Frame {
+            version: FrameVersion::Ieee2003,
+            frame_pending: false,
+            acknowledge_request: false,
+            sequence_number: Some(1),
+            destination: Some(FullAddress {
+                pan_id: PANID::broadcast(),
+                address: Address::Short(ShortAddress::broadcast()),
+            }),
+            source: None,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: FrameType::Command(Command::BeaconRequest()),
+        };
+        air.receptions
+            .unbounded_send(SimulatedReception {
+                channel: 26,
+                packet: RadioPacket {
+                    data: beacon_request.pack(VecPackTarget::new()).unwrap().into(),
+                    rssi: 0xC0,
+                    link_quality: 200,
+                },
+            })
+            .unwrap();
+        loop {
+            let transmission = air.transmissions.next().await.unwrap();
+            let (frame, _) = Frame::unpack(&transmission.data).unwrap();
+            if let FrameType::Beacon(beacon) = frame.frame_type {
+                assert_eq!(transmission.channel, 26);
+                assert!(beacon.pan_coordinator);
+                assert_eq!(
+                    frame.source,
+                    Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x0000)),
+                    })
+                );
+                break;
+            }
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_indirect_association() {
+    let executor = AsyncStdExecutor::new();
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let (radio_requests, radio_responses, coordinator_node) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: COORDINATOR_ADDRESS,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    let (radio_requests, radio_responses, _) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: DEVICE_ADDRESS,
+            ..Default::default()
+        },
+    );
+    let coordinator_radio = medium.watch(coordinator_node);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        wait_for_addresses(&coordinator_radio, PANID(0x1234), ShortAddress(0x0000)).await;
+        let mut device = ScriptedDevice::new(
+            radio_requests,
+            radio_responses,
+            PANID(0x1234),
+            ShortAddress::invalid(),
+        )
+        .await;
+
+        // Association request, with capability information "allocate address"
+        let mut association_request = vec![0x23, 0xC8, 0x01, 0x34, 0x12, 0x00, 0x00, 0xFF, 0xFF];
+        association_request.extend_from_slice(&DEVICE_ADDRESS.to_le_bytes());
+        association_request.extend_from_slice(&[0x01, 0x80]);
+        device.send(association_request).await;
+        let ack = device.receive().await;
+        assert_eq!(ack.frame_type, FrameType::Ack(Payload(vec![])));
+        assert_eq!(ack.sequence_number, Some(0x01));
+
+        let device_address = loop {
+            if let mlme::Output::Indication(mlme::Indication::Associate {
+                device_address, ..
+            }) = mac.mlme_output.next().await.unwrap()
+            {
+                break device_address;
+            }
+        };
+        assert_eq!(device_address, ExtendedAddress(DEVICE_ADDRESS));
+        mac.mlme_input
+            .send(mlme::Input::Response(mlme::Response::Associate {
+                device_address,
+                fast_association: false,
+                status: Ok(Some(ShortAddress(0x4567))),
+            }))
+            .await
+            .unwrap();
+        coordinator_radio
+            .wait_until(|radio| {
+                radio.is_pending(&Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+                }))
+            })
+            .await;
+
+        device
+            .send_frame(&Frame {
+                version: FrameVersion::Ieee2003,
+                frame_pending: false,
+                acknowledge_request: true,
+                sequence_number: Some(0x02),
+                destination: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x0000)),
+                }),
+                source: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+                }),
+                security: None,
+                header_ies: vec![],
+                payload_ies: vec![],
+                frame_type: FrameType::Command(Command::DataRequest()),
+            })
+            .await;
+        let ack = device.receive().await;
+        assert_eq!(ack.sequence_number, Some(0x02));
+        assert!(ack.frame_pending);
+
+        let response = device.receive().await;
+        assert_eq!(
+            response.frame_type,
+            FrameType::Command(Command::AssociationResponse(AssociationResponse {
+                fast_association: false,
+                status: Ok(ShortAddress(0x4567)),
+            }))
+        );
+        assert_eq!(
+            response.destination,
+            Some(FullAddress {
+                pan_id: PANID(0x1234),
+                address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+            })
+        );
+
+        let (destination, status) = loop {
+            if let mlme::Output::Indication(mlme::Indication::CommStatus {
+                destination,
+                status,
+                ..
+            }) = mac.mlme_output.next().await.unwrap()
+            {
+                break (destination, status);
+            }
+        };
+        assert_eq!(
+            destination,
+            FullAddress {
+                pan_id: PANID(0x1234),
+                address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+            }
+        );
+        assert!(status.is_ok());
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_comm_status_expired() {
+    let executor = AsyncStdExecutor::new();
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let (radio_requests, radio_responses, _) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: COORDINATOR_ADDRESS,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        match mac
+            .request(mlme::Request::Set(mlme::SetRequest {
+                attribute: PIBProperty::MacTransactionPersistenceTime,
+                value: Duration::from_millis(100).into(),
+            }))
+            .await
+        {
+            mlme::Confirm::Set(_, Ok(())) => (),
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+
+        // Nobody ever polls for this response.
+        mac.mlme_input
+            .send(mlme::Input::Response(mlme::Response::Associate {
+                device_address: ExtendedAddress(DEVICE_ADDRESS),
+                fast_association: false,
+                status: Ok(Some(ShortAddress(0x4567))),
+            }))
+            .await
+            .unwrap();
+        match mac.mlme_output.next().await.unwrap() {
+            mlme::Output::Indication(mlme::Indication::CommStatus {
+                source,
+                destination,
+                status,
+            }) => {
+                assert_eq!(
+                    source,
+                    FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Extended(ExtendedAddress(COORDINATOR_ADDRESS)),
+                    }
+                );
+                assert_eq!(
+                    destination.address,
+                    Address::Extended(ExtendedAddress(DEVICE_ADDRESS))
+                );
+                assert!(matches!(status, Err(mlme::Error::TransactionExpired)));
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_expired_association_forgotten() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        coordinator_radio,
+        mut device,
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        coordinator.start_coordinator(PANID(0x1234), 15).await;
+        match coordinator
+            .request(mlme::Request::Set(mlme::SetRequest {
+                attribute: PIBProperty::MacTransactionPersistenceTime,
+                value: Duration::from_millis(100).into(),
+            }))
+            .await
+        {
+            mlme::Confirm::Set(_, Ok(())) => (),
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+        coordinator
+            .mlme_input
+            .send(mlme::Input::Response(mlme::Response::Associate {
+                device_address: ExtendedAddress(DEVICE_ADDRESS),
+                fast_association: false,
+                status: Ok(Some(ShortAddress(0x4567))),
+            }))
+            .await
+            .unwrap();
+        assert!(matches!(
+            coordinator.mlme_output.next().await.unwrap(),
+            mlme::Output::Indication(mlme::Indication::CommStatus {
+                status: Err(mlme::Error::TransactionExpired),
+                ..
+            })
+        ));
+
+        // The device never got its short address, so data for it is not for the device.
+        coordinator
+            .mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle: mcps::MsduHandle::new(),
+                    ack_tx: true,
+                    indirect_tx: true,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        coordinator_radio
+            .wait_until(|radio| {
+                radio.is_pending(&Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x4567)),
+                }))
+            })
+            .await;
+        match device
+            .request(mlme::Request::Scan(mlme::ScanRequest {
+                scan_type: mlme::ScanType::Active,
+                scan_channels: vec![15],
+                scan_duration: 2,
+                channel_page: 0,
+            }))
+            .await
+        {
+            mlme::Confirm::Scan(confirm) => {
+                let beacon = &confirm.pan_descriptor_list[0].beacon;
+                assert_eq!(beacon.pending_short_addresses, vec![ShortAddress(0x4567)]);
+                assert_eq!(beacon.pending_extended_addresses, vec![]);
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_retries_without_ack() {
+    let executor = AsyncStdExecutor::new();
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let mut monitor = medium.monitor();
+    let (radio_requests, radio_responses, coordinator) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: COORDINATOR_ADDRESS,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    let (radio_requests, radio_responses, device) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: DEVICE_ADDRESS,
+            ..Default::default()
+        },
+    );
+    // The device hears everything, but its acks never make it back.
+    medium.set_link(
+        device,
+        coordinator,
+        Some(LinkConfig {
+            loss: 1.0,
+            ..Default::default()
+        }),
+    );
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        let _device = ScriptedDevice::new(
+            radio_requests,
+            radio_responses,
+            PANID(0x1234),
+            ShortAddress(0x4567),
+        )
+        .await;
+        let msdu_handle = mcps::MsduHandle::new();
+        mac.mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle,
+                    ack_tx: true,
+                    indirect_tx: false,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        loop {
+            if let mcps::Output::Confirm(mcps::Confirm::Data(confirm)) =
+                mac.mcps_output.next().await.unwrap()
+            {
+                assert_eq!(confirm.msdu_handle, msdu_handle);
+                assert!(matches!(confirm.ack_payload, Err(mcps::Error::NoAck)));
+                break;
+            }
+        }
+        let mut data_frames = 0;
+        let mut acks = 0;
+        while let Ok((node, transmission)) = monitor.try_recv() {
+            match Frame::unpack(&transmission.data).unwrap().0.frame_type {
+                FrameType::Data(_) if node == coordinator => data_frames += 1,
+                FrameType::Ack(_) if node == device => acks += 1,
+                _ => (),
+            }
+        }
+        assert_eq!(data_frames, 4);
+        assert_eq!(acks, 4);
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_data_indication() {
+    let mut pib = PIB::new(ExtendedAddress(0x0123456789ABCDEF), 26, 0);
+    pib.mac_pan_id = PANID(0x1234);
+    pib.mac_short_address = ShortAddress(0x0000);
+    let mut data = DataService::new();
+    let source = FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Short(ShortAddress(0x4567)),
+    };
+    // Only the frames to us and to broadcast are delivered.
+    for (sequence_number, destination, delivered) in [
+        (0x10, ShortAddress(0x1111), false),
+        (0x11, ShortAddress(0x0000), true),
+        (0x12, ShortAddress::broadcast(), true),
+    ] {
+        let destination = Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(destination),
+        });
+        let frame = Frame {
+            version: FrameVersion::Ieee2003,
+            frame_pending: false,
+            acknowledge_request: false,
+            sequence_number: Some(sequence_number),
+            destination,
+            source: Some(source),
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: FrameType::Data(Payload(vec![sequence_number, 0xAA])),
+        };
+        let packet = RadioPacket {
+            data: vec![],
+            rssi: 0xC0,
+            link_quality: 0x60,
+        };
+        match data.process_frame(&pib, &frame, &packet) {
+            Some(mcps::Indication::Data(indication)) => {
+                assert!(delivered);
+                assert_eq!(indication.source, Some(source));
+                assert_eq!(indication.destination, destination);
+                assert_eq!(indication.msdu, vec![sequence_number, 0xAA]);
+                assert_eq!(indication.dsn, Some(sequence_number));
+                assert_eq!(indication.rssi, 0xC0);
+                assert_eq!(indication.mpdu_link_quality, 0x60);
+            }
+            None => assert!(!delivered),
+        }
+    }
+}
+
+#[test]
+fn test_radio_gone_at_startup() {
+    let (radio_requests, _) = mpsc::unbounded::<RadioRequest>();
+    let (_mlme_input_in, mlme_input_out) = mpsc::unbounded();
+    let (mlme_output_in, _mlme_output_out) = mpsc::unbounded();
+    let (_mcps_input_in, mcps_input_out) = mpsc::unbounded();
+    let (mcps_output_in, _mcps_output_out) = mpsc::unbounded();
+    // Without a radio the MAC gives up instead of waiting or panicking.
+    async_std::task::block_on(async_std::future::timeout(
+        Duration::from_secs(10),
+        start(
+            Box::pin(radio_requests),
+            Box::pin(futures::stream::empty()),
+            Box::pin(mlme_input_out),
+            Box::pin(mlme_output_in),
+            Box::pin(mcps_input_out),
+            Box::pin(mcps_output_in),
+        ),
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_channel_access_failure() {
+    let executor = AsyncStdExecutor::new();
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let mut monitor = medium.monitor();
+    // Someone else is always using the channel.
+    let mut channel_noise = HashMap::new();
+    channel_noise.insert(26, -60);
+    let (radio_requests, radio_responses, _coordinator) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: COORDINATOR_ADDRESS,
+            channel_noise,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        let msdu_handle = mcps::MsduHandle::new();
+        mac.mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle,
+                    ack_tx: true,
+                    indirect_tx: false,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        loop {
+            if let mcps::Output::Confirm(mcps::Confirm::Data(confirm)) =
+                mac.mcps_output.next().await.unwrap()
+            {
+                assert_eq!(confirm.msdu_handle, msdu_handle);
+                assert!(matches!(
+                    confirm.ack_payload,
+                    Err(mcps::Error::ChannelAccessFailure)
+                ));
+                break;
+            }
+        }
+        // Nothing was put on the air.
+        assert!(monitor.try_recv().is_err());
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_radio_send_failure() {
+    let executor = AsyncStdExecutor::new();
+    let (radio_requests, radio_responses, _air) =
+        start_simulated_radio(executor.clone(), SimulatedRadioConfig::default());
+    // The radio fails every frame, while CSMA-CA finds the channel clear.
+    let (requests_in, requests_out) = mpsc::unbounded();
+    let (failures_in, failures_out) = mpsc::unbounded();
+    executor
+        .spawn(
+            requests_out
+                .filter_map(move |request| {
+                    futures::future::ready(match request {
+                        RadioRequest::SendPacket(..) => {
+                            failures_in
+                                .unbounded_send(request.failed(RadioError::Timeout))
+                                .unwrap();
+                            None
+                        }
+                        request => Some(Ok(request)),
+                    })
+                })
+                .forward(radio_requests)
+                .map(|_| ()),
+        )
+        .unwrap();
+    let mut mac = start_mac(
+        &executor,
+        requests_in,
+        futures::stream::select(radio_responses, failures_out),
+    );
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        let msdu_handle = mcps::MsduHandle::new();
+        mac.mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle,
+                    ack_tx: true,
+                    indirect_tx: false,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        loop {
+            if let mcps::Output::Confirm(mcps::Confirm::Data(confirm)) =
+                mac.mcps_output.next().await.unwrap()
+            {
+                assert_eq!(confirm.msdu_handle, msdu_handle);
+                assert!(matches!(
+                    confirm.ack_payload,
+                    Err(mcps::Error::RadioFailure)
+                ));
+                break;
+            }
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_replay_after_reconnect() {
+    let executor = AsyncStdExecutor::new();
+    let (dongles_in, mut dongles) = mpsc::unbounded();
+    let (radio_requests, radio_responses, _) =
+        start_radio_reconnecting(executor.clone(), move || {
+            let (port, dongle) = async_std::os::unix::net::UnixStream::pair()?;
+            dongles_in.unbounded_send(dongle).unwrap();
+            Ok((port.clone(), port))
+        });
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let dongle = dongles.next().await.unwrap();
+        let air = start_emulator(
+            executor.clone(),
+            dongle.clone(),
+            dongle.clone(),
+            SimulatedRadioConfig::default(),
+        );
+        mac.start_coordinator(PANID(0x1234), 15).await;
+        let destination = FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x4567)),
+        };
+        mac.mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(destination),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle: mcps::MsduHandle::new(),
+                    ack_tx: true,
+                    indirect_tx: true,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        air.watch
+            .wait_until(|radio| radio.is_pending(&Some(destination)))
+            .await;
+
+        // Dongle resets: the connection drops, and it comes back with all state lost.
+        dongle.shutdown(std::net::Shutdown::Both).unwrap();
+        let dongle = dongles.next().await.unwrap();
+        let mut air = start_emulator(
+            executor.clone(),
+            dongle.clone(),
+            dongle,
+            SimulatedRadioConfig::default(),
+        );
+        // Wait for the MAC to notice, and to bring the new radio up to date. Requests sent
+        // before the reset may still reach it, so only entries set after initializing the
+        // pending table count.
+        air.watch
+            .wait_until(|radio| {
+                radio.is_on()
+                    && radio.channel() == 15
+                    && radio.value(RadioParam::PanId) == 0x1234
+                    && radio.value(RadioParam::ShortAddress) == 0x0000
+                    && radio.is_pending_table_initialized()
+                    && radio.is_pending(&Some(destination))
+            })
+            .await;
+
+        let data_request = Frame {
+            version: FrameVersion::Ieee2003,
+            frame_pending: false,
+            acknowledge_request: true,
+            sequence_number: Some(0x10),
+            destination: Some(FullAddress {
+                pan_id: PANID(0x1234),
+                address: Address::Short(ShortAddress(0x0000)),
+            }),
+            source: Some(destination),
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: FrameType::Command(Command::DataRequest()),
+        };
+        air.receptions
+            .unbounded_send(SimulatedReception {
+                channel: 15,
+                packet: RadioPacket {
+                    data: data_request.pack(VecPackTarget::new()).unwrap().into(),
+                    rssi: 0xC0,
+                    link_quality: 200,
+                },
+            })
+            .unwrap();
+        let ack = air.transmissions.next().await.unwrap();
+        assert_eq!(ack.channel, 15);
+        let (ack, _) = Frame::unpack(&ack.data).unwrap();
+        assert_eq!(ack.frame_type, FrameType::Ack(Payload(vec![])));
+        assert!(ack.frame_pending);
+        let data = air.transmissions.next().await.unwrap();
+        let (data, _) = Frame::unpack(&data.data).unwrap();
+        assert_eq!(data.frame_type, FrameType::Data(Payload(vec![1, 2, 3])));
+        assert_eq!(data.destination, Some(destination));
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_drop_duplicate_frames() {
+    let executor = AsyncStdExecutor::new();
+    let (radio_requests, radio_responses, air) =
+        start_simulated_radio(executor.clone(), SimulatedRadioConfig::default());
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        wait_for_addresses(&air.watch, PANID(0x1234), ShortAddress(0x0000)).await;
+        let data = |sequence_number, source| {
+            let frame = Frame {
+                version: FrameVersion::Ieee2003,
+                frame_pending: false,
+                acknowledge_request: true,
+                sequence_number: Some(sequence_number),
+                destination: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x0000)),
+                }),
+                source: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(source),
+                }),
+                security: None,
+                header_ies: vec![],
+                payload_ies: vec![],
+                frame_type: FrameType::Data(Payload(vec![sequence_number])),
+            };
+            SimulatedReception {
+                channel: 26,
+                packet: RadioPacket {
+                    data: frame.pack(VecPackTarget::new()).unwrap().into(),
+                    rssi: 0xC0,
+                    link_quality: 200,
+                },
+            }
+        };
+        // Retransmissions are dropped, also when another frame came in between, but the same
+        // number from another device is not.
+        for (sequence_number, source) in [
+            (1, 0x0001),
+            (1, 0x0001),
+            (1, 0x0002),
+            (2, 0x0001),
+            (1, 0x0001),
+            (3, 0x0001),
+        ] {
+            air.receptions
+                .unbounded_send(data(sequence_number, ShortAddress(source)))
+                .unwrap();
+        }
+        for (sequence_number, source) in [(1, 0x0001), (1, 0x0002), (2, 0x0001), (3, 0x0001)] {
+            match mac.mcps_output.next().await.unwrap() {
+                mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                    assert_eq!(indication.dsn, Some(sequence_number));
+                    assert_eq!(
+                        indication.source.map(|source| source.address),
+                        Some(Address::Short(ShortAddress(source)))
+                    );
+                }
+                output => panic!("Unexpected output: {:?}", output),
+            }
+        }
+        assert_eq!(
+            mac.get(PIBProperty::MacDuplicateFrameCount).await,
+            PIBValue::U32(2)
+        );
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_drop_unsupported_frames() {
+    let executor = AsyncStdExecutor::new();
+    let (radio_requests, radio_responses, mut air) =
+        start_simulated_radio(executor.clone(), SimulatedRadioConfig::default());
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        mac.start_coordinator(PANID(0x1234), 26).await;
+        let beacon_request: Vec<u8> = Frame {
+            version: FrameVersion::Ieee2003,
+            frame_pending: false,
+            acknowledge_request: false,
+            sequence_number: Some(1),
+            destination: Some(FullAddress {
+                pan_id: PANID::broadcast(),
+                address: Address::Short(ShortAddress::broadcast()),
+            }),
+            source: None,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: FrameType::Command(Command::BeaconRequest()),
+        }
+        .pack(VecPackTarget::new())
+        .unwrap()
+        .into();
+        let mut secured = beacon_request.clone();
+        secured[0] |= 0x08;
+        let mut information_elements = beacon_request.clone();
+        information_elements[1] |= 0x02;
+        let truncated = beacon_request[..beacon_request.len() - 1].to_vec();
+        for data in [secured, information_elements, truncated, beacon_request] {
+            air.receptions
+                .unbounded_send(SimulatedReception {
+                    channel: 26,
+                    packet: RadioPacket {
+                        data,
+                        rssi: 0xC0,
+                        link_quality: 200,
+                    },
+                })
+                .unwrap();
+        }
+        // Only the valid beacon request gets an answer, the MAC survived the others.
+        loop {
+            let transmission = air.transmissions.next().await.unwrap();
+            let (frame, _) = Frame::unpack(&transmission.data).unwrap();
+            if let FrameType::Beacon(_) = frame.frame_type {
+                break;
+            }
+        }
+        for (attribute, expected) in [
+            (PIBProperty::MacSecurityFailure, 1),
+            (PIBProperty::MacRxDropCount, 2),
+            (PIBProperty::MacRxSuccessCount, 1),
+        ] {
+            match mac
+                .request(mlme::Request::Get(mlme::GetRequest { attribute }))
+                .await
+            {
+                mlme::Confirm::Get(_, Ok(PIBValue::U32(count))) => assert_eq!(count, expected),
+                confirm => panic!("Unexpected confirm: {:?}", confirm),
+            }
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_energy_detect_scan() {
+    let executor = AsyncStdExecutor::new();
+    let mut channel_noise = HashMap::new();
+    channel_noise.insert(15, -60);
+    channel_noise.insert(20, -80);
+    channel_noise.insert(25, i16::MAX);
+    let (radio_requests, radio_responses, _air) = start_simulated_radio(
+        executor.clone(),
+        SimulatedRadioConfig {
+            channel_noise,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = mac
+            .request(mlme::Request::Scan(mlme::ScanRequest {
+                scan_type: mlme::ScanType::EnergyDetect,
+                scan_channels: vec![11, 15, 20, 25],
+                scan_duration: 0,
+                channel_page: 0,
+            }))
+            .await;
+        match confirm {
+            mlme::Confirm::Scan(confirm) => {
+                assert!(confirm.status.is_ok());
+                assert!(confirm.unscanned_channels.is_empty());
+                assert_eq!(confirm.energy_detect_list, vec![0, 80, 40, 0xFF]);
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_active_scan() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        coordinator.start_coordinator(PANID(0x1234), 15).await;
+        let confirm = device
+            .request(mlme::Request::Scan(mlme::ScanRequest {
+                scan_type: mlme::ScanType::Active,
+                scan_channels: vec![11, 15, 26],
+                scan_duration: 2,
+                channel_page: 0,
+            }))
+            .await;
+        match confirm {
+            mlme::Confirm::Scan(confirm) => {
+                assert!(confirm.status.is_ok());
+                assert_eq!(confirm.pan_descriptor_list.len(), 1);
+                let descriptor = &confirm.pan_descriptor_list[0];
+                assert_eq!(
+                    descriptor.coord_address,
+                    FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x0000)),
+                    }
+                );
+                assert_eq!(descriptor.channel_number, 15);
+                assert!(descriptor.beacon.pan_coordinator);
+                assert!(descriptor.beacon.association_permit);
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_beacon_pending_addresses() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        coordinator_radio,
+        mut device,
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        coordinator.start_coordinator(PANID(0x1234), 15).await;
+        for address in [
+            Address::Short(ShortAddress(0x4567)),
+            Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+        ] {
+            coordinator
+                .mcps_input
+                .send(mcps::Input::Request(mcps::Request::Data(
+                    mcps::DataRequest {
+                        source_addressing_mode: AddressingMode::Short,
+                        destination: Some(FullAddress {
+                            pan_id: PANID(0x1234),
+                            address,
+                        }),
+                        msdu: vec![1, 2, 3],
+                        msdu_handle: mcps::MsduHandle::new(),
+                        ack_tx: true,
+                        indirect_tx: true,
+                        security_level: SecurityLevel::None,
+                        key_identifier: KeyIdentifier::Implicit(),
+                        seq_num_suppressed: false,
+                        header_ie_list: vec![],
+                        payload_ie_list: vec![],
+                    },
+                )))
+                .await
+                .unwrap();
+        }
+        // Both frames are queued once the radio has them in its pending table.
+        coordinator_radio
+            .wait_until(|radio| {
+                [
+                    Address::Short(ShortAddress(0x4567)),
+                    Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+                ]
+                .iter()
+                .all(|&address| {
+                    radio.is_pending(&Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address,
+                    }))
+                })
+            })
+            .await;
+        let confirm = device
+            .request(mlme::Request::Scan(mlme::ScanRequest {
+                scan_type: mlme::ScanType::Active,
+                scan_channels: vec![15],
+                scan_duration: 2,
+                channel_page: 0,
+            }))
+            .await;
+        match confirm {
+            mlme::Confirm::Scan(confirm) => {
+                assert_eq!(confirm.pan_descriptor_list.len(), 1);
+                let beacon = &confirm.pan_descriptor_list[0].beacon;
+                assert_eq!(beacon.pending_short_addresses, vec![ShortAddress(0x4567)]);
+                assert_eq!(
+                    beacon.pending_extended_addresses,
+                    vec![ExtendedAddress(DEVICE_ADDRESS)]
+                );
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_enhanced_active_scan() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        coordinator.start_coordinator(PANID(0x1234), 15).await;
+        let confirm = device
+            .request(mlme::Request::Scan(mlme::ScanRequest {
+                scan_type: mlme::ScanType::EnhancedActive,
+                scan_channels: vec![15],
+                scan_duration: 2,
+                channel_page: 0,
+            }))
+            .await;
+        match confirm {
+            mlme::Confirm::Scan(confirm) => {
+                assert!(confirm.status.is_ok());
+                assert_eq!(confirm.pan_descriptor_list.len(), 1);
+                let descriptor = &confirm.pan_descriptor_list[0];
+                assert_eq!(
+                    descriptor.coord_address,
+                    FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x0000)),
+                    }
+                );
+                // Enhanced beacons have no superframe specification.
+                assert_eq!(descriptor.beacon.beacon_order, 15);
+                assert!(!descriptor.beacon.association_permit);
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_orphan_scan() {
+    let executor = AsyncStdExecutor::new();
+    let medium = VirtualMedium::new(Some(LinkConfig::default()), 1);
+    let (radio_requests, radio_responses, _) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: DEVICE_ADDRESS,
+            ..Default::default()
+        },
+    );
+    let mut mac = start_mac(&executor, radio_requests, radio_responses);
+    let (radio_requests, radio_responses, _) = medium.start_node(
+        &executor,
+        SimulatedRadioConfig {
+            extended_address: COORDINATOR_ADDRESS,
+            ..Default::default()
+        },
+    );
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let mut coordinator = ScriptedDevice::new(
+            radio_requests,
+            radio_responses,
+            PANID(0x1234),
+            ShortAddress(0x0000),
+        )
+        .await;
+        mac.mlme_input
+            .send(mlme::Input::Request(mlme::Request::Scan(
+                mlme::ScanRequest {
+                    scan_type: mlme::ScanType::Orphan,
+                    scan_channels: vec![26],
+                    scan_duration: 0,
+                    channel_page: 0,
+                },
+            )))
+            .await
+            .unwrap();
+        let orphan_notification = coordinator.receive().await;
+        assert_eq!(
+            orphan_notification.frame_type,
+            FrameType::Command(Command::OrphanNotification())
+        );
+        assert_eq!(
+            orphan_notification.source,
+            Some(FullAddress {
+                pan_id: PANID::broadcast(),
+                address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+            })
+        );
+        coordinator
+            .send_frame(&Frame {
+                version: FrameVersion::Ieee2003,
+                frame_pending: false,
+                acknowledge_request: true,
+                sequence_number: Some(0x10),
+                destination: orphan_notification.source,
+                source: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Extended(ExtendedAddress(COORDINATOR_ADDRESS)),
+                }),
+                security: None,
+                header_ies: vec![],
+                payload_ies: vec![],
+                frame_type: FrameType::Command(Command::CoordinatorRealignment(
+                    CoordinatorRealignment {
+                        pan_id: PANID(0x1234),
+                        coordinator_short_address: ShortAddress(0x0000),
+                        channel_number: 26,
+                        short_address: ShortAddress(0x4567),
+                        channel_page: None,
+                    },
+                )),
+            })
+            .await;
+        loop {
+            if let mlme::Output::Confirm(mlme::Confirm::Scan(confirm)) =
+                mac.mlme_output.next().await.unwrap()
+            {
+                assert!(confirm.status.is_ok());
+                break;
+            }
+        }
+        match mac
+            .request(mlme::Request::Get(mlme::GetRequest {
+                attribute: PIBProperty::MacShortAddress,
+            }))
+            .await
+        {
+            mlme::Confirm::Get(_, Ok(PIBValue::ShortAddress(address))) => {
+                assert_eq!(address, ShortAddress(0x4567))
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+/**
+ * Lets the device MAC associate with the coordinator MAC, which answers with the given status.
+ */
+async fn associate(
+    coordinator: &mut SimulatedMac,
+    device: &mut SimulatedMac,
+    status: Result<Option<ShortAddress>, AssociationError>,
+) -> mlme::AssociateConfirm {
+    let capability_information = CapabilityInformation {
+        device_type: DeviceType::RFD,
+        power_source: PowerSource::Battery,
+        receiver_on_when_idle: false,
+        fast_association: false,
+        security_capable: false,
+        allocate_address: true,
+    };
+    coordinator.start_coordinator(PANID(0x1234), 15).await;
+    device
+        .mlme_input
+        .send(mlme::Input::Request(mlme::Request::Associate(
+            mlme::AssociateRequest {
+                channel_number: 15,
+                channel_page: 0,
+                coord_address: FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x0000)),
+                },
+                capability_information: capability_information.clone(),
+            },
+        )))
+        .await
+        .unwrap();
+    loop {
+        if let mlme::Output::Indication(mlme::Indication::Associate {
+            device_address,
+            capability_information: received,
+        }) = coordinator.mlme_output.next().await.unwrap()
+        {
+            assert_eq!(device_address, ExtendedAddress(DEVICE_ADDRESS));
+            assert_eq!(received, capability_information);
+            break;
+        }
+    }
+    coordinator
+        .mlme_input
+        .send(mlme::Input::Response(mlme::Response::Associate {
+            device_address: ExtendedAddress(DEVICE_ADDRESS),
+            fast_association: false,
+            status,
+        }))
+        .await
+        .unwrap();
+    let confirm = loop {
+        if let mlme::Output::Confirm(mlme::Confirm::Associate(confirm)) =
+            device.mlme_output.next().await.unwrap()
+        {
+            break confirm;
+        }
+    };
+    // The response was delivered, whatever it said.
+    loop {
+        if let mlme::Output::Indication(mlme::Indication::CommStatus { status, .. }) =
+            coordinator.mlme_output.next().await.unwrap()
+        {
+            assert!(status.is_ok());
+            return confirm;
+        }
+    }
+}
+
+#[test]
+fn test_association() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(matches!(confirm.status, Ok(ShortAddress(0x4567))));
+        for (attribute, expected) in [
+            (PIBProperty::MacPanId, PIBValue::PANID(PANID(0x1234))),
+            (
+                PIBProperty::MacShortAddress,
+                PIBValue::ShortAddress(ShortAddress(0x4567)),
+            ),
+            (
+                PIBProperty::MacAssociatedPanCoord,
+                Some((ExtendedAddress(COORDINATOR_ADDRESS), ShortAddress(0x0000))).into(),
+            ),
+        ] {
+            match device
+                .request(mlme::Request::Get(mlme::GetRequest { attribute }))
+                .await
+            {
+                mlme::Confirm::Get(_, Ok(value)) => assert_eq!(value, expected),
+                confirm => panic!("Unexpected confirm: {:?}", confirm),
+            }
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_association_denied() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Err(AssociationError::PANAccessDenied),
+        )
+        .await;
+        assert!(matches!(
+            confirm.status,
+            Err(mlme::Error::Association(AssociationError::PANAccessDenied))
+        ));
+        match device
+            .request(mlme::Request::Get(mlme::GetRequest {
+                attribute: PIBProperty::MacPanId,
+            }))
+            .await
+        {
+            mlme::Confirm::Get(_, Ok(PIBValue::PANID(pan_id))) => {
+                assert_eq!(pan_id, PANID::broadcast())
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_poll() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        coordinator_radio,
+        mut device,
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        coordinator
+            .mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle: mcps::MsduHandle::new(),
+                    ack_tx: true,
+                    indirect_tx: true,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        coordinator_radio
+            .wait_until(|radio| {
+                radio.is_pending(&Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x4567)),
+                }))
+            })
+            .await;
+
+        let coord_address = FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0000)),
+        };
+        device
+            .mlme_input
+            .send(mlme::Input::Request(mlme::Request::Poll(
+                mlme::PollRequest { coord_address },
+            )))
+            .await
+            .unwrap();
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.msdu, vec![1, 2, 3]);
+                assert_eq!(indication.source, Some(coord_address));
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert!(matches!(
+            device.mlme_output.next().await.unwrap(),
+            mlme::Output::Confirm(mlme::Confirm::Poll(Ok(())))
+        ));
+
+        // Nothing left, the Ack tells us so.
+        assert!(matches!(
+            device
+                .request(mlme::Request::Poll(mlme::PollRequest { coord_address }))
+                .await,
+            mlme::Confirm::Poll(Err(mlme::Error::NoData))
+        ));
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_poll_by_other_address() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        coordinator_radio,
+        mut device,
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        // Queued for the extended address, but the device polls with its short address.
+        coordinator
+            .mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Extended(ExtendedAddress(DEVICE_ADDRESS)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle: mcps::MsduHandle::new(),
+                    ack_tx: true,
+                    indirect_tx: true,
+                    security_level: SecurityLevel::None,
+                    key_identifier: KeyIdentifier::Implicit(),
+                    seq_num_suppressed: false,
+                    header_ie_list: vec![],
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        // The radio acks the data request with the pending bit once the short address is in.
+        coordinator_radio
+            .wait_until(|radio| {
+                radio.is_pending(&Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x4567)),
+                }))
+            })
+            .await;
+
+        let coord_address = FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0000)),
+        };
+        device
+            .mlme_input
+            .send(mlme::Input::Request(mlme::Request::Poll(
+                mlme::PollRequest { coord_address },
+            )))
+            .await
+            .unwrap();
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.msdu, vec![1, 2, 3]);
+                assert_eq!(
+                    indication
+                        .destination
+                        .map(|destination| destination.address),
+                    Some(Address::Extended(ExtendedAddress(DEVICE_ADDRESS)))
+                );
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert!(matches!(
+            device.mlme_output.next().await.unwrap(),
+            mlme::Output::Confirm(mlme::Confirm::Poll(Ok(())))
+        ));
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_disassociation_by_coordinator() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        coordinator_radio,
+        mut device,
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        // The device polls with its short address, so that is where the notification must wait.
+        let device_address = FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x4567)),
+        };
+        coordinator
+            .mlme_input
+            .send(mlme::Input::Request(mlme::Request::Disassociate(
+                mlme::DisassociateRequest {
+                    device_address,
+                    reason: DisassociationReason::CoordinatorWishesDeviceToLeave,
+                    tx_indirect: true,
+                },
+            )))
+            .await
+            .unwrap();
+        coordinator_radio
+            .wait_until(|radio| radio.is_pending(&Some(device_address)))
+            .await;
+
+        device
+            .mlme_input
+            .send(mlme::Input::Request(mlme::Request::Poll(
+                mlme::PollRequest {
+                    coord_address: FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x0000)),
+                    },
+                },
+            )))
+            .await
+            .unwrap();
+        let mut polled = false;
+        let mut notified = false;
+        while !polled || !notified {
+            match device.mlme_output.next().await.unwrap() {
+                mlme::Output::Confirm(mlme::Confirm::Poll(status)) => {
+                    assert!(status.is_ok());
+                    polled = true;
+                }
+                mlme::Output::Indication(mlme::Indication::Disassociate {
+                    device_address,
+                    reason,
+                }) => {
+                    assert_eq!(device_address, ExtendedAddress(COORDINATOR_ADDRESS));
+                    assert_eq!(reason, DisassociationReason::CoordinatorWishesDeviceToLeave);
+                    notified = true;
+                }
+                output => panic!("Unexpected output: {:?}", output),
+            }
+        }
+        match coordinator.mlme_output.next().await.unwrap() {
+            mlme::Output::Confirm(mlme::Confirm::Disassociate(confirm)) => {
+                assert!(confirm.status.is_ok());
+                assert_eq!(confirm.device_address, device_address);
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        match device
+            .request(mlme::Request::Get(mlme::GetRequest {
+                attribute: PIBProperty::MacShortAddress,
+            }))
+            .await
+        {
+            mlme::Confirm::Get(_, Ok(PIBValue::ShortAddress(address))) => {
+                assert_eq!(address, ShortAddress::broadcast())
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_disassociation_by_device() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        let coord_address = FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0000)),
+        };
+        match device
+            .request(mlme::Request::Disassociate(mlme::DisassociateRequest {
+                device_address: coord_address,
+                reason: DisassociationReason::DeviceWishesToLeave,
+                tx_indirect: false,
+            }))
+            .await
+        {
+            mlme::Confirm::Disassociate(confirm) => {
+                assert!(confirm.status.is_ok());
+                assert_eq!(confirm.device_address, coord_address);
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+        loop {
+            if let mlme::Output::Indication(mlme::Indication::Disassociate {
+                device_address,
+                reason,
+            }) = coordinator.mlme_output.next().await.unwrap()
+            {
+                assert_eq!(device_address, ExtendedAddress(DEVICE_ADDRESS));
+                assert_eq!(reason, DisassociationReason::DeviceWishesToLeave);
+                break;
+            }
+        }
+        match device
+            .request(mlme::Request::Get(mlme::GetRequest {
+                attribute: PIBProperty::MacPanId,
+            }))
+            .await
+        {
+            mlme::Confirm::Get(_, Ok(PIBValue::PANID(pan_id))) => {
+                assert_eq!(pan_id, PANID::broadcast())
+            }
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_orphan_realignment() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        coordinator.start_coordinator(PANID(0x1234), 20).await;
+        device
+            .mlme_input
+            .send(mlme::Input::Request(mlme::Request::Scan(
+                mlme::ScanRequest {
+                    scan_type: mlme::ScanType::Orphan,
+                    scan_channels: vec![15, 20],
+                    scan_duration: 0,
+                    channel_page: 0,
+                },
+            )))
+            .await
+            .unwrap();
+        let orphan_address = loop {
+            if let mlme::Output::Indication(mlme::Indication::Orphan { orphan_address }) =
+                coordinator.mlme_output.next().await.unwrap()
+            {
+                break orphan_address;
+            }
+        };
+        assert_eq!(orphan_address, ExtendedAddress(DEVICE_ADDRESS));
+        coordinator
+            .mlme_input
+            .send(mlme::Input::Response(mlme::Response::Orphan {
+                orphan_address,
+                short_address: ShortAddress(0x4567),
+                associated_member: true,
+            }))
+            .await
+            .unwrap();
+        match device.mlme_output.next().await.unwrap() {
+            mlme::Output::Confirm(mlme::Confirm::Scan(confirm)) => {
+                assert!(confirm.status.is_ok());
+                assert!(confirm.unscanned_channels.is_empty());
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert_eq!(
+            device.get(PIBProperty::MacPanId).await,
+            PIBValue::PANID(PANID(0x1234))
+        );
+        assert_eq!(
+            device.get(PIBProperty::MacShortAddress).await,
+            PIBValue::ShortAddress(ShortAddress(0x4567))
+        );
+        assert_eq!(
+            device.get(PIBProperty::PhyCurrentChannel).await,
+            PIBValue::U16(20)
+        );
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_start_realignment() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        // Move the PAN, taking the device along.
+        match coordinator
+            .request(mlme::Request::Start(mlme::StartRequest {
+                pan_id: PANID(0x4321),
+                channel_number: 20,
+                channel_page: 0,
+                start_time: 0,
+                beacon_order: 15,
+                superframe_order: 15,
+                pan_coordinator: true,
+                battery_life_extension: false,
+                coord_realignment: true,
+            }))
+            .await
+        {
+            mlme::Confirm::Start(status) => assert!(status.is_ok()),
+            confirm => panic!("Unexpected confirm: {:?}", confirm),
+        }
+        match device.mlme_output.next().await.unwrap() {
+            mlme::Output::Indication(mlme::Indication::SyncLoss {
+                reason,
+                pan_id,
+                channel_number,
+                ..
+            }) => {
+                assert_eq!(reason, mlme::SyncLossReason::Realignment);
+                assert_eq!(pan_id, PANID(0x4321));
+                assert_eq!(channel_number, 20);
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert_eq!(
+            device.get(PIBProperty::MacPanId).await,
+            PIBValue::PANID(PANID(0x4321))
+        );
+        assert_eq!(
+            device.get(PIBProperty::MacShortAddress).await,
+            PIBValue::ShortAddress(ShortAddress(0x4567))
+        );
+        assert_eq!(
+            coordinator.get(PIBProperty::MacPanId).await,
+            PIBValue::PANID(PANID(0x4321))
+        );
+        assert_eq!(
+            coordinator.get(PIBProperty::PhyCurrentChannel).await,
+            PIBValue::U16(20)
+        );
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_secured_data() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        let key = [0x55; 16];
+        coordinator
+            .enable_security(
+                key,
+                DeviceDescriptor {
+                    pan_id: PANID(0x1234),
+                    short_address: ShortAddress(0x4567),
+                    extended_address: ExtendedAddress(DEVICE_ADDRESS),
+                    frame_counter: 0,
+                },
+            )
+            .await;
+        device
+            .enable_security(
+                key,
+                DeviceDescriptor {
+                    pan_id: PANID(0x1234),
+                    short_address: ShortAddress(0x0000),
+                    extended_address: ExtendedAddress(COORDINATOR_ADDRESS),
+                    frame_counter: 0,
+                },
+            )
+            .await;
+
+        let request = |key_identifier| {
+            mcps::Input::Request(mcps::Request::Data(mcps::DataRequest {
+                source_addressing_mode: AddressingMode::Short,
+                destination: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x0000)),
+                }),
+                msdu: vec![1, 2, 3],
+                msdu_handle: mcps::MsduHandle::new(),
+                ack_tx: true,
+                indirect_tx: false,
+                security_level: SecurityLevel::EncMic32,
+                key_identifier,
+                seq_num_suppressed: false,
+                header_ie_list: vec![],
+                payload_ie_list: vec![],
+            }))
+        };
+        device
+            .mcps_input
+            .send(request(KeyIdentifier::Index(1)))
+            .await
+            .unwrap();
+        match coordinator.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.msdu, vec![1, 2, 3]);
+                assert_eq!(indication.security_level, SecurityLevel::EncMic32);
+                assert_eq!(indication.key_identifier, KeyIdentifier::Index(1));
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Confirm(mcps::Confirm::Data(confirm)) => {
+                assert!(confirm.ack_payload.is_ok())
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert_eq!(
+            device.get(PIBProperty::MacFrameCounter).await,
+            PIBValue::U32(1)
+        );
+        assert_eq!(
+            coordinator.get(PIBProperty::MacSecurityFailure).await,
+            PIBValue::U32(0)
+        );
+
+        // Keys that are not in the table are refused right away.
+        device
+            .mcps_input
+            .send(request(KeyIdentifier::Index(2)))
+            .await
+            .unwrap();
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Confirm(mcps::Confirm::Data(confirm)) => {
+                assert_eq!(confirm.ack_payload, Err(mcps::Error::UnavailableKey))
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_enhanced_ack() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+
+        // Header IEs need a version 2 frame, which the radio does not acknowledge by itself.
+        let header_ie_list = vec![HeaderIE::VendorSpecific {
+            vendor_oui: [0x00, 0x12, 0x4B],
+            content: vec![0x01],
+        }];
+        let request = |ack_tx, seq_num_suppressed| {
+            mcps::Input::Request(mcps::Request::Data(mcps::DataRequest {
+                source_addressing_mode: AddressingMode::Short,
+                destination: Some(FullAddress {
+                    pan_id: PANID(0x1234),
+                    address: Address::Short(ShortAddress(0x0000)),
+                }),
+                msdu: vec![1, 2, 3],
+                msdu_handle: mcps::MsduHandle::new(),
+                ack_tx,
+                indirect_tx: false,
+                security_level: SecurityLevel::None,
+                key_identifier: KeyIdentifier::Implicit(),
+                seq_num_suppressed,
+                header_ie_list: header_ie_list.clone(),
+                payload_ie_list: vec![],
+            }))
+        };
+        device.mcps_input.send(request(true, false)).await.unwrap();
+        match coordinator.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.msdu, vec![1, 2, 3]);
+                assert_eq!(indication.header_ie_list, header_ie_list);
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Confirm(mcps::Confirm::Data(confirm)) => {
+                assert!(confirm.ack_payload.is_ok())
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+
+        // Without a sequence number, there is nothing to acknowledge.
+        device.mcps_input.send(request(true, true)).await.unwrap();
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Confirm(mcps::Confirm::Data(confirm)) => {
+                assert_eq!(confirm.ack_payload, Err(mcps::Error::InvalidParameter))
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        device.mcps_input.send(request(false, true)).await.unwrap();
+        match coordinator.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.dsn, None);
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_secured_enhanced_ack() {
+    let executor = AsyncStdExecutor::new();
+    let TwoNodeNetwork {
+        mut coordinator,
+        mut device,
+        ..
+    } = two_node_network(&executor);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let confirm = associate(
+            &mut coordinator,
+            &mut device,
+            Ok(Some(ShortAddress(0x4567))),
+        )
+        .await;
+        assert!(confirm.status.is_ok());
+        let key = [0x55; 16];
+        coordinator
+            .enable_security(
+                key,
+                DeviceDescriptor {
+                    pan_id: PANID(0x1234),
+                    short_address: ShortAddress(0x4567),
+                    extended_address: ExtendedAddress(DEVICE_ADDRESS),
+                    frame_counter: 0,
+                },
+            )
+            .await;
+        device
+            .enable_security(
+                key,
+                DeviceDescriptor {
+                    pan_id: PANID(0x1234),
+                    short_address: ShortAddress(0x0000),
+                    extended_address: ExtendedAddress(COORDINATOR_ADDRESS),
+                    frame_counter: 0,
+                },
+            )
+            .await;
+
+        // The Ack from the device has to name it, the coordinator has no coordinator of its own.
+        let header_ie_list = vec![HeaderIE::VendorSpecific {
+            vendor_oui: [0x00, 0x12, 0x4B],
+            content: vec![0x01],
+        }];
+        coordinator
+            .mcps_input
+            .send(mcps::Input::Request(mcps::Request::Data(
+                mcps::DataRequest {
+                    source_addressing_mode: AddressingMode::Short,
+                    destination: Some(FullAddress {
+                        pan_id: PANID(0x1234),
+                        address: Address::Short(ShortAddress(0x4567)),
+                    }),
+                    msdu: vec![1, 2, 3],
+                    msdu_handle: mcps::MsduHandle::new(),
+                    ack_tx: true,
+                    indirect_tx: false,
+                    security_level: SecurityLevel::EncMic32,
+                    key_identifier: KeyIdentifier::Index(1),
+                    seq_num_suppressed: false,
+                    header_ie_list,
+                    payload_ie_list: vec![],
+                },
+            )))
+            .await
+            .unwrap();
+        match device.mcps_output.next().await.unwrap() {
+            mcps::Output::Indication(mcps::Indication::Data(indication)) => {
+                assert_eq!(indication.msdu, vec![1, 2, 3]);
+                assert_eq!(indication.security_level, SecurityLevel::EncMic32);
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        match coordinator.mcps_output.next().await.unwrap() {
+            mcps::Output::Confirm(mcps::Confirm::Data(confirm)) => {
+                assert!(confirm.ack_payload.is_ok())
+            }
+            output => panic!("Unexpected output: {:?}", output),
+        }
+        assert_eq!(
+            coordinator.get(PIBProperty::MacSecurityFailure).await,
+            PIBValue::U32(0)
+        );
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_tx_scheduler_order() {
+    let mut pib = PIB::new(ExtendedAddress(COORDINATOR_ADDRESS), 26, 0);
+    let mut scheduler = TxScheduler::new();
+    let device = |address| {
+        Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(address)),
+        })
+    };
+    for (priority, destination, data) in [
+        (TxPriority::Data, device(0x0001), 1),
+        (TxPriority::Data, device(0x0001), 2),
+        (TxPriority::Data, device(0x0002), 3),
+        (TxPriority::Command, device(0x0001), 4),
+        (TxPriority::Ack, None, 5),
+    ] {
+        scheduler.queue_packet(
+            &mut pib,
+            priority,
+            destination,
+            UniqueKey::new(),
+            vec![data],
+        );
+    }
+    assert_eq!(pib.mac_tx_queue_depth, 5);
+    let mut sent = vec![];
+    let mut channel_clears = 0;
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        while !scheduler.is_empty() {
+            match futures::future::poll_fn(|cx| scheduler.poll_action(&mut pib, cx)).await {
+                CsmaAction::ChannelClear(key) => {
+                    channel_clears += 1;
+                    scheduler.process_channel_clear_result(&pib, key, Ok(true));
+                }
+                CsmaAction::SendPacket(key, data) => {
+                    sent.push(data[0]);
+                    scheduler.process_send_result(key);
+                }
+                CsmaAction::Failure(_) => panic!("Unexpected channel access failure"),
+            }
+        }
+    }))
+    .unwrap();
+    // The ack goes first without CCA, then the command, and destinations take turns for data.
+    assert_eq!(sent, vec![5, 4, 1, 3, 2]);
+    assert_eq!(channel_clears, 4);
+    assert_eq!(pib.mac_tx_queue_depth, 0);
+    assert_eq!(pib.mac_tx_queue_max_depth, 5);
+}
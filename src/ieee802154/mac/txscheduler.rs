@@ -0,0 +1,182 @@
+use crate::ieee802154::frame;
+use crate::ieee802154::frame::{FrameType, FullAddress};
+use crate::ieee802154::mac::csma::{Csma, CsmaAction};
+use crate::ieee802154::pib::PIB;
+use crate::radio::RadioError;
+use crate::unique_key::UniqueKey;
+use crate::waker_store::WakerStore;
+use std::collections::VecDeque;
+use std::task::{Context, Poll};
+
+/**
+ * Order in which queued frames are transmitted, most urgent first.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TxPriority {
+    Ack,     // The sender is waiting for it, sent without CSMA-CA
+    Beacon,  // Answers to beacon requests, scanning devices only listen for a short while
+    Command, // MAC commands
+    Data,
+}
+
+const TX_PRIORITIES: usize = 4;
+
+impl TxPriority {
+    pub fn of(frame: &frame::Frame) -> Self {
+        match frame.frame_type {
+            FrameType::Ack(_) => TxPriority::Ack,
+            FrameType::Beacon(_) | FrameType::EnhancedBeacon(_) => TxPriority::Beacon,
+            FrameType::Command(_) => TxPriority::Command,
+            _ => TxPriority::Data,
+        }
+    }
+}
+
+struct TxEntry {
+    key: UniqueKey,
+    data: Vec<u8>,
+}
+
+/**
+ * Frames of a single priority. Every destination has its own queue, and the destinations take
+ * turns, so a device with a lot of traffic can not hold up frames for the others.
+ */
+#[derive(Default)]
+struct TxQueue {
+    destinations: VecDeque<(Option<FullAddress>, VecDeque<TxEntry>)>,
+}
+
+impl TxQueue {
+    fn push(&mut self, destination: Option<FullAddress>, entry: TxEntry) {
+        match self
+            .destinations
+            .iter_mut()
+            .find(|(queue_destination, _)| *queue_destination == destination)
+        {
+            Some((_, entries)) => entries.push_back(entry),
+            None => self
+                .destinations
+                .push_back((destination, vec![entry].into_iter().collect())),
+        }
+    }
+
+    fn pop(&mut self) -> Option<TxEntry> {
+        let (destination, mut entries) = self.destinations.pop_front()?;
+        let entry = entries.pop_front();
+        if !entries.is_empty() {
+            // Back of the line for this destination.
+            self.destinations.push_back((destination, entries));
+        }
+        entry
+    }
+
+    fn len(&self) -> usize {
+        self.destinations
+            .iter()
+            .map(|(_, entries)| entries.len())
+            .sum()
+    }
+}
+
+/**
+ * Serializes all transmissions of the MAC, as the radio can only send one frame at a time, and
+ * can not receive while sending. Only a single frame is handed to the radio until it reports the
+ * result.
+ */
+pub struct TxScheduler {
+    queues: [TxQueue; TX_PRIORITIES],
+    csma: Csma,
+    in_flight: Option<UniqueKey>, // Frame handed to the radio, waiting for the result
+    waker: WakerStore,
+}
+
+impl TxScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: Default::default(),
+            csma: Csma::new(),
+            in_flight: None,
+            waker: WakerStore::new(),
+        }
+    }
+
+    pub fn queue_packet(
+        &mut self,
+        pib: &mut PIB,
+        priority: TxPriority,
+        destination: Option<FullAddress>,
+        key: UniqueKey,
+        data: Vec<u8>,
+    ) {
+        self.queues[priority as usize].push(destination, TxEntry { key, data });
+        self.update_metrics(pib);
+        self.waker.wake();
+    }
+
+    /**
+     * Number of frames waiting to be transmitted, including the one in channel access.
+     */
+    pub fn len(&self) -> usize {
+        let csma = if self.csma.is_idle() { 0 } else { 1 };
+        self.queues.iter().map(TxQueue::len).sum::<usize>() + csma
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn update_metrics(&self, pib: &mut PIB) {
+        let depth = self.len().min(u16::MAX as usize) as u16;
+        pib.mac_tx_queue_depth = depth;
+        pib.mac_tx_queue_max_depth = pib.mac_tx_queue_max_depth.max(depth);
+    }
+
+    pub fn process_channel_clear_result(
+        &mut self,
+        pib: &PIB,
+        key: UniqueKey,
+        result: Result<bool, RadioError>,
+    ) {
+        self.csma.process_channel_clear_result(pib, key, result);
+    }
+
+    pub fn process_send_result(&mut self, key: UniqueKey) {
+        if self.in_flight == Some(key) {
+            self.in_flight = None;
+            self.waker.wake();
+        }
+    }
+
+    pub fn poll_action(&mut self, pib: &mut PIB, cx: &mut Context<'_>) -> Poll<CsmaAction> {
+        if self.in_flight.is_some() {
+            return self.waker.pend(cx);
+        }
+        // Acks go out right away, even while another frame is in channel access.
+        if let Some(entry) = self.queues[TxPriority::Ack as usize].pop() {
+            self.in_flight = Some(entry.key);
+            self.update_metrics(pib);
+            return Poll::Ready(CsmaAction::SendPacket(entry.key, entry.data));
+        }
+        if self.csma.is_idle() {
+            if let Some(entry) = self.queues.iter_mut().find_map(TxQueue::pop) {
+                self.csma.start(pib, entry.key, entry.data);
+            }
+        }
+        match self.csma.poll_action(cx) {
+            Poll::Ready(action) => {
+                if let CsmaAction::SendPacket(key, _) = &action {
+                    self.in_flight = Some(*key);
+                }
+                self.update_metrics(pib);
+                Poll::Ready(action)
+            }
+            Poll::Pending => self.waker.pend(cx),
+        }
+    }
+}
+
+impl Default for TxScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,6 +1,9 @@
 pub mod frame;
 pub mod mac;
 pub mod pib;
+pub mod security;
 pub mod services;
+#[cfg(test)]
+mod tests;
 
 pub use frame::{Address, AddressingMode, ExtendedAddress, FullAddress, ShortAddress, PANID};
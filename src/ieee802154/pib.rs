@@ -9,7 +9,6 @@ use std::time::Duration;
  * Implements a PIB as described in 8.4 of 802.15.4-2015 standard
  * Only properties relevant to this implementation are implemented.
  */
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub enum PIBProperty {
     MacExtendedAddress,
@@ -23,25 +22,64 @@ pub enum PIBProperty {
     MacBeaconAutoRespond,
     MacTransactionPersistenceTime,
     MacMaxFrameRetries,
+    MacMinBe,
+    MacMaxBe,
+    MacMaxCsmaBackoffs,
+    MacRxSuccessCount,
+    MacSecurityFailure,
+    MacDuplicateFrameCount,
+    MacRxDropCount,
+    MacTxQueueDepth,
+    MacTxQueueMaxDepth,
+    MacSecurityEnabled,
+    MacFrameCounter,
+    MacKeyTable,
+    MacDeviceTable,
+    MacEnhAckHeaderIeList,
     PhyCurrentChannel,
     PhyMaxTxPower,
     PhyTxPower,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PIBValue {
     Bool(bool),
     U8(u8),
     U16(u16),
+    U32(u32),
     Blob(Vec<u8>),
     Duration(Duration),
     ShortAddress(ShortAddress),
     ExtendedAddress(ExtendedAddress),
     PANID(PANID),
     Pair(Box<PIBValue>, Box<PIBValue>),
+    KeyTable(Vec<KeyDescriptor>),
+    DeviceTable(Vec<DeviceDescriptor>),
+    HeaderIEList(Vec<frame::HeaderIE>),
     Empty,
 }
 
+/**
+ * Entry of macKeyTable, as described in 9.5 of 802.15.4-2015.
+ * Only the key lookup is implemented, any key may be used for any frame type.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDescriptor {
+    pub key_identifier: frame::KeyIdentifier, // How secured frames refer to this key
+    pub key: [u8; 16],
+}
+
+/**
+ * Entry of macDeviceTable, as described in 9.5 of 802.15.4-2015.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub pan_id: PANID,
+    pub short_address: ShortAddress,
+    pub extended_address: ExtendedAddress,
+    pub frame_counter: u32, // Lowest frame counter that will still be accepted
+}
+
 impl From<bool> for PIBValue {
     fn from(value: bool) -> PIBValue {
         PIBValue::Bool(value)
@@ -57,6 +95,11 @@ impl From<u16> for PIBValue {
         PIBValue::U16(value)
     }
 }
+impl From<u32> for PIBValue {
+    fn from(value: u32) -> PIBValue {
+        PIBValue::U32(value)
+    }
+}
 impl From<Vec<u8>> for PIBValue {
     fn from(value: Vec<u8>) -> PIBValue {
         PIBValue::Blob(value)
@@ -82,6 +125,21 @@ impl From<PANID> for PIBValue {
         PIBValue::PANID(value)
     }
 }
+impl From<Vec<KeyDescriptor>> for PIBValue {
+    fn from(value: Vec<KeyDescriptor>) -> PIBValue {
+        PIBValue::KeyTable(value)
+    }
+}
+impl From<Vec<DeviceDescriptor>> for PIBValue {
+    fn from(value: Vec<DeviceDescriptor>) -> PIBValue {
+        PIBValue::DeviceTable(value)
+    }
+}
+impl From<Vec<frame::HeaderIE>> for PIBValue {
+    fn from(value: Vec<frame::HeaderIE>) -> PIBValue {
+        PIBValue::HeaderIEList(value)
+    }
+}
 impl<A, B> From<(A, B)> for PIBValue
 where
     PIBValue: From<A> + From<B>,
@@ -133,6 +191,16 @@ impl TryInto<u16> for PIBValue {
         }
     }
 }
+impl TryInto<u32> for PIBValue {
+    type Error = ();
+    fn try_into(self) -> Result<u32, Self::Error> {
+        if let PIBValue::U32(x) = self {
+            Ok(x)
+        } else {
+            Err(())
+        }
+    }
+}
 impl TryInto<Vec<u8>> for PIBValue {
     type Error = ();
     fn try_into(self) -> Result<Vec<u8>, Self::Error> {
@@ -183,6 +251,36 @@ impl TryInto<PANID> for PIBValue {
         }
     }
 }
+impl TryInto<Vec<KeyDescriptor>> for PIBValue {
+    type Error = ();
+    fn try_into(self) -> Result<Vec<KeyDescriptor>, Self::Error> {
+        if let PIBValue::KeyTable(x) = self {
+            Ok(x)
+        } else {
+            Err(())
+        }
+    }
+}
+impl TryInto<Vec<DeviceDescriptor>> for PIBValue {
+    type Error = ();
+    fn try_into(self) -> Result<Vec<DeviceDescriptor>, Self::Error> {
+        if let PIBValue::DeviceTable(x) = self {
+            Ok(x)
+        } else {
+            Err(())
+        }
+    }
+}
+impl TryInto<Vec<frame::HeaderIE>> for PIBValue {
+    type Error = ();
+    fn try_into(self) -> Result<Vec<frame::HeaderIE>, Self::Error> {
+        if let PIBValue::HeaderIEList(x) = self {
+            Ok(x)
+        } else {
+            Err(())
+        }
+    }
+}
 impl<A, B> TryInto<(A, B)> for PIBValue
 where
     PIBValue: TryInto<A, Error = ()> + TryInto<B, Error = ()>,
@@ -225,6 +323,26 @@ pub struct PIB {
     pub mac_beacon_auto_respond: bool,
     pub mac_transaction_persistence_time: Duration,
     pub mac_max_frame_retries: u16,
+    // CSMA-CA attributes as described in 8.4.2.1
+    pub mac_min_be: u8,
+    pub mac_max_be: u8,
+    pub mac_max_csma_backoffs: u8,
+    // Metrics as described in 8.4.2.6, macRxDropCount is not part of the standard and counts
+    // received frames that were dropped as they could not be parsed.
+    pub mac_rx_success_count: u32,
+    pub mac_security_failure: u32,
+    pub mac_duplicate_frame_count: u32,
+    pub mac_rx_drop_count: u32,
+    // Not part of the standard, frames waiting to be transmitted, now and at most since reset
+    pub mac_tx_queue_depth: u16,
+    pub mac_tx_queue_max_depth: u16,
+    // Security attributes as described in 9.5
+    pub mac_security_enabled: bool,
+    pub mac_frame_counter: u32,
+    pub mac_key_table: Vec<KeyDescriptor>,
+    pub mac_device_table: Vec<DeviceDescriptor>,
+    // Not part of the standard, header IEs to include in every Enh-Ack we send
+    pub mac_enh_ack_header_ie_list: Vec<frame::HeaderIE>,
     pub phy_current_channel: u16,
     pub phy_max_tx_power: u16,
     pub phy_tx_power: u16,
@@ -248,6 +366,20 @@ impl PIB {
             mac_beacon_auto_respond: false,
             mac_transaction_persistence_time: Duration::from_secs(5 * 60), // NOTE: Normal default is 500 unit periods
             mac_max_frame_retries: 3,
+            mac_min_be: 3,
+            mac_max_be: 5,
+            mac_max_csma_backoffs: 4,
+            mac_rx_success_count: 0,
+            mac_security_failure: 0,
+            mac_duplicate_frame_count: 0,
+            mac_rx_drop_count: 0,
+            mac_tx_queue_depth: 0,
+            mac_tx_queue_max_depth: 0,
+            mac_security_enabled: false,
+            mac_frame_counter: 0,
+            mac_key_table: Vec::new(),
+            mac_device_table: Vec::new(),
+            mac_enh_ack_header_ie_list: Vec::new(),
             phy_current_channel,
             phy_max_tx_power,
             phy_tx_power: phy_max_tx_power,
@@ -276,6 +408,22 @@ impl PIB {
             PIBProperty::MacTransactionPersistenceTime => {
                 Ok(self.mac_transaction_persistence_time.into())
             }
+            PIBProperty::MacMinBe => Ok(self.mac_min_be.into()),
+            PIBProperty::MacMaxBe => Ok(self.mac_max_be.into()),
+            PIBProperty::MacMaxCsmaBackoffs => Ok(self.mac_max_csma_backoffs.into()),
+            PIBProperty::MacRxSuccessCount => Ok(self.mac_rx_success_count.into()),
+            PIBProperty::MacSecurityFailure => Ok(self.mac_security_failure.into()),
+            PIBProperty::MacDuplicateFrameCount => Ok(self.mac_duplicate_frame_count.into()),
+            PIBProperty::MacRxDropCount => Ok(self.mac_rx_drop_count.into()),
+            PIBProperty::MacTxQueueDepth => Ok(self.mac_tx_queue_depth.into()),
+            PIBProperty::MacTxQueueMaxDepth => Ok(self.mac_tx_queue_max_depth.into()),
+            PIBProperty::MacSecurityEnabled => Ok(self.mac_security_enabled.into()),
+            PIBProperty::MacFrameCounter => Ok(self.mac_frame_counter.into()),
+            PIBProperty::MacKeyTable => Ok(self.mac_key_table.clone().into()),
+            PIBProperty::MacDeviceTable => Ok(self.mac_device_table.clone().into()),
+            PIBProperty::MacEnhAckHeaderIeList => {
+                Ok(self.mac_enh_ack_header_ie_list.clone().into())
+            }
             PIBProperty::PhyCurrentChannel => Ok(self.phy_current_channel.into()),
             PIBProperty::PhyMaxTxPower => Ok(self.phy_max_tx_power.into()),
             PIBProperty::PhyTxPower => Ok(self.phy_tx_power.into()),
@@ -322,6 +470,78 @@ impl PIB {
                     value.try_into().or(Err(mlme::Error::InvalidParameter))?;
                 Ok(())
             }
+            PIBProperty::MacMinBe => {
+                let min_be: u8 = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                if min_be > self.mac_max_be {
+                    return Err(mlme::Error::InvalidParameter);
+                }
+                self.mac_min_be = min_be;
+                Ok(())
+            }
+            PIBProperty::MacMaxBe => {
+                let max_be: u8 = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                if !(3..=8).contains(&max_be) || max_be < self.mac_min_be {
+                    return Err(mlme::Error::InvalidParameter);
+                }
+                self.mac_max_be = max_be;
+                Ok(())
+            }
+            PIBProperty::MacMaxCsmaBackoffs => {
+                let max_csma_backoffs: u8 =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                if max_csma_backoffs > 5 {
+                    return Err(mlme::Error::InvalidParameter);
+                }
+                self.mac_max_csma_backoffs = max_csma_backoffs;
+                Ok(())
+            }
+            PIBProperty::MacRxSuccessCount => {
+                self.mac_rx_success_count =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacSecurityFailure => {
+                self.mac_security_failure =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacDuplicateFrameCount => {
+                self.mac_duplicate_frame_count =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacRxDropCount => {
+                self.mac_rx_drop_count = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacTxQueueDepth => Err(mlme::Error::ReadOnly),
+            PIBProperty::MacTxQueueMaxDepth => {
+                self.mac_tx_queue_max_depth =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacSecurityEnabled => {
+                self.mac_security_enabled =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacFrameCounter => {
+                self.mac_frame_counter = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacKeyTable => {
+                self.mac_key_table = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacDeviceTable => {
+                self.mac_device_table = value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
+            PIBProperty::MacEnhAckHeaderIeList => {
+                self.mac_enh_ack_header_ie_list =
+                    value.try_into().or(Err(mlme::Error::InvalidParameter))?;
+                Ok(())
+            }
             PIBProperty::PhyCurrentChannel => {
                 self.phy_current_channel =
                     value.try_into().or(Err(mlme::Error::InvalidParameter))?;
@@ -0,0 +1,209 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use bitfield::bitfield;
+
+/**
+ * Size of the length field in the CCM* blocks. Both 802.15.4 and Zigbee use 2 bytes, leaving
+ * 13 bytes for the nonce.
+ */
+const LENGTH_SIZE: usize = 2;
+pub const NONCE_SIZE: usize = 15 - LENGTH_SIZE;
+const BLOCK_SIZE: usize = 16;
+
+bitfield! {
+    struct AuthDataFlag(u8);
+    impl Debug;
+    pub reserved, set_reserved: 7, 7;
+    pub a_data, set_a_data: 6, 6;
+    pub m, set_m: 5,3;
+    pub l, set_l: 2,0;
+}
+
+bitfield! {
+    struct NonceFlag(u8);
+    impl Debug;
+    pub reserved, set_reserved: 7, 6;
+    pub zero, set_zero: 5, 3;
+    pub l, set_l: 2, 0;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CcmStarIntegrityCodeLen {
+    None = 0,
+    MIC4 = 1,
+    MIC6 = 2,
+    MIC8 = 3,
+    MIC10 = 4,
+    MIC12 = 5,
+    MIC14 = 6,
+    MIC16 = 7,
+}
+
+impl CcmStarIntegrityCodeLen {
+    pub fn tag_size(&self) -> usize {
+        let as_num = *self as usize;
+        if as_num == 0 {
+            0
+        } else {
+            (as_num * 2) + 2
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CcmStarError {
+    TooLong,              // Message does not fit in the length field
+    AuthenticationFailed, // Integrity code did not match
+}
+
+/**
+ * CCM* as described in annex B of 802.15.4-2015, with AES-128 as block cipher.
+ * Unlike CCM, it allows for encryption without an integrity code.
+ */
+pub struct CcmStar {
+    cipher: Aes128,
+    integrity_code_len: CcmStarIntegrityCodeLen,
+}
+
+impl CcmStar {
+    pub fn new(key: &[u8; 16], integrity_code_len: CcmStarIntegrityCodeLen) -> Self {
+        CcmStar {
+            cipher: Aes128::new(GenericArray::from_slice(key)),
+            integrity_code_len,
+        }
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        self.cipher
+            .encrypt_block(GenericArray::from_mut_slice(&mut block[..]));
+    }
+
+    /**
+     * CBC-MAC over B0, the length-prefixed additional data and the message, each padded with
+     * zeroes to a full block.
+     */
+    fn calculate_tag(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<[u8; BLOCK_SIZE], CcmStarError> {
+        if (msg.len() >> (LENGTH_SIZE * 8)) != 0 {
+            return Err(CcmStarError::TooLong);
+        }
+        let mut auth_data = vec![];
+        if aad.is_empty() {
+            // No additional data, not even its length is included
+        } else if aad.len() < 0xFF00 {
+            auth_data.extend_from_slice(&(aad.len() as u16).to_be_bytes());
+        } else if aad.len() <= u32::MAX as usize {
+            auth_data.extend_from_slice(&0xFFFE_u16.to_be_bytes());
+            auth_data.extend_from_slice(&(aad.len() as u32).to_be_bytes());
+        } else {
+            return Err(CcmStarError::TooLong);
+        }
+        auth_data.extend_from_slice(aad);
+        while auth_data.len() % BLOCK_SIZE != 0 {
+            auth_data.push(0);
+        }
+        auth_data.extend_from_slice(msg);
+        while auth_data.len() % BLOCK_SIZE != 0 {
+            auth_data.push(0);
+        }
+
+        let mut auth_data_flag = AuthDataFlag(0);
+        auth_data_flag.set_reserved(0);
+        auth_data_flag.set_a_data(!aad.is_empty() as u8);
+        auth_data_flag.set_m(self.integrity_code_len as u8);
+        auth_data_flag.set_l((LENGTH_SIZE as u8) - 1); // L-1
+        let mut mac = [0; BLOCK_SIZE];
+        mac[0] = auth_data_flag.0;
+        mac[1..1 + NONCE_SIZE].copy_from_slice(nonce);
+        mac[1 + NONCE_SIZE..].copy_from_slice(&(msg.len() as u16).to_be_bytes());
+        self.encrypt_block(&mut mac);
+        for block in auth_data.chunks(BLOCK_SIZE) {
+            for (x, y) in mac.iter_mut().zip(block) {
+                *x ^= y;
+            }
+            self.encrypt_block(&mut mac);
+        }
+        Ok(mac)
+    }
+
+    /**
+     * XORs the data with the key stream starting at the given counter value.
+     */
+    fn apply_keystream(&self, nonce: &[u8; NONCE_SIZE], counter: u16, data: &mut [u8]) {
+        let mut nonce_flag = NonceFlag(0);
+        nonce_flag.set_reserved(0);
+        nonce_flag.set_zero(0);
+        nonce_flag.set_l((LENGTH_SIZE as u8) - 1);
+        for (index, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            let mut block = [0; BLOCK_SIZE];
+            block[0] = nonce_flag.0;
+            block[1..1 + NONCE_SIZE].copy_from_slice(nonce);
+            block[1 + NONCE_SIZE..]
+                .copy_from_slice(&counter.wrapping_add(index as u16).to_be_bytes());
+            self.encrypt_block(&mut block);
+            for (x, y) in chunk.iter_mut().zip(block.iter()) {
+                *x ^= y;
+            }
+        }
+    }
+
+    pub fn get_tag_len(&self) -> usize {
+        self.integrity_code_len.tag_size()
+    }
+
+    /**
+     * Encrypts msg and authenticates both aad and msg, the ciphertext is returned with the
+     * encrypted integrity code appended.
+     */
+    pub fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>, CcmStarError> {
+        let mut mac = self.calculate_tag(nonce, aad, msg)?;
+        self.apply_keystream(nonce, 0, &mut mac);
+        let mut output = msg.to_vec();
+        self.apply_keystream(nonce, 1, &mut output);
+        output.extend_from_slice(&mac[0..self.get_tag_len()]);
+        Ok(output)
+    }
+
+    /**
+     * Decrypts a ciphertext with integrity code appended, as returned by encrypt.
+     */
+    pub fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CcmStarError> {
+        let tag_len = self.get_tag_len();
+        if ciphertext.len() < tag_len {
+            return Err(CcmStarError::AuthenticationFailed);
+        }
+        let msg_len = ciphertext.len() - tag_len;
+        let mut tag = [0; BLOCK_SIZE];
+        tag[0..tag_len].copy_from_slice(&ciphertext[msg_len..]);
+        self.apply_keystream(nonce, 0, &mut tag);
+        let mut plaintext = ciphertext[0..msg_len].to_vec();
+        self.apply_keystream(nonce, 1, &mut plaintext);
+        if tag_len > 0 {
+            let mac = self.calculate_tag(nonce, aad, &plaintext)?;
+            // Compare in constant time, to not leak how much of the tag was right.
+            let difference = mac[0..tag_len]
+                .iter()
+                .zip(tag[0..tag_len].iter())
+                .fold(0, |acc, (x, y)| acc | (x ^ y));
+            if difference != 0 {
+                return Err(CcmStarError::AuthenticationFailed);
+            }
+        }
+        Ok(plaintext)
+    }
+}
@@ -0,0 +1,185 @@
+pub mod ccmstar;
+#[cfg(test)]
+mod tests;
+
+use crate::ieee802154::frame;
+use crate::ieee802154::pib::PIB;
+use crate::ieee802154::services::error::Error;
+use crate::ieee802154::ExtendedAddress;
+use crate::pack::{ExtEnum, Pack, VecPackTarget};
+use ccmstar::{CcmStar, CcmStarIntegrityCodeLen, NONCE_SIZE};
+
+fn integrity_code_len(level: frame::SecurityLevel) -> CcmStarIntegrityCodeLen {
+    match level.mic_length() {
+        4 => CcmStarIntegrityCodeLen::MIC4,
+        8 => CcmStarIntegrityCodeLen::MIC8,
+        16 => CcmStarIntegrityCodeLen::MIC16,
+        _ => CcmStarIntegrityCodeLen::None,
+    }
+}
+
+/**
+ * CCM* nonce as described in 9.3.2.2 of 802.15.4-2015, the extended address of the originator,
+ * followed by the frame counter and the security level. Unlike the frame, this is big-endian.
+ */
+fn nonce(
+    originator: ExtendedAddress,
+    frame_counter: u32,
+    level: frame::SecurityLevel,
+) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[0..8].copy_from_slice(&originator.0.to_be_bytes());
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = level.into_tag();
+    nonce
+}
+
+fn lookup_key(pib: &PIB, key_identifier: frame::KeyIdentifier) -> Result<[u8; 16], Error> {
+    pib.mac_key_table
+        .iter()
+        .find(|descriptor| descriptor.key_identifier == key_identifier)
+        .map(|descriptor| descriptor.key)
+        .ok_or(Error::UnavailableKey)
+}
+
+/**
+ * Splits an unsecured frame into its header, and the offset of the private payload. Everything
+ * before that offset is authenticated, but only the private payload is ever encrypted, as
+ * described in 9.3.5 of 802.15.4-2015. Header IEs are part of the header, and left in the open.
+ * The payload should not include the integrity code.
+ */
+fn split_frame(data: &[u8], mic_length: usize) -> Result<(frame::Header, usize), Error> {
+    let (header, payload) = frame::Header::unpack(data).or(Err(Error::SecurityError))?;
+    if payload.len() < mic_length {
+        return Err(Error::SecurityError);
+    }
+    let payload = &payload[..payload.len() - mic_length];
+    let open_payload_length = match header.frame_control.frame_type() {
+        // Payload IEs come first, and are always private
+        _ if header.header_termination == Some(frame::HeaderTermination::PayloadIEs) => 0,
+        // Enhanced beacon, there is nothing but the beacon payload
+        0 if header.frame_control.frame_version() == frame::FrameVersion::Ieee2015.into_tag() => 0,
+        // Beacon, only the beacon payload is private
+        0 => match frame::Beacon::unpack(payload) {
+            Ok((beacon, _)) => payload.len() - beacon.payload.0.len(),
+            Err(_) => return Err(Error::SecurityError),
+        },
+        // Command, the command identifier is left in the open
+        3 => payload.len().min(1),
+        _ => 0,
+    };
+    let header_length = data.len() - mic_length - payload.len();
+    Ok((header, header_length + open_payload_length))
+}
+
+/**
+ * Checks whether a frame could be secured with the given security level and key, as the first
+ * steps of the outgoing frame security procedure in 9.2.2 of 802.15.4-2015.
+ */
+pub fn check_outgoing(
+    pib: &PIB,
+    security_level: frame::SecurityLevel,
+    key_identifier: frame::KeyIdentifier,
+) -> Result<(), Error> {
+    if !pib.mac_security_enabled || security_level == frame::SecurityLevel::None {
+        return Err(Error::UnsupportedSecurity);
+    }
+    if pib.mac_frame_counter == u32::MAX {
+        return Err(Error::CounterError);
+    }
+    lookup_key(pib, key_identifier).map(|_| ())
+}
+
+/**
+ * Packs a frame, securing it if it has an auxiliary security header. The frame counter in the
+ * header is taken from macFrameCounter.
+ */
+pub fn secure_frame(pib: &mut PIB, mut frame: frame::Frame) -> Result<Vec<u8>, Error> {
+    let security = match &mut frame.security {
+        Some(security) => security,
+        None => return Ok(frame.pack(VecPackTarget::new()).unwrap().into()),
+    };
+    check_outgoing(pib, security.security_level, security.key_identifier)?;
+    let key = lookup_key(pib, security.key_identifier)?;
+    security.frame_counter = pib.mac_frame_counter;
+    pib.mac_frame_counter += 1;
+    let level = security.security_level;
+    let nonce = nonce(pib.mac_extended_address, security.frame_counter, level);
+    let data: Vec<u8> = frame.pack(VecPackTarget::new()).unwrap().into();
+    let (_, private_offset) = split_frame(&data, 0)?;
+    let (authenticated, private) = if level.is_encrypted() {
+        data.split_at(private_offset)
+    } else {
+        (&data[..], &[][..])
+    };
+    let secured = CcmStar::new(&key, integrity_code_len(level))
+        .encrypt(&nonce, authenticated, private)
+        .or(Err(Error::FrameTooLong))?;
+    let mut output = authenticated.to_vec();
+    output.extend_from_slice(&secured);
+    Ok(output)
+}
+
+/**
+ * Reverses secure_frame for a received frame, as described in 9.2.5 of 802.15.4-2015.
+ * The frame is returned with its auxiliary security header intact, but with the payload
+ * decrypted and the integrity code removed, ready to be unpacked. Frames without security
+ * are returned as is.
+ */
+pub fn unsecure_frame(pib: &mut PIB, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let (header, _) = frame::Header::unpack(data).or(Err(Error::SecurityError))?;
+    let security = match header.security {
+        Some(security) => security,
+        None => return Ok(data.to_vec()),
+    };
+    let level = security.security_level;
+    if !pib.mac_security_enabled || level == frame::SecurityLevel::None {
+        return Err(Error::UnsupportedSecurity);
+    }
+    let (header, private_offset) = split_frame(data, level.mic_length())?;
+    // Frames without source address can only come from our coordinator.
+    let originator = match header.source {
+        Some(frame::FullAddress {
+            address: frame::Address::Extended(address),
+            ..
+        }) => Some(address),
+        Some(frame::FullAddress {
+            pan_id,
+            address: frame::Address::Short(address),
+        }) => pib
+            .mac_device_table
+            .iter()
+            .find(|device| device.pan_id == pan_id && device.short_address == address)
+            .map(|device| device.extended_address),
+        None => pib.mac_associated_pan_coord.map(|(address, _)| address),
+    };
+    let device = originator
+        .and_then(|originator| {
+            pib.mac_device_table
+                .iter()
+                .position(|device| device.extended_address == originator)
+        })
+        .ok_or(Error::UnavailableKey)?;
+    let device_frame_counter = pib.mac_device_table[device].frame_counter;
+    if security.frame_counter < device_frame_counter || security.frame_counter == u32::MAX {
+        return Err(Error::CounterError);
+    }
+    let key = lookup_key(pib, security.key_identifier)?;
+    let originator = pib.mac_device_table[device].extended_address;
+    let (authenticated, secured) = if level.is_encrypted() {
+        data.split_at(private_offset)
+    } else {
+        data.split_at(data.len() - level.mic_length())
+    };
+    let private = CcmStar::new(&key, integrity_code_len(level))
+        .decrypt(
+            &nonce(originator, security.frame_counter, level),
+            authenticated,
+            secured,
+        )
+        .or(Err(Error::SecurityError))?;
+    pib.mac_device_table[device].frame_counter = security.frame_counter + 1;
+    let mut output = authenticated.to_vec();
+    output.extend_from_slice(&private);
+    Ok(output)
+}
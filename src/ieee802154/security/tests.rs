@@ -0,0 +1,164 @@
+use crate::ieee802154::frame::{
+    Address, AuxiliarySecurityHeader, Frame, FrameType, FrameVersion, FullAddress, KeyIdentifier,
+    Payload, SecurityLevel,
+};
+use crate::ieee802154::pib::{DeviceDescriptor, KeyDescriptor, PIB};
+use crate::ieee802154::security::ccmstar::{CcmStar, CcmStarError, CcmStarIntegrityCodeLen};
+use crate::ieee802154::security::{check_outgoing, secure_frame, unsecure_frame};
+use crate::ieee802154::services::error::Error;
+use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use crate::pack::{Pack, VecPackTarget};
+
+const KEY: [u8; 16] = [
+    0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD, 0xCE, 0xCF,
+];
+const SENDER_ADDRESS: u64 = 0xACDE480000000001;
+const RECEIVER_ADDRESS: u64 = 0xACDE480000000002;
+
+#[test]
+fn test_vectors_ccmstar() {
+    let nonce = [
+        0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0x03, 0x02, 0x01, 0x00, 0x06,
+    ];
+    let m: [u8; 23] = [
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16,
+        0x17, 0x18, 0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E,
+    ];
+    let a: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+    let checker = CcmStar::new(&KEY, CcmStarIntegrityCodeLen::MIC8);
+    let expected_ciphertext = vec![
+        0x1A, 0x55, 0xA3, 0x6A, 0xBB, 0x6C, 0x61, 0x0D, 0x06, 0x6B, 0x33, 0x75, 0x64, 0x9C, 0xEF,
+        0x10, 0xD4, 0x66, 0x4E, 0xCA, 0xD8, 0x54, 0xA8, 0x0A, 0x89, 0x5C, 0xC1, 0xD8, 0xFF, 0x94,
+        0x69,
+    ];
+    let ciphertext = checker.encrypt(&nonce, &a, &m).unwrap();
+    assert_eq!(ciphertext, expected_ciphertext);
+
+    // Check to see if the ciphertext we just got, decrypts to the plaintext we had before.
+    let plaintext = checker.decrypt(&nonce, &a, &ciphertext).unwrap();
+    assert_eq!(plaintext, m);
+
+    // Test to see if mangled ciphertext fails to decode.
+    let mut mangled_ciphertext = ciphertext.clone();
+    mangled_ciphertext[m.len()] ^= 0x8; // Mangle a bit just after the message length.
+    assert_eq!(
+        checker.decrypt(&nonce, &a, &mangled_ciphertext),
+        Err(CcmStarError::AuthenticationFailed)
+    );
+}
+
+fn secured_pib(own_address: u64, other_address: u64) -> PIB {
+    let mut pib = PIB::new(ExtendedAddress(own_address), 11, 0);
+    pib.mac_pan_id = PANID(0x1234);
+    pib.mac_security_enabled = true;
+    pib.mac_key_table.push(KeyDescriptor {
+        key_identifier: KeyIdentifier::Index(1),
+        key: KEY,
+    });
+    pib.mac_device_table.push(DeviceDescriptor {
+        pan_id: PANID(0x1234),
+        short_address: ShortAddress(0x0001),
+        extended_address: ExtendedAddress(other_address),
+        frame_counter: 0,
+    });
+    pib
+}
+
+fn data_frame(security_level: SecurityLevel) -> Frame {
+    Frame {
+        version: FrameVersion::Ieee2006,
+        frame_pending: false,
+        acknowledge_request: true,
+        sequence_number: Some(0x42),
+        destination: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0002)),
+        }),
+        source: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Extended(ExtendedAddress(SENDER_ADDRESS)),
+        }),
+        security: Some(AuxiliarySecurityHeader {
+            security_level,
+            key_identifier: KeyIdentifier::Index(1),
+            frame_counter: 0,
+        }),
+        header_ies: vec![],
+        payload_ies: vec![],
+        frame_type: FrameType::Data(Payload(b"Hello world".to_vec())),
+    }
+}
+
+#[test]
+fn test_secure_frame_encrypted() {
+    let mut sender = secured_pib(SENDER_ADDRESS, RECEIVER_ADDRESS);
+    let mut receiver = secured_pib(RECEIVER_ADDRESS, SENDER_ADDRESS);
+    sender.mac_frame_counter = 5;
+    let frame = data_frame(SecurityLevel::EncMic32);
+    let secured = secure_frame(&mut sender, frame.clone()).unwrap();
+    assert_eq!(sender.mac_frame_counter, 6);
+    assert!(!secured.windows(5).any(|window| window == b"Hello"));
+
+    let unsecured = unsecure_frame(&mut receiver, &secured).unwrap();
+    assert_eq!(unsecured.len() + 4, secured.len());
+    let (received, rest) = Frame::unpack(&unsecured).unwrap();
+    assert!(rest.is_empty());
+    let mut expected = frame;
+    expected.security.as_mut().unwrap().frame_counter = 5;
+    assert_eq!(received, expected);
+    assert_eq!(receiver.mac_device_table[0].frame_counter, 6);
+
+    // The same frame again is a replay.
+    assert_eq!(
+        unsecure_frame(&mut receiver, &secured),
+        Err(Error::CounterError)
+    );
+}
+
+#[test]
+fn test_secure_frame_authenticated() {
+    let mut sender = secured_pib(SENDER_ADDRESS, RECEIVER_ADDRESS);
+    let mut receiver = secured_pib(RECEIVER_ADDRESS, SENDER_ADDRESS);
+    let frame = data_frame(SecurityLevel::Mic64);
+    let secured = secure_frame(&mut sender, frame.clone()).unwrap();
+    let plain: Vec<u8> = {
+        let mut frame = frame;
+        frame.security.as_mut().unwrap().frame_counter = 0;
+        frame.pack(VecPackTarget::new()).unwrap().into()
+    };
+    // Without encryption, only the integrity code is added.
+    assert_eq!(&secured[..secured.len() - 8], &plain[..]);
+
+    let mut tampered = secured.clone();
+    tampered[secured.len() - 9] ^= 0x01;
+    assert_eq!(
+        unsecure_frame(&mut receiver, &tampered),
+        Err(Error::SecurityError)
+    );
+    assert_eq!(receiver.mac_device_table[0].frame_counter, 0);
+    assert_eq!(unsecure_frame(&mut receiver, &secured), Ok(plain));
+}
+
+#[test]
+fn test_secure_frame_unavailable() {
+    let mut sender = secured_pib(SENDER_ADDRESS, RECEIVER_ADDRESS);
+    let mut frame = data_frame(SecurityLevel::EncMic64);
+    frame.security.as_mut().unwrap().key_identifier = KeyIdentifier::Index(2);
+    assert_eq!(secure_frame(&mut sender, frame), Err(Error::UnavailableKey));
+    assert_eq!(sender.mac_frame_counter, 0);
+
+    // A device we do not know about can not have its frames checked.
+    let secured = secure_frame(&mut sender, data_frame(SecurityLevel::EncMic64)).unwrap();
+    let mut receiver = secured_pib(RECEIVER_ADDRESS, 0xACDE480000000003);
+    assert_eq!(
+        unsecure_frame(&mut receiver, &secured),
+        Err(Error::UnavailableKey)
+    );
+
+    sender.mac_security_enabled = false;
+    assert_eq!(
+        check_outgoing(&sender, SecurityLevel::EncMic64, KeyIdentifier::Index(1)),
+        Err(Error::UnsupportedSecurity)
+    );
+}
@@ -1,7 +1,9 @@
-#[allow(dead_code)] // API
-#[derive(Debug)]
+use crate::ieee802154::frame::AssociationError;
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     ChannelAccessFailure,
+    RadioFailure, // The radio did not send the frame, or did not answer
     FrameTooLong,
     ReadOnly,
     UnsupportedAttribute,
@@ -11,4 +13,13 @@ pub enum Error {
     NoShortAddress,
     TransactionExpired,
     NoAck,
+    NoBeacon,
+    ScanInProgress,
+    LimitReached,
+    NoData,
+    UnsupportedSecurity, // Security is disabled, or the requested level is not supported
+    UnavailableKey,      // No key or device table entry for a secured frame
+    CounterError,        // Frame counter exhausted, or a frame was replayed
+    SecurityError,       // Integrity check of a received frame failed
+    Association(AssociationError), // Association was denied by the coordinator
 }
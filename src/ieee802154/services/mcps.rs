@@ -1,4 +1,6 @@
-use crate::ieee802154::frame::{AddressingMode, FullAddress};
+use crate::ieee802154::frame::{
+    AddressingMode, FullAddress, HeaderIE, KeyIdentifier, PayloadIE, SecurityLevel,
+};
 pub use crate::ieee802154::services::error::Error;
 use crate::unique_key::UniqueKey;
 
@@ -11,6 +13,12 @@ impl MsduHandle {
     }
 }
 
+impl Default for MsduHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct DataRequest {
     pub source_addressing_mode: AddressingMode,
@@ -19,6 +27,11 @@ pub struct DataRequest {
     pub msdu_handle: MsduHandle,
     pub ack_tx: bool,
     pub indirect_tx: bool,
+    pub security_level: SecurityLevel,
+    pub key_identifier: KeyIdentifier, // Ignored if security_level is None
+    pub seq_num_suppressed: bool,      // Not allowed together with ack_tx
+    pub header_ie_list: Vec<HeaderIE>,
+    pub payload_ie_list: Vec<PayloadIE>,
 }
 #[derive(Debug)]
 pub struct DataConfirm {
@@ -33,6 +46,10 @@ pub struct DataIndication {
     pub mpdu_link_quality: u8,
     pub dsn: Option<u8>,
     pub rssi: u8,
+    pub security_level: SecurityLevel,
+    pub key_identifier: KeyIdentifier, // Implicit if security_level is None
+    pub header_ie_list: Vec<HeaderIE>,
+    pub payload_ie_list: Vec<PayloadIE>,
 }
 #[derive(Debug)]
 pub struct PurgeRequest {
@@ -45,7 +62,6 @@ pub struct PurgeConfirm {
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Request {
     Data(DataRequest),
     Purge(PurgeRequest),
@@ -58,7 +74,6 @@ pub enum Confirm {
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Indication {
     Data(DataIndication),
 }
@@ -67,13 +82,11 @@ pub enum Indication {
 pub enum Response {}
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Input {
     Request(Request),
     Response(Response),
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub enum Output {
     Confirm(Confirm),
@@ -1,11 +1,12 @@
 use crate::ieee802154::frame::FullAddress;
-use crate::ieee802154::frame::{AssociationError, CapabilityInformation};
+use crate::ieee802154::frame::{
+    AssociationError, Beacon, CapabilityInformation, DisassociationReason, HeaderIE, PayloadIE,
+};
 use crate::ieee802154::pib::{PIBProperty, PIBValue};
 pub use crate::ieee802154::services::error::Error;
 use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
 
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BeaconType {
     Beacon,
     EnhancedBeacon,
@@ -25,8 +26,10 @@ pub struct StartRequest {
     pub superframe_order: u8,
     pub pan_coordinator: bool,
     pub battery_life_extension: bool,
+    // Broadcast a coordinator realignment, such that children follow the PAN ID or channel change
+    pub coord_realignment: bool,
     // Not supported currently:
-    // - CoordRealign*
+    // - CoordRealignSecurity*
     // - BeaconSecurity*
     // - BeaconKey *
     // - HeaderIe* PayloadIe*
@@ -37,8 +40,8 @@ pub struct BeaconRequest {
     pub channel: u16,
     pub channel_page: u16,
     pub superframe_order: usize,
-    // header_ie_list
-    // payload_ie_list
+    pub header_ie_list: Vec<HeaderIE>,
+    pub payload_ie_list: Vec<PayloadIE>,
     // header_ie_id_list
     // payload_ie_id_list
     // beacon_security_level
@@ -48,6 +51,90 @@ pub struct BeaconRequest {
     pub dst_addr: Option<FullAddress>,
     // bsn_suppression
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanType {
+    EnergyDetect,
+    Active,
+    Passive,
+    Orphan,
+    EnhancedActive, // Active scan with an Enhanced Beacon Request
+}
+#[derive(Debug)]
+pub struct ScanRequest {
+    pub scan_type: ScanType,
+    pub scan_channels: Vec<u16>,
+    pub scan_duration: u8, // Time spent on each channel is aBaseSuperframeDuration * (2^n + 1)
+    pub channel_page: u16,
+    // Not supported currently:
+    // - Security*
+    // - LinkQualityScan
+    // - FrameControlOptions
+    // - HeaderIeIdList, PayloadIeIdList
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanDescriptor {
+    pub coord_address: FullAddress,
+    pub channel_number: u16,
+    pub channel_page: u16,
+    // Superframe specification, as well as the beacon payload normally delivered through
+    // MLME-BEACON-NOTIFY. Enhanced beacons have no superframe specification, they are reported
+    // as beacons of a nonbeacon-enabled PAN, with association permit cleared.
+    pub beacon: Beacon,
+    pub link_quality: u8,
+    // IEs of the beacon, normally delivered through MLME-BEACON-NOTIFY.
+    pub header_ie_list: Vec<HeaderIE>,
+    pub payload_ie_list: Vec<PayloadIE>,
+}
+#[derive(Debug)]
+pub struct ScanConfirm {
+    pub status: Result<(), Error>,
+    pub scan_type: ScanType,
+    pub channel_page: u16,
+    pub unscanned_channels: Vec<u16>,
+    pub energy_detect_list: Vec<u8>, // In the order of the requested channels
+    pub pan_descriptor_list: Vec<PanDescriptor>,
+}
+#[derive(Debug)]
+pub struct AssociateRequest {
+    pub channel_number: u16,
+    pub channel_page: u16,
+    pub coord_address: FullAddress,
+    pub capability_information: CapabilityInformation,
+    // Not supported currently:
+    // - Security*
+    // - ChannelOffset, HoppingSequenceId
+}
+#[derive(Debug)]
+pub struct AssociateConfirm {
+    // Short address assigned by the coordinator, 0xFFFE if we should use our extended address
+    pub status: Result<ShortAddress, Error>,
+}
+#[derive(Debug)]
+pub struct DisassociateRequest {
+    // Device to remove from the PAN, or our coordinator to leave the PAN ourselves
+    pub device_address: FullAddress,
+    pub reason: DisassociationReason,
+    pub tx_indirect: bool, // Ignored when leaving
+                           // Not supported currently:
+                           // - Security*
+}
+#[derive(Debug)]
+pub struct DisassociateConfirm {
+    pub status: Result<(), Error>,
+    pub device_address: FullAddress,
+}
+#[derive(Debug)]
+pub struct PollRequest {
+    pub coord_address: FullAddress,
+    // Not supported currently:
+    // - Security*
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncLossReason {
+    PanIdConflict,
+    Realignment,
+    BeaconLost,
+}
 #[derive(Debug)]
 pub struct GetRequest {
     pub attribute: PIBProperty,
@@ -59,11 +146,14 @@ pub struct SetRequest {
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Request {
     Reset(ResetRequest),
     Start(StartRequest),
     Beacon(BeaconRequest),
+    Scan(ScanRequest),
+    Associate(AssociateRequest),
+    Disassociate(DisassociateRequest),
+    Poll(PollRequest),
     Get(GetRequest),
     Set(SetRequest),
 }
@@ -73,6 +163,11 @@ pub enum Confirm {
     Reset(Result<(), Error>),
     Start(Result<(), Error>),
     Beacon(Result<(), Error>),
+    Scan(ScanConfirm),
+    Associate(AssociateConfirm),
+    Disassociate(DisassociateConfirm),
+    // Ok if data was received, which is delivered through MCPS-DATA.indication
+    Poll(Result<(), Error>),
     Get(PIBProperty, Result<PIBValue, Error>),
     Set(PIBProperty, Result<(), Error>),
 }
@@ -83,11 +178,32 @@ pub enum Indication {
         beacon_type: BeaconType,
         src_addr: Option<FullAddress>,
         dst_pan_id: PANID,
+        header_ie_list: Vec<HeaderIE>,
+        payload_ie_list: Vec<PayloadIE>, // Holds the EB Filter IE, if any
     },
     Associate {
         device_address: ExtendedAddress,
         capability_information: CapabilityInformation, // 7.5.2
     },
+    Disassociate {
+        device_address: ExtendedAddress,
+        reason: DisassociationReason,
+    },
+    Orphan {
+        orphan_address: ExtendedAddress,
+    },
+    SyncLoss {
+        reason: SyncLossReason,
+        pan_id: PANID,
+        channel_number: u16,
+        channel_page: u16,
+    },
+    CommStatus {
+        source: FullAddress,
+        destination: FullAddress,
+        // Success, NoAck, TransactionExpired, ChannelAccessFailure or RadioFailure
+        status: Result<(), Error>,
+    },
 }
 
 #[derive(Debug)]
@@ -97,6 +213,11 @@ pub enum Response {
         fast_association: bool,
         status: Result<Option<ShortAddress>, AssociationError>,
     },
+    Orphan {
+        orphan_address: ExtendedAddress,
+        short_address: ShortAddress, // Currently assigned to the orphan
+        associated_member: bool,     // Whether the orphan was associated with us
+    },
 }
 
 #[derive(Debug)]
@@ -0,0 +1,441 @@
+use crate::ieee802154::frame::{
+    Address, AssociationError, AssociationResponse, Beacon, CapabilityInformation, Command,
+    CoordinatorRealignment, DeviceType, DisassociationReason, DsmeAssociationRequest,
+    DsmeAssociationResponse, DsmeGtsManagement, DsmeGtsManagementType, DsmeGtsReply,
+    DsmeGtsRequest, DsmeSabSpecification, EnhancedBeaconFilter, Frame, FrameType, FrameVersion,
+    FullAddress, GtsCharacteristics, GtsDescriptor, GtsDirection, HeaderIE, NestedIE, Payload,
+    PayloadIE, PowerSource, RitDataRequest, VendorSpecificCommand,
+};
+use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use crate::pack::{Pack, PackError, VecPackTarget};
+
+fn data_frame(payload: Vec<u8>) -> Frame {
+    Frame {
+        version: FrameVersion::Ieee2015,
+        frame_pending: false,
+        acknowledge_request: false,
+        sequence_number: Some(0x01),
+        destination: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress::broadcast()),
+        }),
+        source: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0001)),
+        }),
+        security: None,
+        header_ies: vec![],
+        payload_ies: vec![],
+        frame_type: FrameType::Data(Payload(payload)),
+    }
+}
+
+fn test_roundtrip<T: core::fmt::Debug + Eq + PartialEq + Pack>(input: T, packed: Vec<u8>) {
+    let (unpacked, remaining) = T::unpack(&packed).unwrap();
+    assert!(remaining.is_empty());
+    assert_eq!(unpacked, input);
+
+    let repacked: Vec<u8> = input.pack(VecPackTarget::new()).unwrap().into();
+    assert_eq!(packed, repacked);
+}
+
+fn pack_frame(frame: &Frame) -> Vec<u8> {
+    frame.pack(VecPackTarget::new()).unwrap().into()
+}
+
+#[test]
+fn test_frame_information_elements() {
+    let mut frame = data_frame(vec![0x06]);
+    frame.header_ies = vec![HeaderIE::VendorSpecific {
+        vendor_oui: [0x01, 0x02, 0x03],
+        content: vec![0x04],
+    }];
+    frame.payload_ies = vec![PayloadIE::Mlme(vec![NestedIE::Short {
+        sub_id: 0x1A,
+        content: vec![0x05],
+    }])];
+    let data = pack_frame(&frame);
+    assert_eq!(
+        data,
+        vec![
+            0x41, 0xAA, 0x01, 0x34, 0x12, 0xFF, 0xFF, 0x01, 0x00, // Header, frame version 2
+            0x04, 0x00, 0x01, 0x02, 0x03, 0x04, // Vendor specific header IE
+            0x00, 0x3F, // Header termination 1
+            0x03, 0x88, 0x01, 0x1A, 0x05, // MLME payload IE
+            0x00, 0xF8, // Payload termination
+            0x06,
+        ]
+    );
+    assert_eq!(Frame::unpack(&data), Ok((frame, &[][..])));
+}
+
+#[test]
+fn test_frame_header_termination() {
+    // Header IEs only need to be terminated if a payload follows.
+    let mut frame = data_frame(vec![]);
+    frame.header_ies = vec![HeaderIE::Other {
+        element_id: 0x1E,
+        content: vec![0xAB],
+    }];
+    let data = pack_frame(&frame);
+    assert_eq!(&data[9..], &[0x01, 0x0F, 0xAB]);
+    assert_eq!(Frame::unpack(&data), Ok((frame.clone(), &[][..])));
+
+    frame.frame_type = FrameType::Data(Payload(vec![0x06]));
+    let data = pack_frame(&frame);
+    assert_eq!(&data[9..], &[0x01, 0x0F, 0xAB, 0x80, 0x3F, 0x06]);
+    assert_eq!(Frame::unpack(&data), Ok((frame, &[][..])));
+
+    // The terminations can not be passed in as IEs of their own.
+    for element_id in [0x7E, 0x7F] {
+        let ie = HeaderIE::Other {
+            element_id,
+            content: vec![],
+        };
+        assert!(matches!(
+            ie.pack(VecPackTarget::new()),
+            Err(PackError::NotAllowed(_))
+        ));
+    }
+}
+
+#[test]
+fn test_nested_information_elements() {
+    let ie = PayloadIE::Mlme(vec![
+        NestedIE::Long {
+            sub_id: 0x9,
+            content: vec![0x01, 0x02],
+        },
+        NestedIE::Short {
+            sub_id: 0x40,
+            content: vec![],
+        },
+    ]);
+    let data: Vec<u8> = ie.pack(VecPackTarget::new()).unwrap().into();
+    assert_eq!(data, vec![0x06, 0x88, 0x02, 0xC8, 0x01, 0x02, 0x00, 0x40]);
+    assert_eq!(PayloadIE::unpack(&data), Ok((ie, &[][..])));
+
+    let ie = NestedIE::Long {
+        sub_id: 0x10,
+        content: vec![],
+    };
+    assert!(matches!(
+        ie.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+    let ie = HeaderIE::Other {
+        element_id: 0x20,
+        content: vec![0; 0x80],
+    };
+    assert!(matches!(
+        ie.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+}
+
+#[test]
+fn test_frame_version_pan_ids() {
+    // Version 2 frames between extended addresses never carry the source PAN ID.
+    let mut frame = data_frame(vec![0x06]);
+    frame.destination = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Extended(ExtendedAddress(0x0102030405060708)),
+    });
+    frame.source = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Extended(ExtendedAddress(0x1112131415161718)),
+    });
+    let data = pack_frame(&frame);
+    assert_eq!(&data[..5], &[0x01, 0xEC, 0x01, 0x34, 0x12]);
+    assert_eq!(data.len(), 5 + 8 + 8 + 1);
+    assert_eq!(Frame::unpack(&data), Ok((frame.clone(), &[][..])));
+    frame.source.as_mut().unwrap().pan_id = PANID(0x5678);
+    assert!(matches!(
+        frame.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+
+    // Older versions can carry both.
+    frame.version = FrameVersion::Ieee2006;
+    let data = pack_frame(&frame);
+    assert_eq!(&data[..5], &[0x01, 0xDC, 0x01, 0x34, 0x12]);
+    assert_eq!(data.len(), 5 + 8 + 2 + 8 + 1);
+    assert_eq!(Frame::unpack(&data), Ok((frame.clone(), &[][..])));
+
+    // Without a destination, the source PAN ID is there.
+    frame.version = FrameVersion::Ieee2015;
+    frame.destination = None;
+    let data = pack_frame(&frame);
+    assert_eq!(&data[..5], &[0x01, 0xE0, 0x01, 0x78, 0x56]);
+    assert_eq!(Frame::unpack(&data), Ok((frame, &[][..])));
+}
+
+#[test]
+fn test_frame_version_checks() {
+    let mut frame = data_frame(vec![0x06]);
+    frame.sequence_number = None;
+    frame.version = FrameVersion::Ieee2006;
+    assert!(matches!(
+        frame.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+    assert_eq!(frame.minimum_version(), FrameVersion::Ieee2015);
+    frame.version = FrameVersion::Ieee2015;
+    let data = pack_frame(&frame);
+    assert_eq!(&data[..3], &[0x41, 0xA9, 0x34]);
+    assert_eq!(Frame::unpack(&data), Ok((frame, &[][..])));
+
+    // A version 2 frame of type beacon is always an enhanced beacon.
+    let mut frame = data_frame(vec![]);
+    frame.destination = None;
+    frame.frame_type = FrameType::EnhancedBeacon(Payload(vec![0x01, 0x02]));
+    let data = pack_frame(&frame);
+    assert_eq!(&data[..2], &[0x00, 0xA0]);
+    assert_eq!(Frame::unpack(&data), Ok((frame.clone(), &[][..])));
+    frame.version = FrameVersion::Ieee2006;
+    assert!(matches!(
+        frame.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+
+    // And so is a beacon request carrying IEs.
+    let mut frame = data_frame(vec![]);
+    frame.version = FrameVersion::Ieee2003;
+    frame.frame_type = FrameType::Command(Command::BeaconRequest());
+    frame.header_ies = vec![HeaderIE::Other {
+        element_id: 0x1E,
+        content: vec![],
+    }];
+    assert_eq!(frame.minimum_version(), FrameVersion::Ieee2015);
+    assert!(matches!(
+        frame.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+}
+
+#[test]
+fn test_beacon_lists() {
+    let mut beacon = Beacon {
+        beacon_order: 15,
+        superframe_order: 15,
+        final_cap_slot: 15,
+        battery_life_extension: false,
+        pan_coordinator: true,
+        association_permit: true,
+        gts_permit: true,
+        gts_descriptors: vec![
+            GtsDescriptor {
+                short_address: ShortAddress(0x1234),
+                starting_slot: 14,
+                length: 1,
+                direction: GtsDirection::Transmit,
+            },
+            GtsDescriptor {
+                short_address: ShortAddress(0x5678),
+                starting_slot: 15,
+                length: 1,
+                direction: GtsDirection::Receive,
+            },
+        ],
+        pending_short_addresses: vec![ShortAddress(0x4567)],
+        pending_extended_addresses: vec![ExtendedAddress(0x0102030405060708)],
+        payload: Payload(vec![0xAB]),
+    };
+    let data: Vec<u8> = beacon.pack(VecPackTarget::new()).unwrap().into();
+    assert_eq!(
+        data,
+        vec![
+            0xFF, 0xCF, // Superframe specification
+            0x82, 0x02, // GTS specification and directions
+            0x34, 0x12, 0x1E, 0x78, 0x56, 0x1F, // GTS list
+            0x11, // Pending address specification
+            0x67, 0x45, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, // Pending addresses
+            0xAB,
+        ]
+    );
+    assert_eq!(Beacon::unpack(&data), Ok((beacon.clone(), &[][..])));
+
+    // Without GTSs, the directions are left out.
+    beacon.gts_descriptors.clear();
+    let data: Vec<u8> = beacon.pack(VecPackTarget::new()).unwrap().into();
+    assert_eq!(&data[2..4], &[0x80, 0x11]);
+    assert_eq!(Beacon::unpack(&data), Ok((beacon.clone(), &[][..])));
+
+    beacon.pending_short_addresses = vec![ShortAddress(0x0001); 7];
+    assert!(matches!(
+        beacon.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+}
+
+#[test]
+fn test_commands() {
+    test_roundtrip(
+        Command::AssociationRequest(CapabilityInformation {
+            device_type: DeviceType::FFD,
+            power_source: PowerSource::AC,
+            receiver_on_when_idle: true,
+            fast_association: false,
+            security_capable: false,
+            allocate_address: true,
+        }),
+        vec![0x01, 0x8E],
+    );
+    test_roundtrip(
+        Command::AssociationResponse(AssociationResponse {
+            fast_association: false,
+            status: Ok(ShortAddress(0x4567)),
+        }),
+        vec![0x02, 0x67, 0x45, 0x00],
+    );
+    test_roundtrip(
+        Command::DisassociationNotification(DisassociationReason::DeviceWishesToLeave),
+        vec![0x03, 0x02],
+    );
+    test_roundtrip(Command::DataRequest(), vec![0x04]);
+    test_roundtrip(Command::PanIdConflictNotification(), vec![0x05]);
+    test_roundtrip(Command::OrphanNotification(), vec![0x06]);
+    test_roundtrip(Command::BeaconRequest(), vec![0x07]);
+    test_roundtrip(
+        Command::CoordinatorRealignment(CoordinatorRealignment {
+            pan_id: PANID(0x1234),
+            coordinator_short_address: ShortAddress(0x0000),
+            channel_number: 15,
+            short_address: ShortAddress::broadcast(),
+            channel_page: Some(0),
+        }),
+        vec![0x08, 0x34, 0x12, 0x00, 0x00, 0x0F, 0xFF, 0xFF, 0x00],
+    );
+    test_roundtrip(
+        Command::GtsRequest(GtsCharacteristics {
+            length: 2,
+            direction: GtsDirection::Receive,
+            allocation: true,
+        }),
+        vec![0x09, 0x32],
+    );
+    test_roundtrip(
+        Command::DsmeAssociationRequest(DsmeAssociationRequest {
+            capability_information: CapabilityInformation {
+                device_type: DeviceType::RFD,
+                power_source: PowerSource::Battery,
+                receiver_on_when_idle: false,
+                fast_association: false,
+                security_capable: false,
+                allocate_address: true,
+            },
+            hopping_sequence_id: 0x01,
+            channel_offset: 0x0203,
+        }),
+        vec![0x13, 0x80, 0x01, 0x03, 0x02],
+    );
+    test_roundtrip(
+        Command::DsmeAssociationResponse(DsmeAssociationResponse {
+            status: Ok(ShortAddress(0x4567)),
+            hopping_sequence: vec![11, 15, 20],
+        }),
+        vec![0x14, 0x67, 0x45, 0x00, 0x03, 0x0B, 0x0F, 0x14],
+    );
+    test_roundtrip(
+        Command::DsmeAssociationResponse(DsmeAssociationResponse {
+            status: Err(AssociationError::PANAtCapacity),
+            hopping_sequence: vec![],
+        }),
+        vec![0x14, 0xFF, 0xFF, 0x01, 0x00],
+    );
+    let sab = DsmeSabSpecification {
+        sub_block_index: 0x0002,
+        sub_block: vec![0x0F, 0x00],
+    };
+    test_roundtrip(
+        Command::DsmeGtsRequest(DsmeGtsRequest {
+            management: DsmeGtsManagement {
+                management_type: DsmeGtsManagementType::Allocation,
+                direction: GtsDirection::Receive,
+                prioritized_channel_access: false,
+            },
+            number_of_slots: 4,
+            preferred_superframe_id: 0x0001,
+            preferred_slot_id: 0x03,
+            sab: sab.clone(),
+        }),
+        vec![
+            0x15, 0x09, 0x04, 0x01, 0x00, 0x03, 0x02, 0x02, 0x00, 0x0F, 0x00,
+        ],
+    );
+    let reply = DsmeGtsReply {
+        management: DsmeGtsManagement {
+            management_type: DsmeGtsManagementType::Deallocation,
+            direction: GtsDirection::Transmit,
+            prioritized_channel_access: true,
+        },
+        destination_address: ShortAddress(0x4567),
+        channel_offset: 0x0005,
+        sab,
+    };
+    test_roundtrip(
+        Command::DsmeGtsReply(reply.clone()),
+        vec![
+            0x16, 0x10, 0x67, 0x45, 0x05, 0x00, 0x02, 0x02, 0x00, 0x0F, 0x00,
+        ],
+    );
+    test_roundtrip(
+        Command::DsmeGtsNotify(reply),
+        vec![
+            0x17, 0x10, 0x67, 0x45, 0x05, 0x00, 0x02, 0x02, 0x00, 0x0F, 0x00,
+        ],
+    );
+    test_roundtrip(
+        Command::RitDataRequest(RitDataRequest {
+            time_to_first_listen: 0x10,
+            number_of_repeat_listen: 0x02,
+            repeat_listen_interval: 0x0100,
+        }),
+        vec![0x20, 0x10, 0x02, 0x00, 0x01],
+    );
+    test_roundtrip(
+        Command::VendorSpecific(VendorSpecificCommand {
+            vendor_oui: [0x00, 0x12, 0x4B],
+            content: vec![0xAA],
+        }),
+        vec![0x24, 0x00, 0x12, 0x4B, 0xAA],
+    );
+    // Reserved, and commands for modes the MAC does not implement.
+    for command_id in [0x0A, 0x0C, 0x18, 0x21, 0x23, 0x28] {
+        assert!(Command::unpack(&[command_id, 0x01]).is_err());
+    }
+    // Management types 6 and 7 are reserved.
+    assert!(DsmeGtsManagement::unpack(&[0x06]).is_err());
+
+    let request = GtsCharacteristics {
+        length: 0x10,
+        direction: GtsDirection::Transmit,
+        allocation: true,
+    };
+    assert!(matches!(
+        request.pack(VecPackTarget::new()),
+        Err(PackError::NotAllowed(_))
+    ));
+}
+
+#[test]
+fn test_enhanced_beacon_filter() {
+    test_roundtrip(
+        EnhancedBeaconFilter {
+            permit_joining_on: true,
+            link_quality: Some(0x80),
+            percent_filter: None,
+            pib_identifiers: vec![0x4F],
+        },
+        vec![0x0B, 0x80, 0x4F],
+    );
+    test_roundtrip(
+        EnhancedBeaconFilter {
+            permit_joining_on: false,
+            link_quality: None,
+            percent_filter: Some(50),
+            pib_identifiers: vec![],
+        },
+        vec![0x04, 0x32],
+    );
+}
@@ -0,0 +1,13 @@
+/*!
+ * Zigbee coordinator building blocks: a driver for radios running the Contiki serial bridge
+ * firmware, an 802.15.4 MAC on top of it, and the packing framework used for the frames.
+ *
+ * The MAC is started with ieee802154::mac::service::start, and is driven through the
+ * MLME and MCPS service primitives in ieee802154::services.
+ */
+pub mod async_std_executor;
+pub mod ieee802154;
+pub mod pack;
+pub mod radio;
+pub mod unique_key;
+mod waker_store;
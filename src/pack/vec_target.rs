@@ -20,6 +20,12 @@ impl VecPackTarget {
     }
 }
 
+impl Default for VecPackTarget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Into<Vec<u8>> for VecPackTarget {
     fn into(self) -> Vec<u8> {
         self.0
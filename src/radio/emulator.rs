@@ -0,0 +1,239 @@
+use crate::radio::raw::{
+    RawRadioCommand, RawRadioMessage, RawRadioParam, RawRadioSink, RawRadioStats, RawRadioStream,
+};
+use crate::radio::simulated::{
+    SimulatedAir, SimulatedRadio, SimulatedRadioConfig, SimulatedRadioWatch, SimulatedReception,
+    SimulatedTransmission,
+};
+use crate::radio::{RadioError, RadioParamType, RadioParamValue, RadioRequest, RadioResponse};
+use crate::unique_key::UniqueKey;
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::select;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use futures::task::{Spawn, SpawnExt};
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+
+fn parse_param(data: &[u8]) -> Option<(RawRadioParam, &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let param = u16::from_be_bytes(data[0..2].try_into().unwrap());
+    let param = RawRadioParam::try_from(param).ok()?;
+    Some((param, &data[2..]))
+}
+
+/**
+ * Inverse of RadioRequest::into_raw, turns a message as received by the firmware back into a
+ * request. Returns None for commands the firmware would reject.
+ */
+fn parse_request(message: RawRadioMessage) -> Option<RadioRequest> {
+    let key = UniqueKey::new();
+    let data = message.data;
+    match message.command_id {
+        RawRadioCommand::GetValue => {
+            let (param, rest) = parse_param(&data)?;
+            if rest.is_empty() {
+                Some(RadioRequest::GetParam(key, param, RadioParamType::U16))
+            } else {
+                None
+            }
+        }
+        RawRadioCommand::GetObject => {
+            let (param, rest) = parse_param(&data)?;
+            let size = u16::from_be_bytes(rest.try_into().ok()?);
+            let param_type = match size {
+                4 => RadioParamType::U32,
+                8 => RadioParamType::U64,
+                _ => return None,
+            };
+            Some(RadioRequest::GetParam(key, param, param_type))
+        }
+        RawRadioCommand::SetValue => {
+            let (param, rest) = parse_param(&data)?;
+            let value = u16::from_be_bytes(rest.try_into().ok()?);
+            Some(RadioRequest::SetParam(key, param, value.into()))
+        }
+        RawRadioCommand::SetObject => {
+            let (param, rest) = parse_param(&data)?;
+            let value = match rest.len() {
+                4 => u32::from_be_bytes(rest.try_into().unwrap()).into(),
+                8 => u64::from_be_bytes(rest.try_into().unwrap()).into(),
+                _ => return None,
+            };
+            Some(RadioRequest::SetParam(key, param, value))
+        }
+        RawRadioCommand::InitPendingTable => Some(RadioRequest::InitPendingDataTable(key)),
+        RawRadioCommand::On => Some(RadioRequest::SetPower(key, true)),
+        RawRadioCommand::Off => Some(RadioRequest::SetPower(key, false)),
+        RawRadioCommand::Send => Some(RadioRequest::SendPacket(key, data)),
+        RawRadioCommand::ChannelClear => Some(RadioRequest::ChannelClear(key)),
+        RawRadioCommand::SetPending => {
+            let (index, rest) = data.split_first()?;
+            let extended = (index & 0x80) != 0;
+            let index = (index & 0x7F) as usize;
+            match (extended, rest.len()) {
+                (false, 0) => Some(RadioRequest::SetPendingShort(key, index, None)),
+                (false, 4) => Some(RadioRequest::SetPendingShort(
+                    key,
+                    index,
+                    Some((
+                        u16::from_le_bytes(rest[0..2].try_into().unwrap()),
+                        u16::from_le_bytes(rest[2..4].try_into().unwrap()),
+                    )),
+                )),
+                (true, 0) => Some(RadioRequest::SetPendingExtended(key, index, None)),
+                (true, 8) => Some(RadioRequest::SetPendingExtended(
+                    key,
+                    index,
+                    Some(u64::from_le_bytes(rest.try_into().unwrap())),
+                )),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn encode_result(
+    success_retval: u16,
+    result: Result<Vec<u8>, RadioError>,
+) -> (RawRadioCommand, Vec<u8>) {
+    let (retval, mut value) = match result {
+        Ok(value) => (success_retval, value),
+        Err(RadioError::UnexpectedRetvalError(retval, value)) => (retval, value),
+        Err(_) => return (RawRadioCommand::Err, Vec::new()),
+    };
+    let mut data = retval.to_be_bytes().to_vec();
+    data.append(&mut value);
+    (RawRadioCommand::Ok, data)
+}
+
+fn encode_value(value: RadioParamValue) -> Vec<u8> {
+    match value {
+        RadioParamValue::U16(v) => v.to_be_bytes().to_vec(),
+        RadioParamValue::U32(v) => v.to_be_bytes().to_vec(),
+        RadioParamValue::U64(v) => v.to_be_bytes().to_vec(),
+    }
+}
+
+/**
+ * Inverse of the response parsers in RadioRequest::into_raw.
+ */
+fn encode_response(response: RadioResponse) -> (RawRadioCommand, Vec<u8>) {
+    match response {
+        RadioResponse::GetParam(_, _, result) => encode_result(0, result.map(encode_value)),
+        RadioResponse::SetParam(_, _, result) => encode_result(0, result.map(|_| Vec::new())),
+        // Contiki's radio on() and off() return 1 on success
+        RadioResponse::SetPower(_, _, result) => encode_result(1, result.map(|_| Vec::new())),
+        // And channel_clear() returns 1 if the channel is clear
+        RadioResponse::ChannelClear(_, Ok(clear)) => encode_result(clear as u16, Ok(Vec::new())),
+        RadioResponse::ChannelClear(_, Err(e)) => encode_result(1, Err(e)),
+        RadioResponse::InitPendingDataTable(_, result)
+        | RadioResponse::SendPacket(_, result)
+        | RadioResponse::SetPendingShort(_, result)
+        | RadioResponse::SetPendingExtended(_, result) => {
+            encode_result(0, result.map(|_| Vec::new()))
+        }
+        RadioResponse::OnPacket(packet) => {
+            let mut data = packet.data;
+            data.push(packet.rssi);
+            data.push(packet.link_quality);
+            (RawRadioCommand::OnPacket, data)
+        }
+        // Only produced on the host side, there is nothing the bridge could send for it
+        RadioResponse::Reconnected => (RawRadioCommand::Err, Vec::new()),
+    }
+}
+
+async fn emulator_task<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    read: R,
+    write: W,
+    radio: SimulatedRadioWatch,
+    receptions: mpsc::UnboundedReceiver<SimulatedReception>,
+    transmissions: mpsc::UnboundedSender<SimulatedTransmission>,
+) {
+    let mut port_in = RawRadioStream::new(read, Arc::new(RawRadioStats::default())).fuse();
+    let mut port_out = RawRadioSink::new(write);
+    let mut receptions = receptions.fuse();
+    loop {
+        let (message, transmission) = select! {
+            message = port_in.next() => match message {
+                Some(Ok(message)) => {
+                    let request_id = message.request_id;
+                    let (response, transmission) = match parse_request(message) {
+                        Some(request) => {
+                            let (response, transmission) =
+                                radio.update(|radio| radio.process_request(request));
+                            (Some(encode_response(response)), transmission)
+                        }
+                        None => (Some((RawRadioCommand::Err, Vec::new())), None),
+                    };
+                    (response.map(|(command_id, data)| RawRadioMessage {
+                        command_id,
+                        request_id,
+                        data,
+                    }), transmission)
+                }
+                Some(Err(e)) => {
+                    println!("[EMULATOR] Dropped frame: {:?}", e);
+                    (None, None)
+                }
+                None => break,
+            },
+            reception = receptions.next() => match reception {
+                Some(reception) => {
+                    let (packet, ack) = radio.update(|radio| radio.process_reception(reception));
+                    (packet.map(|packet| {
+                        let (command_id, data) = encode_response(RadioResponse::OnPacket(packet));
+                        RawRadioMessage {
+                            command_id,
+                            request_id: 0,
+                            data,
+                        }
+                    }), ack)
+                }
+                None => (None, None),
+            },
+        };
+        if let Some(transmission) = transmission {
+            transmissions.unbounded_send(transmission).unwrap_or(());
+        }
+        if let Some(message) = message {
+            if let Err(e) = port_out.send(message).await {
+                println!("[EMULATOR] Unable to send: {:?}", e);
+                break;
+            }
+        }
+    }
+    println!("[EMULATOR] Serial port closed, stopping emulator");
+}
+
+/**
+ * Emulates the Contiki serial bridge firmware on the device side of a serial link,
+ * such that start_radio can talk to it as it would to a real dongle.
+ * The radio itself is a SimulatedRadio, of which the air-side is returned.
+ */
+pub fn start_emulator<
+    S: Spawn,
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+>(
+    executor: S,
+    read: R,
+    write: W,
+    config: SimulatedRadioConfig,
+) -> SimulatedAir {
+    let (transmissions_in, transmissions_out) = mpsc::unbounded();
+    let (receptions_in, receptions_out) = mpsc::unbounded();
+    let watch = SimulatedRadioWatch::new(SimulatedRadio::new(config));
+    let task = emulator_task(read, write, watch.clone(), receptions_out, transmissions_in);
+    executor.spawn(task).unwrap();
+    SimulatedAir {
+        transmissions: transmissions_out,
+        receptions: receptions_in,
+        watch,
+    }
+}
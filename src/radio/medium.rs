@@ -0,0 +1,243 @@
+use crate::radio::simulated::{
+    start_simulated_radio, SimulatedAir, SimulatedRadioConfig, SimulatedRadioWatch,
+    SimulatedReception, SimulatedTransmission,
+};
+use crate::radio::{RadioPacket, RadioRequest, RadioResponse};
+use futures::channel::mpsc;
+use futures::future::FutureExt;
+use futures::sink::Sink;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Spawn, SpawnExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/**
+ * Properties of a one-directional link between two nodes on the medium.
+ */
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    pub loss: f64, // Probability (0.0 - 1.0) that a frame is not heard at all
+    pub rssi: u8,
+    pub link_quality: u8,
+    pub latency: Duration,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            rssi: 0xC0,
+            link_quality: 0xFF,
+            latency: Duration::from_millis(0),
+        }
+    }
+}
+
+/**
+ * A reception on its way to a node, not to be delivered before the due time.
+ */
+type Delivery = (Instant, SimulatedReception);
+
+struct MediumState {
+    nodes: Vec<mpsc::UnboundedSender<Delivery>>, // Ordered queue of deliveries per node
+    watches: Vec<SimulatedRadioWatch>,
+    links: HashMap<(NodeId, NodeId), Option<LinkConfig>>,
+    default_link: Option<LinkConfig>,
+    monitors: Vec<mpsc::UnboundedSender<(NodeId, SimulatedTransmission)>>,
+    rng: StdRng,
+    router: Option<mpsc::UnboundedSender<(NodeId, SimulatedTransmission)>>,
+}
+
+impl MediumState {
+    fn link(&self, from: NodeId, to: NodeId) -> Option<LinkConfig> {
+        self.links
+            .get(&(from, to))
+            .cloned()
+            .unwrap_or_else(|| self.default_link.clone())
+    }
+
+    /**
+     * Decides which nodes hear a transmission, and queues it for them with the link latency.
+     */
+    fn route(&mut self, from: NodeId, transmission: &SimulatedTransmission) {
+        self.monitors
+            .retain(|monitor| monitor.unbounded_send((from, transmission.clone())).is_ok());
+        let now = Instant::now();
+        for index in 0..self.nodes.len() {
+            let to = NodeId(index);
+            if to == from {
+                continue;
+            }
+            if let Some(link) = self.link(from, to) {
+                if link.loss > 0.0 && self.rng.gen::<f64>() < link.loss {
+                    continue;
+                }
+                let reception = SimulatedReception {
+                    channel: transmission.channel,
+                    packet: RadioPacket {
+                        data: transmission.data.clone(),
+                        rssi: link.rssi,
+                        link_quality: link.link_quality,
+                    },
+                };
+                self.nodes[index]
+                    .unbounded_send((now + link.latency, reception))
+                    .unwrap_or(());
+            }
+        }
+    }
+}
+
+/**
+ * A shared virtual air interface. Every simulated radio attached to it hears the transmissions
+ * of all other radios on the same channel, subject to the configured link properties.
+ * All transmissions pass through a single task, which decides on frame loss with a seeded
+ * random generator, such that runs are reproducible. Frames reach each node in the order they
+ * were sent.
+ */
+#[derive(Clone)]
+pub struct VirtualMedium {
+    state: Arc<Mutex<MediumState>>,
+}
+
+impl VirtualMedium {
+    pub fn new(default_link: Option<LinkConfig>, seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MediumState {
+                nodes: Vec::new(),
+                watches: Vec::new(),
+                links: HashMap::new(),
+                default_link,
+                monitors: Vec::new(),
+                rng: StdRng::seed_from_u64(seed),
+                router: None,
+            })),
+        }
+    }
+
+    /**
+     * Sets the properties of the link from one node to another, None meaning out of range.
+     */
+    pub fn set_link(&self, from: NodeId, to: NodeId, link: Option<LinkConfig>) {
+        self.state.lock().unwrap().links.insert((from, to), link);
+    }
+
+    pub fn set_link_symmetric(&self, a: NodeId, b: NodeId, link: Option<LinkConfig>) {
+        self.set_link(a, b, link.clone());
+        self.set_link(b, a, link);
+    }
+
+    /**
+     * Returns a stream of every transmission on the medium, regardless of channel or links.
+     */
+    pub fn monitor(&self) -> mpsc::UnboundedReceiver<(NodeId, SimulatedTransmission)> {
+        let (monitor_in, monitor_out) = mpsc::unbounded();
+        self.state.lock().unwrap().monitors.push(monitor_in);
+        monitor_out
+    }
+
+    /**
+     * Returns access to the state of the radio of a node.
+     */
+    pub fn watch(&self, node: NodeId) -> SimulatedRadioWatch {
+        self.state.lock().unwrap().watches[node.0].clone()
+    }
+
+    /**
+     * Attaches the air-side of a simulated radio to the medium.
+     */
+    pub fn attach<S: Spawn + Clone + Send + 'static>(
+        &self,
+        executor: &S,
+        air: SimulatedAir,
+    ) -> NodeId {
+        let SimulatedAir {
+            transmissions,
+            receptions,
+            watch,
+        } = air;
+        let (deliveries_in, deliveries_out) = mpsc::unbounded();
+        let (node_id, router) = {
+            let mut state = self.state.lock().unwrap();
+            state.nodes.push(deliveries_in);
+            state.watches.push(watch);
+            let router = match &state.router {
+                Some(router) => router.clone(),
+                None => {
+                    let (router_in, router_out) = mpsc::unbounded();
+                    executor
+                        .spawn(medium_task(self.state.clone(), router_out))
+                        .unwrap();
+                    state.router = Some(router_in.clone());
+                    router_in
+                }
+            };
+            (NodeId(state.nodes.len() - 1), router)
+        };
+        executor
+            .spawn(
+                transmissions
+                    .map(move |transmission| Ok::<_, mpsc::SendError>((node_id, transmission)))
+                    .forward(router)
+                    .map(|_| ()),
+            )
+            .unwrap();
+        executor
+            .spawn(medium_delivery_task(deliveries_out, receptions))
+            .unwrap();
+        node_id
+    }
+
+    /**
+     * Starts a simulated radio and attaches it to the medium in one go.
+     */
+    pub fn start_node<S: Spawn + Clone + Send + 'static>(
+        &self,
+        executor: &S,
+        config: SimulatedRadioConfig,
+    ) -> (
+        impl Sink<RadioRequest, Error = mpsc::SendError>,
+        impl Stream<Item = RadioResponse>,
+        NodeId,
+    ) {
+        let (requests, responses, air) = start_simulated_radio(executor.clone(), config);
+        let node_id = self.attach(executor, air);
+        (requests, responses, node_id)
+    }
+}
+
+/**
+ * Routes the transmissions of all nodes, one at a time.
+ */
+async fn medium_task(
+    state: Arc<Mutex<MediumState>>,
+    mut transmissions: mpsc::UnboundedReceiver<(NodeId, SimulatedTransmission)>,
+) {
+    while let Some((from, transmission)) = transmissions.next().await {
+        state.lock().unwrap().route(from, &transmission);
+    }
+}
+
+/**
+ * Hands the receptions for a node to its radio in order, each once it is due.
+ */
+async fn medium_delivery_task(
+    mut deliveries: mpsc::UnboundedReceiver<Delivery>,
+    receptions: mpsc::UnboundedSender<SimulatedReception>,
+) {
+    while let Some((due, reception)) = deliveries.next().await {
+        let now = Instant::now();
+        if due > now {
+            async_std::task::sleep(due - now).await;
+        }
+        if receptions.unbounded_send(reception).is_err() {
+            break;
+        }
+    }
+}
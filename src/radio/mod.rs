@@ -1,6 +1,11 @@
+pub mod emulator;
+pub mod medium;
 pub mod raw;
+pub mod simulated;
+#[cfg(test)]
+mod tests;
 use crate::radio::raw::{
-    RawRadioCommand, RawRadioMessage, RawRadioParam, RawRadioSink, RawRadioStream,
+    RawRadioCommand, RawRadioMessage, RawRadioParam, RawRadioSink, RawRadioStats, RawRadioStream,
 };
 use crate::unique_key::UniqueKey;
 use async_std::sync::Mutex;
@@ -14,11 +19,23 @@ use rand::prelude::*;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::marker::Unpin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/**
+ * Time the dongle gets to answer a request, after which it is answered with RadioError::Timeout.
+ */
+const RADIO_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const RADIO_REQUEST_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+/**
+ * Delays between attempts to reopen a lost radio, doubling on each failed attempt.
+ */
+const RADIO_RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const RADIO_RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
 
 pub type RadioParam = RawRadioParam;
 
 #[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
 pub enum RadioParamType {
     U16,
     U32,
@@ -141,6 +158,7 @@ pub enum RadioRequest {
     SendPacket(UniqueKey, Vec<u8>),
     SetPendingShort(UniqueKey, usize, Option<(u16, u16)>),
     SetPendingExtended(UniqueKey, usize, Option<u64>),
+    ChannelClear(UniqueKey), // Clear channel assessment
 }
 
 #[derive(Debug)]
@@ -153,9 +171,11 @@ pub enum RadioResponse {
     OnPacket(RadioPacket),
     SetPendingShort(UniqueKey, Result<(), RadioError>),
     SetPendingExtended(UniqueKey, Result<(), RadioError>),
+    ChannelClear(UniqueKey, Result<bool, RadioError>), // True if the channel is clear
+    Reconnected, // Radio was lost and reopened, and has lost all state.
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RadioPacket {
     pub data: Vec<u8>,
     pub rssi: u8,
@@ -168,6 +188,8 @@ pub enum RadioError {
     UnexpectedRetvalError(u16, Vec<u8>),
     UnexpectedResponse,
     UnexpectedResponseSize,
+    Timeout,
+    Disconnected,
 }
 
 impl From<std::array::TryFromSliceError> for RadioError {
@@ -178,6 +200,18 @@ impl From<std::array::TryFromSliceError> for RadioError {
 
 type RadioResponseParser = Box<dyn FnOnce(Result<&[u8], RadioError>) -> RadioResponse + Send>;
 
+struct PendingRadioRequest {
+    deadline: Instant,
+    parser: RadioResponseParser,
+}
+
+type RadioResponseMap = Mutex<HashMap<u16, PendingRadioRequest>>;
+
+enum RadioConnectionEnd {
+    RequestsDriedUp,
+    Disconnected,
+}
+
 fn check_retval(expected_retval: u16, data: &[u8]) -> Result<&[u8], RadioError> {
     const U16_SIZE: usize = std::mem::size_of::<u16>();
     if data.len() < U16_SIZE {
@@ -228,7 +262,48 @@ fn unpack_result_only_retval(
     }
 }
 
+/**
+ * Contiki's channel_clear() returns 1 if the channel is clear, and 0 if it is busy.
+ */
+fn unpack_result_channel_clear(response: Result<&[u8], RadioError>) -> Result<bool, RadioError> {
+    let data = response?;
+    match check_retval(1, data) {
+        Ok([]) => Ok(true),
+        Ok(_) => Err(RadioError::UnexpectedResponse),
+        Err(RadioError::UnexpectedRetvalError(0, data)) if data.is_empty() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
 impl RadioRequest {
+    /**
+     * The response to this request when the radio could not be asked at all.
+     */
+    pub fn failed(&self, error: RadioError) -> RadioResponse {
+        match *self {
+            RadioRequest::SetParam(token, param, _) => {
+                RadioResponse::SetParam(token, param, Err(error))
+            }
+            RadioRequest::GetParam(token, param, _) => {
+                RadioResponse::GetParam(token, param, Err(error))
+            }
+            RadioRequest::InitPendingDataTable(token) => {
+                RadioResponse::InitPendingDataTable(token, Err(error))
+            }
+            RadioRequest::SetPower(token, power) => {
+                RadioResponse::SetPower(token, power, Err(error))
+            }
+            RadioRequest::SendPacket(token, _) => RadioResponse::SendPacket(token, Err(error)),
+            RadioRequest::SetPendingShort(token, _, _) => {
+                RadioResponse::SetPendingShort(token, Err(error))
+            }
+            RadioRequest::SetPendingExtended(token, _, _) => {
+                RadioResponse::SetPendingExtended(token, Err(error))
+            }
+            RadioRequest::ChannelClear(token) => RadioResponse::ChannelClear(token, Err(error)),
+        }
+    }
+
     fn into_raw(self) -> (raw::RawRadioCommand, Vec<u8>, RadioResponseParser) {
         match self {
             RadioRequest::SetParam(token, param, param_value) => (
@@ -334,6 +409,13 @@ impl RadioRequest {
                     RadioResponse::SetPendingExtended(token, unpack_result_only_retval(0, response))
                 }),
             ),
+            RadioRequest::ChannelClear(token) => (
+                RawRadioCommand::ChannelClear,
+                Vec::new(),
+                Box::new(move |response| {
+                    RadioResponse::ChannelClear(token, unpack_result_channel_clear(response))
+                }),
+            ),
         }
     }
 }
@@ -341,8 +423,8 @@ impl RadioRequest {
 async fn radio_request_task<W: AsyncWrite + Unpin, S: Stream<Item = RadioRequest> + Unpin>(
     port: W,
     mut requests: S,
-    responsemap: &Mutex<HashMap<u16, RadioResponseParser>>,
-) {
+    responsemap: &RadioResponseMap,
+) -> RadioConnectionEnd {
     let mut port = RawRadioSink::new(port);
     while let Some(request) = requests.next().await {
         // Generate a request ID.
@@ -355,7 +437,13 @@ async fn radio_request_task<W: AsyncWrite + Unpin, S: Stream<Item = RadioRequest
                     break potential_id;
                 }
             };
-            responsemap.insert(request_id, response_parser);
+            responsemap.insert(
+                request_id,
+                PendingRadioRequest {
+                    deadline: Instant::now() + RADIO_REQUEST_TIMEOUT,
+                    parser: response_parser,
+                },
+            );
             request_id
         };
         let request = raw::RawRadioMessage {
@@ -365,68 +453,149 @@ async fn radio_request_task<W: AsyncWrite + Unpin, S: Stream<Item = RadioRequest
         };
         if let Err(e) = port.send(request).await {
             println!("Unable to send: {:?}", e);
+            return RadioConnectionEnd::Disconnected;
         }
     }
     println!("Radio: Requests dried up, stopping service");
+    RadioConnectionEnd::RequestsDriedUp
+}
+
+/**
+ * Answers all requests the dongle failed to answer in time with RadioError::Timeout.
+ */
+async fn sweep_expired_requests<S: Sink<RadioResponse> + Unpin>(
+    responses: &mut S,
+    responsemap: &RadioResponseMap,
+) {
+    let expired: Vec<PendingRadioRequest> = {
+        let mut responsemap = responsemap.lock().await;
+        let now = Instant::now();
+        let expired_ids: Vec<u16> = responsemap
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(request_id, _)| *request_id)
+            .collect();
+        expired_ids
+            .iter()
+            .filter_map(|request_id| responsemap.remove(request_id))
+            .collect()
+    };
+    for pending in expired {
+        println!("Radio request timed out");
+        responses
+            .send((pending.parser)(Err(RadioError::Timeout)))
+            .await
+            .unwrap_or(());
+    }
 }
 
 async fn radio_response_task<R: AsyncRead + Unpin, S: Sink<RadioResponse> + Unpin>(
     port: R,
     mut responses: S,
-    responsemap: &Mutex<HashMap<u16, RadioResponseParser>>,
-) {
-    let mut port = RawRadioStream::new(port);
+    responsemap: &RadioResponseMap,
+    stats: Arc<RawRadioStats>,
+) -> RadioConnectionEnd {
+    let mut port = RawRadioStream::new(port, stats);
     loop {
-        let RawRadioMessage {
-            command_id,
-            request_id,
-            data,
-        } = port.next().await.unwrap();
-        match command_id {
-            RawRadioCommand::Ok => {
-                if let Some(parser) = responsemap.lock().await.remove(&request_id) {
-                    responses.send(parser(Ok(&data))).await.unwrap_or(());
-                } else {
-                    println!(
-                        "Unable to find response parser for request_id {}",
-                        request_id
-                    );
+        sweep_expired_requests(&mut responses, responsemap).await;
+        if let Ok(message) =
+            async_std::future::timeout(RADIO_REQUEST_SWEEP_INTERVAL, port.next()).await
+        {
+            let RawRadioMessage {
+                command_id,
+                request_id,
+                data,
+            } = match message {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    println!("Dropped frame from radio: {:?}", e);
+                    continue;
                 }
-            }
-            RawRadioCommand::Err => {
-                println!("Received Err");
-                if let Some(parser) = responsemap.lock().await.remove(&request_id) {
-                    responses
-                        .send(parser(Err(RadioError::RawError(data))))
-                        .await
-                        .unwrap_or(());
+                None => return RadioConnectionEnd::Disconnected,
+            };
+            match command_id {
+                RawRadioCommand::Ok => {
+                    if let Some(pending) = responsemap.lock().await.remove(&request_id) {
+                        responses
+                            .send((pending.parser)(Ok(&data)))
+                            .await
+                            .unwrap_or(());
+                    } else {
+                        println!(
+                            "Unable to find response parser for request_id {}",
+                            request_id
+                        );
+                    }
                 }
-            }
-            RawRadioCommand::OnPacket => {
-                if data.len() < 2 {
-                    println!("RADIO: Packet received without postfix");
-                } else {
-                    let mut data = data;
-                    let link_quality = data.pop().unwrap();
-                    let rssi = data.pop().unwrap();
-                    let packet = RadioPacket {
-                        data,
-                        link_quality,
-                        rssi,
-                    };
-                    responses
-                        .send(RadioResponse::OnPacket(packet))
-                        .await
-                        .unwrap_or(());
+                RawRadioCommand::Err => {
+                    println!("Received Err");
+                    if let Some(pending) = responsemap.lock().await.remove(&request_id) {
+                        responses
+                            .send((pending.parser)(Err(RadioError::RawError(data))))
+                            .await
+                            .unwrap_or(());
+                    }
+                }
+                RawRadioCommand::OnPacket => {
+                    if data.len() < 2 {
+                        println!("RADIO: Packet received without postfix");
+                    } else {
+                        let mut data = data;
+                        let link_quality = data.pop().unwrap();
+                        let rssi = data.pop().unwrap();
+                        let packet = RadioPacket {
+                            data,
+                            link_quality,
+                            rssi,
+                        };
+                        responses
+                            .send(RadioResponse::OnPacket(packet))
+                            .await
+                            .unwrap_or(());
+                    }
+                }
+                _ => {
+                    println!("Unexpected packed from radio: {:?}", command_id);
                 }
-            }
-            _ => {
-                println!("Unexpected packed from radio: {:?}", command_id);
             }
         }
     }
 }
 
+/**
+ * Runs the radio protocol over a single connection, until either the connection is lost or no
+ * more requests will come. Requests still outstanding at that point fail with Disconnected.
+ */
+async fn radio_connection<
+    W: AsyncWrite + Unpin + Send,
+    R: AsyncRead + Unpin + Send,
+    RQ: Stream<Item = RadioRequest> + Unpin + Send,
+    RS: Sink<RadioResponse> + Unpin + Send,
+>(
+    write: W,
+    read: R,
+    requests: &mut RQ,
+    responses: &mut RS,
+    stats: Arc<RawRadioStats>,
+) -> RadioConnectionEnd {
+    let map = Mutex::new(HashMap::new());
+    let end = {
+        let a = radio_request_task(write, &mut *requests, &map);
+        let b = radio_response_task(read, &mut *responses, &map, stats);
+        futures::future::select(a.boxed(), b.boxed())
+            .await
+            .factor_first()
+            .0
+    };
+    for (_, pending) in map.into_inner().drain() {
+        responses
+            .send((pending.parser)(Err(RadioError::Disconnected)))
+            .await
+            .unwrap_or(());
+    }
+    end
+}
+
 async fn radio_service<
     W: AsyncWrite + Unpin + Send,
     R: AsyncRead + Unpin + Send,
@@ -435,17 +604,58 @@ async fn radio_service<
 >(
     write: W,
     read: R,
-    requests: RQ,
-    responses: RS,
+    mut requests: RQ,
+    mut responses: RS,
+    stats: Arc<RawRadioStats>,
 ) {
-    let map = Mutex::new(HashMap::new());
-    let a = radio_request_task(write, requests, &map);
-    let b = radio_response_task(read, responses, &map);
-    futures::future::select(a.boxed(), b.boxed()).await;
-    //futures::future::join(a, b).await;
+    radio_connection(write, read, &mut requests, &mut responses, stats).await;
     println!("[RADIO] Either request or response task quit, radio aborting");
 }
 
+async fn reconnecting_radio_service<
+    W: AsyncWrite + Unpin + Send,
+    R: AsyncRead + Unpin + Send,
+    F: FnMut() -> std::io::Result<(R, W)> + Send,
+    RQ: Stream<Item = RadioRequest> + Unpin + Send,
+    RS: Sink<RadioResponse> + Unpin + Send,
+>(
+    mut open: F,
+    mut requests: RQ,
+    mut responses: RS,
+    stats: Arc<RawRadioStats>,
+) {
+    let mut backoff = RADIO_RECONNECT_BACKOFF_MIN;
+    let mut reconnecting = false;
+    loop {
+        match open() {
+            Ok((read, write)) => {
+                if reconnecting {
+                    println!("[RADIO] Reconnected");
+                    responses
+                        .send(RadioResponse::Reconnected)
+                        .await
+                        .unwrap_or(());
+                }
+                let connected_at = Instant::now();
+                match radio_connection(write, read, &mut requests, &mut responses, stats.clone())
+                    .await
+                {
+                    RadioConnectionEnd::RequestsDriedUp => break,
+                    RadioConnectionEnd::Disconnected => println!("[RADIO] Radio lost"),
+                }
+                reconnecting = true;
+                if connected_at.elapsed() > RADIO_RECONNECT_BACKOFF_MAX {
+                    backoff = RADIO_RECONNECT_BACKOFF_MIN;
+                }
+            }
+            Err(e) => println!("[RADIO] Unable to open radio: {:?}", e),
+        }
+        async_std::task::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, RADIO_RECONNECT_BACKOFF_MAX);
+    }
+    println!("[RADIO] Requests dried up, radio stopped");
+}
+
 pub fn start_radio<
     S: Spawn,
     W: AsyncWrite + Unpin + Send + 'static,
@@ -457,10 +667,39 @@ pub fn start_radio<
 ) -> (
     impl Sink<RadioRequest, Error = mpsc::SendError>,
     impl Stream<Item = RadioResponse>,
+    Arc<RawRadioStats>,
+) {
+    let (response_in, response_out) = mpsc::unbounded();
+    let (request_in, request_out) = mpsc::channel(0);
+    let stats = Arc::new(RawRadioStats::default());
+    let task = radio_service(write, read, request_out, response_in, stats.clone());
+    executor.spawn(task).unwrap();
+    (request_in, response_out, stats)
+}
+
+/**
+ * Like start_radio, but (re)opens the connection to the radio using open,
+ * whenever it is lost. RadioResponse::Reconnected is sent out after every reconnect.
+ */
+pub fn start_radio_reconnecting<
+    S: Spawn,
+    W: AsyncWrite + Unpin + Send + 'static,
+    R: AsyncRead + Unpin + Send + 'static,
+    F: FnMut() -> std::io::Result<(R, W)> + Send + 'static,
+>(
+    executor: S,
+    open: F,
+) -> (
+    impl Sink<RadioRequest, Error = mpsc::SendError>,
+    impl Stream<Item = RadioResponse>,
+    Arc<RawRadioStats>,
 ) {
-    let (response_in, response_out) = mpsc::channel(0);
+    // Unbounded, such that failing outstanding requests never waits for the MAC,
+    // which may in turn be waiting for the radio to accept its next request.
+    let (response_in, response_out) = mpsc::unbounded();
     let (request_in, request_out) = mpsc::channel(0);
-    let task = radio_service(write, read, request_out, response_in);
+    let stats = Arc::new(RawRadioStats::default());
+    let task = reconnecting_radio_service(open, request_out, response_in, stats.clone());
     executor.spawn(task).unwrap();
-    (request_in, response_out)
+    (request_in, response_out, stats)
 }
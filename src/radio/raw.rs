@@ -6,13 +6,19 @@ use pin_project::pin_project;
 use std::convert::TryFrom;
 use std::io::Write;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 static RADIO_MAGIC_PREFIX: &[u8] = b"ZPB";
+/**
+ * Well above the largest message the bridge sends (a 127 byte packet plus RSSI and LQI),
+ * anything longer is taken to be a garbled header.
+ */
+const RADIO_MAX_DATA_LENGTH: u16 = 1024;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, ExtEnum)]
 #[tag_type(u8)]
-#[allow(dead_code)]
 pub enum RawRadioCommand {
     Prepare = 0,
     Transmit = 1,
@@ -31,33 +37,33 @@ pub enum RawRadioCommand {
     OnPacket = 0xC0,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RawRadioMessage {
     pub command_id: RawRadioCommand,
     pub request_id: u16,
     pub data: Vec<u8>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ExtEnum)]
+#[tag_type(u16)]
 pub enum RawRadioParam {
     PowerMode = 0,
-    Channel,
-    PanId,
-    ShortAddress,
-    RxMode,
-    TxMode,
-    TxPower,
-    CcaThreshold,
-    Rssi,
-    LastRssi,
-    LastLinkQuality,
-    LongAddress,
-    LastPacketTimestamp,
-    ChannelMin,
-    ChannelMax,
-    TxPowerMin,
-    TxPowerMax,
+    Channel = 1,
+    PanId = 2,
+    ShortAddress = 3,
+    RxMode = 4,
+    TxMode = 5,
+    TxPower = 6,
+    CcaThreshold = 7,
+    Rssi = 8,
+    LastRssi = 9,
+    LastLinkQuality = 10,
+    LongAddress = 11,
+    LastPacketTimestamp = 12,
+    ChannelMin = 13,
+    ChannelMax = 14,
+    TxPowerMin = 15,
+    TxPowerMax = 16,
 }
 
 pub fn gen_raw_radio_message<'a, W: Write + 'a>(
@@ -85,14 +91,64 @@ impl Into<Vec<u8>> for RawRadioMessage {
     }
 }
 
-pub fn parse_raw_radio_message(input: &[u8]) -> nom::IResult<&[u8], RawRadioMessage> {
+/**
+ * Reasons a frame from the serial bridge was dropped.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawRadioError {
+    UnknownCommand(u8),
+    InvalidLength(u16),
+    Malformed,
+}
+
+impl<I> nom::error::ParseError<I> for RawRadioError {
+    fn from_error_kind(_input: I, _kind: nom::error::ErrorKind) -> Self {
+        RawRadioError::Malformed
+    }
+
+    fn append(_input: I, _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+/**
+ * Counters for the framing layer, shared with whoever wants to keep an eye on the link quality.
+ */
+#[derive(Debug, Default)]
+pub struct RawRadioStats {
+    frames_received: AtomicUsize,
+    frames_dropped: AtomicUsize,
+    bytes_skipped: AtomicUsize,
+}
+
+impl RawRadioStats {
+    pub fn frames_received(&self) -> usize {
+        self.frames_received.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_dropped(&self) -> usize {
+        self.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_skipped(&self) -> usize {
+        self.bytes_skipped.load(Ordering::Relaxed)
+    }
+}
+
+pub fn parse_raw_radio_message(
+    input: &[u8],
+) -> nom::IResult<&[u8], RawRadioMessage, RawRadioError> {
     let (input, (_, command_id, request_id, data_len)) = nom::sequence::tuple((
         nom::bytes::streaming::tag(RADIO_MAGIC_PREFIX),
         nom::number::streaming::be_u8,
         nom::number::streaming::be_u16,
         nom::number::streaming::be_u16,
     ))(input)?;
-    let command_id: RawRadioCommand = RawRadioCommand::try_from(command_id).unwrap();
+    let command_id = RawRadioCommand::try_from(command_id)
+        .map_err(|_| nom::Err::Failure(RawRadioError::UnknownCommand(command_id)))?;
+    if data_len > RADIO_MAX_DATA_LENGTH {
+        return Err(nom::Err::Failure(RawRadioError::InvalidLength(data_len)));
+    }
     let (input, data) = nom::bytes::streaming::take(data_len as usize)(input)?;
     Ok((
         input,
@@ -171,8 +227,9 @@ impl<T: AsyncWrite> Sink<RawRadioMessage> for RawRadioSink<T> {
 pub struct RawRadioStream<T: AsyncRead> {
     #[pin]
     source: T,
-    buffer: [u8; u16::max_value() as usize],
+    buffer: Box<[u8]>,
     buffer_filled: usize,
+    stats: Arc<RawRadioStats>,
 }
 
 fn find_subsequence<T>(haystack: &[T], needle: &[T]) -> Option<usize>
@@ -184,16 +241,20 @@ where
         .position(|window| window == needle)
 }
 impl<T: AsyncRead> RawRadioStream<T> {
-    pub fn new(source: T) -> RawRadioStream<T> {
+    pub fn new(source: T, stats: Arc<RawRadioStats>) -> RawRadioStream<T> {
         RawRadioStream {
             source,
-            buffer: [0; u16::max_value() as usize],
+            buffer: vec![0; u16::MAX as usize].into_boxed_slice(),
             buffer_filled: 0,
+            stats,
         }
     }
 }
 
-fn pop_raw_message(buffer: &mut [u8]) -> (usize, Option<RawRadioMessage>) {
+fn pop_raw_message(
+    buffer: &mut [u8],
+    stats: &RawRadioStats,
+) -> (usize, Option<Result<RawRadioMessage, RawRadioError>>) {
     // Find prefix, if found, remove all before
     // If not found, only keep enough bytes in the buffer so we don't miss the tag next time.
     if buffer.len() < RADIO_MAGIC_PREFIX.len() {
@@ -201,10 +262,14 @@ fn pop_raw_message(buffer: &mut [u8]) -> (usize, Option<RawRadioMessage>) {
     }
     let buffer = match find_subsequence(buffer, RADIO_MAGIC_PREFIX) {
         None => {
+            stats
+                .bytes_skipped
+                .fetch_add(buffer.len() - RADIO_MAGIC_PREFIX.len(), Ordering::Relaxed);
             buffer.rotate_right(RADIO_MAGIC_PREFIX.len());
             return (RADIO_MAGIC_PREFIX.len(), None);
         }
         Some(index) => {
+            stats.bytes_skipped.fetch_add(index, Ordering::Relaxed);
             buffer.rotate_left(index);
             let new_len = buffer.len() - index;
             &mut buffer[0..new_len]
@@ -214,20 +279,28 @@ fn pop_raw_message(buffer: &mut [u8]) -> (usize, Option<RawRadioMessage>) {
         Ok((remaining, message)) => {
             let remaining_len = remaining.len();
             buffer.rotate_right(remaining_len);
-            (remaining_len, Some(message))
+            stats.frames_received.fetch_add(1, Ordering::Relaxed);
+            (remaining_len, Some(Ok(message)))
         }
         Err(nom::Err::Incomplete(_)) => (buffer.len(), None),
-        Err(e) => panic!("{:?}", e),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            // Skip past this prefix, and resynchronize on the next one.
+            buffer.rotate_left(1);
+            stats.frames_dropped.fetch_add(1, Ordering::Relaxed);
+            stats.bytes_skipped.fetch_add(1, Ordering::Relaxed);
+            (buffer.len() - 1, Some(Err(e)))
+        }
     }
 }
 
 impl<T: AsyncRead> Stream for RawRadioStream<T> {
-    type Item = RawRadioMessage;
+    type Item = Result<RawRadioMessage, RawRadioError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
         loop {
-            let (new_length, message) = pop_raw_message(&mut this.buffer[0..*this.buffer_filled]);
+            let (new_length, message) =
+                pop_raw_message(&mut this.buffer[0..*this.buffer_filled], this.stats);
             *this.buffer_filled = new_length;
             if let Some(message) = message {
                 return Poll::Ready(Some(message));
@@ -237,6 +310,10 @@ impl<T: AsyncRead> Stream for RawRadioStream<T> {
                 return Poll::Ready(None);
             }
             match ready!(this.source.as_mut().poll_read(cx, target_slice)) {
+                Ok(0) => {
+                    println!("End of radio stream");
+                    return Poll::Ready(None);
+                }
                 Ok(read) => {
                     *this.buffer_filled += read;
                 }
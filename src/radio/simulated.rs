@@ -0,0 +1,592 @@
+use crate::ieee802154::frame::{Address, FrameControl, FrameVersion, FullAddress};
+use crate::pack::{ExtEnum, Pack, PackTagged, UnpackError};
+use crate::radio::{
+    RadioError, RadioPacket, RadioParam, RadioParamType, RadioParamValue, RadioRequest,
+    RadioResponse, RadioRxMode,
+};
+use futures::channel::mpsc;
+use futures::select;
+use futures::sink::Sink;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Spawn, SpawnExt};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+/**
+ * Return values of the Contiki radio API, as relayed by the serial bridge.
+ */
+const RADIO_RESULT_NOT_SUPPORTED: u16 = 1;
+const RADIO_RESULT_INVALID_VALUE: u16 = 2;
+const RADIO_TX_ERR: u16 = 1;
+
+const PENDING_TABLE_SIZE_SHORT: usize = 8;
+const PENDING_TABLE_SIZE_EXTENDED: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct SimulatedRadioConfig {
+    pub extended_address: u64,
+    pub channel: u16,
+    pub tx_power_min: i16,
+    pub tx_power_max: i16,
+    pub noise_floor: i16,
+    pub channel_noise: HashMap<u16, i16>, // RSSI on channels that are busier than the noise floor
+}
+
+impl Default for SimulatedRadioConfig {
+    fn default() -> Self {
+        Self {
+            extended_address: 0x00124B0000000001,
+            channel: 26,
+            tx_power_min: -22,
+            tx_power_max: 4,
+            noise_floor: -100,
+            channel_noise: HashMap::new(),
+        }
+    }
+}
+
+/**
+ * A frame as sent out over the air by a simulated radio.
+ */
+#[derive(Debug, Clone)]
+pub struct SimulatedTransmission {
+    pub channel: u16,
+    pub data: Vec<u8>,
+}
+
+/**
+ * A frame as arriving at the antenna of a simulated radio.
+ */
+#[derive(Debug, Clone)]
+pub struct SimulatedReception {
+    pub channel: u16,
+    pub packet: RadioPacket,
+}
+
+/**
+ * The air-side of a simulated radio: everything the radio transmits (including auto-acks) comes
+ * out of transmissions, and anything sent into receptions will be heard by the radio.
+ */
+pub struct SimulatedAir {
+    pub transmissions: mpsc::UnboundedReceiver<SimulatedTransmission>,
+    pub receptions: mpsc::UnboundedSender<SimulatedReception>,
+    pub watch: SimulatedRadioWatch,
+}
+
+/**
+ * MAC header fields the radio hardware looks at for filtering and acknowledging.
+ */
+struct ReceivedHeader {
+    frame_type: u16,
+    frame_version: u16,
+    acknowledge_request: bool,
+    sequence_number: Option<u8>,
+    destination: Option<FullAddress>,
+    source: Option<FullAddress>,
+    command_id: Option<u8>,
+}
+
+fn parse_header(data: &[u8]) -> Result<ReceivedHeader, UnpackError> {
+    let (fc, data) = FrameControl::unpack(data)?;
+    let (sequence_number, data) =
+        <Option<u8>>::unpack_data(fc.sequence_number_supression() == 0, data)?;
+    let ((destination, source), data) = fc.unpack_addresses(data)?;
+    let command_id = if fc.frame_type() == 3 && fc.security_enabled() == 0 {
+        data.first().cloned()
+    } else {
+        None
+    };
+    Ok(ReceivedHeader {
+        frame_type: fc.frame_type(),
+        frame_version: fc.frame_version(),
+        acknowledge_request: fc.acknowledge_request() != 0,
+        sequence_number,
+        destination,
+        source,
+        command_id,
+    })
+}
+
+/**
+ * Software model of the CC2531 running the Contiki serial bridge.
+ * Answers radio requests the way the bridge would, including hardware address filtering,
+ * auto-acknowledgement, and setting the pending bit on acks to data requests.
+ */
+pub struct SimulatedRadio {
+    extended_address: u64,
+    noise_floor: i16,
+    channel_noise: HashMap<u16, i16>,
+    values: HashMap<RadioParam, u16>,
+    pending_short: Vec<Option<(u16, u16)>>,
+    pending_extended: Vec<Option<u64>>,
+    pending_initialized: bool, // Whether the pending table was initialized since power-up
+}
+
+impl SimulatedRadio {
+    pub fn new(config: SimulatedRadioConfig) -> Self {
+        let mut values = HashMap::new();
+        values.insert(RadioParam::PowerMode, 0);
+        values.insert(RadioParam::Channel, config.channel);
+        values.insert(RadioParam::PanId, 0xFFFF);
+        values.insert(RadioParam::ShortAddress, 0xFFFF);
+        let rx_mode: u16 = RadioParamValue::from(RadioRxMode {
+            address_filter: true,
+            autoack: true,
+            poll_mode: false,
+        })
+        .try_into()
+        .unwrap();
+        values.insert(RadioParam::RxMode, rx_mode);
+        values.insert(RadioParam::TxMode, 1);
+        values.insert(RadioParam::TxPower, config.tx_power_max as u16);
+        values.insert(RadioParam::CcaThreshold, -81_i16 as u16);
+        values.insert(RadioParam::LastRssi, config.noise_floor as u16);
+        values.insert(RadioParam::LastLinkQuality, 0);
+        values.insert(RadioParam::ChannelMin, 11);
+        values.insert(RadioParam::ChannelMax, 26);
+        values.insert(RadioParam::TxPowerMin, config.tx_power_min as u16);
+        values.insert(RadioParam::TxPowerMax, config.tx_power_max as u16);
+        Self {
+            extended_address: config.extended_address,
+            noise_floor: config.noise_floor,
+            channel_noise: config.channel_noise,
+            values,
+            pending_short: vec![None; PENDING_TABLE_SIZE_SHORT],
+            pending_extended: vec![None; PENDING_TABLE_SIZE_EXTENDED],
+            pending_initialized: false,
+        }
+    }
+
+    pub fn value(&self, param: RadioParam) -> u16 {
+        *self.values.get(&param).unwrap_or(&0)
+    }
+
+    pub fn channel(&self) -> u16 {
+        self.value(RadioParam::Channel)
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.value(RadioParam::PowerMode) != 0
+    }
+
+    fn rx_mode(&self) -> RadioRxMode {
+        let value = self.value(RadioParam::RxMode);
+        RadioRxMode {
+            address_filter: (value & 1) != 0,
+            autoack: ((value >> 1) & 1) != 0,
+            poll_mode: ((value >> 2) & 1) != 0,
+        }
+    }
+
+    fn rssi(&self) -> i16 {
+        *self
+            .channel_noise
+            .get(&self.channel())
+            .unwrap_or(&self.noise_floor)
+    }
+
+    fn get_value(&self, param: RadioParam) -> Result<u16, u16> {
+        if param == RadioParam::Rssi {
+            return Ok(self.rssi() as u16);
+        }
+        self.values
+            .get(&param)
+            .cloned()
+            .ok_or(RADIO_RESULT_NOT_SUPPORTED)
+    }
+
+    fn set_value(&mut self, param: RadioParam, value: u16) -> Result<(), u16> {
+        match param {
+            RadioParam::PowerMode => {
+                if value > 1 {
+                    return Err(RADIO_RESULT_INVALID_VALUE);
+                }
+            }
+            RadioParam::Channel => {
+                if value < self.value(RadioParam::ChannelMin)
+                    || value > self.value(RadioParam::ChannelMax)
+                {
+                    return Err(RADIO_RESULT_INVALID_VALUE);
+                }
+            }
+            RadioParam::RxMode => {
+                if value >> 3 != 0 {
+                    return Err(RADIO_RESULT_INVALID_VALUE);
+                }
+            }
+            RadioParam::TxPower => {
+                let value = value as i16;
+                if value < self.value(RadioParam::TxPowerMin) as i16
+                    || value > self.value(RadioParam::TxPowerMax) as i16
+                {
+                    return Err(RADIO_RESULT_INVALID_VALUE);
+                }
+            }
+            RadioParam::PanId
+            | RadioParam::ShortAddress
+            | RadioParam::TxMode
+            | RadioParam::CcaThreshold => (),
+            _ => return Err(RADIO_RESULT_NOT_SUPPORTED),
+        }
+        self.values.insert(param, value);
+        Ok(())
+    }
+
+    fn get_param(
+        &self,
+        param: RadioParam,
+        param_type: RadioParamType,
+    ) -> Result<RadioParamValue, RadioError> {
+        let result = match (param_type, param) {
+            (RadioParamType::U16, param) => self.get_value(param).map(RadioParamValue::U16),
+            (RadioParamType::U64, RadioParam::LongAddress) => {
+                Ok(RadioParamValue::U64(self.extended_address))
+            }
+            _ => Err(RADIO_RESULT_NOT_SUPPORTED),
+        };
+        result.map_err(|retval| RadioError::UnexpectedRetvalError(retval, Vec::new()))
+    }
+
+    fn set_param(
+        &mut self,
+        param: RadioParam,
+        value: RadioParamValue,
+    ) -> Result<RadioParamValue, RadioError> {
+        let result = match (&value, param) {
+            (RadioParamValue::U16(v), param) => self.set_value(param, *v),
+            (RadioParamValue::U64(v), RadioParam::LongAddress) => {
+                self.extended_address = *v;
+                Ok(())
+            }
+            _ => Err(RADIO_RESULT_NOT_SUPPORTED),
+        };
+        result
+            .map(|_| value)
+            .map_err(|retval| RadioError::UnexpectedRetvalError(retval, Vec::new()))
+    }
+
+    fn set_pending<T>(
+        table: &mut [Option<T>],
+        index: usize,
+        value: Option<T>,
+    ) -> Result<(), RadioError> {
+        if let Some(entry) = table.get_mut(index) {
+            *entry = value;
+            Ok(())
+        } else {
+            Err(RadioError::UnexpectedRetvalError(
+                RADIO_RESULT_INVALID_VALUE,
+                Vec::new(),
+            ))
+        }
+    }
+
+    /**
+     * Processes a request from the MAC,
+     * returns the response, and optionally a frame that was sent out over the air.
+     */
+    pub fn process_request(
+        &mut self,
+        request: RadioRequest,
+    ) -> (RadioResponse, Option<SimulatedTransmission>) {
+        match request {
+            RadioRequest::GetParam(token, param, param_type) => (
+                RadioResponse::GetParam(token, param, self.get_param(param, param_type)),
+                None,
+            ),
+            RadioRequest::SetParam(token, param, value) => (
+                RadioResponse::SetParam(token, param, self.set_param(param, value)),
+                None,
+            ),
+            RadioRequest::SetPower(token, power) => {
+                self.values.insert(RadioParam::PowerMode, power as u16);
+                (RadioResponse::SetPower(token, power, Ok(())), None)
+            }
+            RadioRequest::InitPendingDataTable(token) => {
+                self.pending_short
+                    .iter_mut()
+                    .for_each(|entry| *entry = None);
+                self.pending_extended
+                    .iter_mut()
+                    .for_each(|entry| *entry = None);
+                self.pending_initialized = true;
+                (RadioResponse::InitPendingDataTable(token, Ok(())), None)
+            }
+            RadioRequest::SetPendingShort(token, index, value) => (
+                RadioResponse::SetPendingShort(
+                    token,
+                    SimulatedRadio::set_pending(&mut self.pending_short, index, value),
+                ),
+                None,
+            ),
+            RadioRequest::SetPendingExtended(token, index, value) => (
+                RadioResponse::SetPendingExtended(
+                    token,
+                    SimulatedRadio::set_pending(&mut self.pending_extended, index, value),
+                ),
+                None,
+            ),
+            RadioRequest::ChannelClear(token) => {
+                // Energy detection only, receptions in progress do not keep the channel busy.
+                let threshold = self.value(RadioParam::CcaThreshold) as i16;
+                let clear = self.is_on() && self.rssi() < threshold;
+                (RadioResponse::ChannelClear(token, Ok(clear)), None)
+            }
+            RadioRequest::SendPacket(token, data) => {
+                if self.is_on() {
+                    (
+                        RadioResponse::SendPacket(token, Ok(())),
+                        Some(SimulatedTransmission {
+                            channel: self.channel(),
+                            data,
+                        }),
+                    )
+                } else {
+                    (
+                        RadioResponse::SendPacket(
+                            token,
+                            Err(RadioError::UnexpectedRetvalError(RADIO_TX_ERR, Vec::new())),
+                        ),
+                        None,
+                    )
+                }
+            }
+        }
+    }
+
+    /**
+     * Address filtering as done by the radio hardware, see 6.7.2 of 802.15.4-2015.
+     */
+    fn passes_address_filter(&self, header: &ReceivedHeader) -> bool {
+        let pan_id = self.value(RadioParam::PanId);
+        let short_address = self.value(RadioParam::ShortAddress);
+        match header.frame_type {
+            // Beacon
+            0 => pan_id == 0xFFFF || header.source.map(|s| s.pan_id.0) == Some(pan_id),
+            // Ack
+            2 => true,
+            _ => match header.destination {
+                Some(FullAddress {
+                    pan_id: destination_pan_id,
+                    address,
+                }) => {
+                    (destination_pan_id.0 == 0xFFFF || destination_pan_id.0 == pan_id)
+                        && match address {
+                            Address::Short(address) => {
+                                address.0 == 0xFFFF || address.0 == short_address
+                            }
+                            Address::Extended(address) => address.0 == self.extended_address,
+                        }
+                }
+                None => header.source.map(|s| s.pan_id.0) == Some(pan_id),
+            },
+        }
+    }
+
+    pub fn is_pending_table_initialized(&self) -> bool {
+        self.pending_initialized
+    }
+
+    /**
+     * Whether the pending table holds the address, such that acks to its data requests have the
+     * pending bit set.
+     */
+    pub fn is_pending(&self, source: &Option<FullAddress>) -> bool {
+        match source {
+            Some(FullAddress {
+                pan_id,
+                address: Address::Short(address),
+            }) => self.pending_short.contains(&Some((pan_id.0, address.0))),
+            Some(FullAddress {
+                address: Address::Extended(address),
+                ..
+            }) => self.pending_extended.contains(&Some(address.0)),
+            None => false,
+        }
+    }
+
+    fn create_ack(&self, header: &ReceivedHeader) -> Option<Vec<u8>> {
+        let is_broadcast = match header.destination {
+            Some(FullAddress {
+                address: Address::Short(address),
+                ..
+            }) => address.0 == 0xFFFF,
+            _ => false,
+        };
+        // The hardware only sends Imm-Acks, Enh-Acks for frames of version 2 are left to the MAC.
+        if !header.acknowledge_request
+            || is_broadcast
+            || header.frame_type == 2
+            || header.frame_version >= FrameVersion::Ieee2015.into_tag()
+        {
+            return None;
+        }
+        let sequence_number = header.sequence_number?;
+        let frame_pending = header.command_id == Some(0x04) && self.is_pending(&header.source);
+        let mut fc = FrameControl(0);
+        fc.set_frame_type(2);
+        fc.set_frame_pending(frame_pending as u16);
+        let mut ack = fc.0.to_le_bytes().to_vec();
+        ack.push(sequence_number);
+        Some(ack)
+    }
+
+    /**
+     * Processes a frame heard over the air,
+     * returns the packet to hand to the MAC (if any), and the ack sent out in response (if any).
+     */
+    pub fn process_reception(
+        &mut self,
+        reception: SimulatedReception,
+    ) -> (Option<RadioPacket>, Option<SimulatedTransmission>) {
+        if !self.is_on() || reception.channel != self.channel() {
+            return (None, None);
+        }
+        let rx_mode = self.rx_mode();
+        let (accepted, ack) = match parse_header(&reception.packet.data) {
+            Ok(header) => {
+                let accepted = !rx_mode.address_filter || self.passes_address_filter(&header);
+                let ack = if accepted && rx_mode.autoack {
+                    self.create_ack(&header)
+                } else {
+                    None
+                };
+                (accepted, ack)
+            }
+            Err(_) => (!rx_mode.address_filter, None),
+        };
+        if !accepted {
+            return (None, None);
+        }
+        self.values.insert(
+            RadioParam::LastRssi,
+            reception.packet.rssi as i8 as i16 as u16,
+        );
+        self.values.insert(
+            RadioParam::LastLinkQuality,
+            reception.packet.link_quality as u16,
+        );
+        let ack = ack.map(|data| SimulatedTransmission {
+            channel: self.channel(),
+            data,
+        });
+        (Some(reception.packet), ack)
+    }
+}
+
+/**
+ * Shared access to a running simulated radio, such that tests can wait for the MAC to have
+ * configured it instead of guessing how long that takes.
+ */
+#[derive(Clone)]
+pub struct SimulatedRadioWatch {
+    radio: Arc<Mutex<SimulatedRadio>>,
+    watchers: Arc<Mutex<Vec<mpsc::UnboundedSender<()>>>>,
+}
+
+impl SimulatedRadioWatch {
+    pub fn new(radio: SimulatedRadio) -> Self {
+        Self {
+            radio: Arc::new(Mutex::new(radio)),
+            watchers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /**
+     * Runs an operation on the radio, and wakes up everyone waiting for its state to change.
+     */
+    pub fn update<T, F: FnOnce(&mut SimulatedRadio) -> T>(&self, operation: F) -> T {
+        let result = operation(&mut self.radio.lock().unwrap());
+        self.watchers
+            .lock()
+            .unwrap()
+            .retain(|watcher| watcher.unbounded_send(()).is_ok());
+        result
+    }
+
+    /**
+     * Waits until the state of the radio satisfies the predicate.
+     */
+    pub async fn wait_until<F: Fn(&SimulatedRadio) -> bool>(&self, predicate: F) {
+        let (watcher_in, mut watcher_out) = mpsc::unbounded();
+        self.watchers.lock().unwrap().push(watcher_in);
+        while !predicate(&self.radio.lock().unwrap()) {
+            watcher_out.next().await;
+        }
+    }
+}
+
+async fn simulated_radio_task(
+    radio: SimulatedRadioWatch,
+    requests: mpsc::Receiver<RadioRequest>,
+    responses: mpsc::UnboundedSender<RadioResponse>,
+    receptions: mpsc::UnboundedReceiver<SimulatedReception>,
+    transmissions: mpsc::UnboundedSender<SimulatedTransmission>,
+) {
+    let mut requests = requests.fuse();
+    let mut receptions = receptions.fuse();
+    loop {
+        let (response, transmission) = select! {
+            request = requests.next() => match request {
+                Some(request) => {
+                    let (response, transmission) =
+                        radio.update(|radio| radio.process_request(request));
+                    (Some(response), transmission)
+                }
+                None => break,
+            },
+            reception = receptions.next() => match reception {
+                Some(reception) => {
+                    let (packet, ack) = radio.update(|radio| radio.process_reception(reception));
+                    (packet.map(RadioResponse::OnPacket), ack)
+                }
+                None => (None, None),
+            },
+        };
+        if let Some(transmission) = transmission {
+            transmissions.unbounded_send(transmission).unwrap_or(());
+        }
+        if let Some(response) = response {
+            if responses.unbounded_send(response).is_err() {
+                break;
+            }
+        }
+    }
+    println!("[SIMRADIO] Requests dried up, stopping simulated radio");
+}
+
+/**
+ * Starts a simulated radio, to be used in place of start_radio when no dongle is available.
+ */
+pub fn start_simulated_radio<S: Spawn>(
+    executor: S,
+    config: SimulatedRadioConfig,
+) -> (
+    impl Sink<RadioRequest, Error = mpsc::SendError>,
+    impl Stream<Item = RadioResponse>,
+    SimulatedAir,
+) {
+    let (response_in, response_out) = mpsc::unbounded();
+    let (request_in, request_out) = mpsc::channel(0);
+    let (transmissions_in, transmissions_out) = mpsc::unbounded();
+    let (receptions_in, receptions_out) = mpsc::unbounded();
+    let watch = SimulatedRadioWatch::new(SimulatedRadio::new(config));
+    let task = simulated_radio_task(
+        watch.clone(),
+        request_out,
+        response_in,
+        receptions_out,
+        transmissions_in,
+    );
+    executor.spawn(task).unwrap();
+    (
+        request_in,
+        response_out,
+        SimulatedAir {
+            transmissions: transmissions_out,
+            receptions: receptions_in,
+            watch,
+        },
+    )
+}
@@ -0,0 +1,454 @@
+use crate::async_std_executor::AsyncStdExecutor;
+use crate::ieee802154::frame::{
+    Address, Command, Frame, FrameType, FrameVersion, FullAddress, Payload,
+};
+use crate::ieee802154::{ExtendedAddress, ShortAddress, PANID};
+use crate::pack::{Pack, VecPackTarget};
+use crate::radio::emulator::start_emulator;
+use crate::radio::medium::{LinkConfig, VirtualMedium};
+use crate::radio::raw::{
+    RawRadioCommand, RawRadioError, RawRadioMessage, RawRadioStats, RawRadioStream,
+};
+use crate::radio::simulated::{
+    SimulatedAir, SimulatedRadio, SimulatedRadioConfig, SimulatedRadioWatch, SimulatedReception,
+    SimulatedTransmission,
+};
+use crate::radio::{
+    start_radio, RadioError, RadioPacket, RadioParam, RadioParamType, RadioParamValue,
+    RadioRequest, RadioResponse,
+};
+use crate::unique_key::UniqueKey;
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use serialport::SerialPort;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn pack_frame(frame: &Frame) -> Vec<u8> {
+    frame.pack(VecPackTarget::new()).unwrap().into()
+}
+
+fn receive(radio: &mut SimulatedRadio, data: Vec<u8>) -> (bool, Option<Vec<u8>>) {
+    let channel = radio.channel();
+    let (packet, ack) = radio.process_reception(SimulatedReception {
+        channel,
+        packet: RadioPacket {
+            data,
+            rssi: 0xC0,
+            link_quality: 200,
+        },
+    });
+    (packet.is_some(), ack.map(|ack| ack.data))
+}
+
+fn configured_radio() -> SimulatedRadio {
+    let mut radio = SimulatedRadio::new(SimulatedRadioConfig::default());
+    let requests = vec![
+        RadioRequest::SetPower(UniqueKey::new(), true),
+        RadioRequest::SetParam(UniqueKey::new(), RadioParam::PanId, 0x1234_u16.into()),
+        RadioRequest::SetParam(
+            UniqueKey::new(),
+            RadioParam::ShortAddress,
+            0x0000_u16.into(),
+        ),
+    ];
+    for request in requests {
+        radio.process_request(request);
+    }
+    radio
+}
+
+#[test]
+fn test_simulated_radio_params() {
+    let mut radio = SimulatedRadio::new(SimulatedRadioConfig {
+        extended_address: 0x0123456789ABCDEF,
+        ..Default::default()
+    });
+    match radio.process_request(RadioRequest::GetParam(
+        UniqueKey::new(),
+        RadioParam::LongAddress,
+        RadioParamType::U64,
+    )) {
+        (RadioResponse::GetParam(_, _, Ok(value)), None) => {
+            assert_eq!(value, RadioParamValue::U64(0x0123456789ABCDEF))
+        }
+        r => panic!("Unexpected response {:?}", r),
+    }
+    match radio.process_request(RadioRequest::SetParam(
+        UniqueKey::new(),
+        RadioParam::Channel,
+        15_u16.into(),
+    )) {
+        (RadioResponse::SetParam(_, _, Ok(value)), None) => {
+            assert_eq!(value, RadioParamValue::U16(15))
+        }
+        r => panic!("Unexpected response {:?}", r),
+    }
+    assert_eq!(radio.channel(), 15);
+    match radio.process_request(RadioRequest::SetParam(
+        UniqueKey::new(),
+        RadioParam::Channel,
+        27_u16.into(),
+    )) {
+        (RadioResponse::SetParam(_, _, Err(_)), None) => (),
+        r => panic!("Unexpected response {:?}", r),
+    }
+    assert_eq!(radio.channel(), 15);
+}
+
+#[test]
+fn test_simulated_radio_send() {
+    let mut radio = SimulatedRadio::new(SimulatedRadioConfig::default());
+    match radio.process_request(RadioRequest::SendPacket(UniqueKey::new(), vec![1, 2, 3])) {
+        (RadioResponse::SendPacket(_, Err(_)), None) => (),
+        r => panic!("Sending with radio off should fail: {:?}", r),
+    }
+    radio.process_request(RadioRequest::SetPower(UniqueKey::new(), true));
+    match radio.process_request(RadioRequest::SendPacket(UniqueKey::new(), vec![1, 2, 3])) {
+        (RadioResponse::SendPacket(_, Ok(())), Some(transmission)) => {
+            assert_eq!(transmission.channel, radio.channel());
+            assert_eq!(transmission.data, vec![1, 2, 3]);
+        }
+        r => panic!("Unexpected response {:?}", r),
+    }
+}
+
+#[test]
+fn test_simulated_radio_address_filter() {
+    let mut radio = configured_radio();
+    let mut frame = Frame {
+        version: FrameVersion::Ieee2003,
+        frame_pending: false,
+        acknowledge_request: true,
+        sequence_number: Some(0x42),
+        destination: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0000)),
+        }),
+        source: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x5678)),
+        }),
+        security: None,
+        header_ies: vec![],
+        payload_ies: vec![],
+        frame_type: FrameType::Data(Payload(vec![1, 2, 3])),
+    };
+    assert_eq!(
+        receive(&mut radio, pack_frame(&frame)),
+        (true, Some(vec![0x02, 0x00, 0x42]))
+    );
+    frame.destination = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Short(ShortAddress(0x0001)),
+    });
+    assert_eq!(receive(&mut radio, pack_frame(&frame)), (false, None));
+    frame.destination = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Short(ShortAddress::broadcast()),
+    });
+    assert_eq!(receive(&mut radio, pack_frame(&frame)), (true, None));
+}
+
+#[test]
+fn test_simulated_radio_pending_bit() {
+    let mut radio = configured_radio();
+    radio.process_request(RadioRequest::SetPendingExtended(
+        UniqueKey::new(),
+        3,
+        Some(0x0011223344556677),
+    ));
+    let mut frame = Frame {
+        version: FrameVersion::Ieee2003,
+        frame_pending: false,
+        acknowledge_request: true,
+        sequence_number: Some(0x10),
+        destination: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Short(ShortAddress(0x0000)),
+        }),
+        source: Some(FullAddress {
+            pan_id: PANID(0x1234),
+            address: Address::Extended(ExtendedAddress(0x0011223344556677)),
+        }),
+        security: None,
+        header_ies: vec![],
+        payload_ies: vec![],
+        frame_type: FrameType::Command(Command::DataRequest()),
+    };
+    assert_eq!(
+        receive(&mut radio, pack_frame(&frame)),
+        (true, Some(vec![0x12, 0x00, 0x10]))
+    );
+    frame.source = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Extended(ExtendedAddress(0x0011223344556688)),
+    });
+    assert_eq!(
+        receive(&mut radio, pack_frame(&frame)),
+        (true, Some(vec![0x02, 0x00, 0x10]))
+    );
+    radio.process_request(RadioRequest::InitPendingDataTable(UniqueKey::new()));
+    frame.source = Some(FullAddress {
+        pan_id: PANID(0x1234),
+        address: Address::Extended(ExtendedAddress(0x0011223344556677)),
+    });
+    assert_eq!(
+        receive(&mut radio, pack_frame(&frame)),
+        (true, Some(vec![0x02, 0x00, 0x10]))
+    );
+}
+
+/**
+ * Attaches a node to the medium of which the test plays the radio.
+ */
+fn attach_node(
+    executor: &AsyncStdExecutor,
+    medium: &VirtualMedium,
+) -> (
+    mpsc::UnboundedSender<SimulatedTransmission>,
+    mpsc::UnboundedReceiver<SimulatedReception>,
+) {
+    let (transmissions_in, transmissions_out) = mpsc::unbounded();
+    let (receptions_in, receptions_out) = mpsc::unbounded();
+    medium.attach(
+        executor,
+        SimulatedAir {
+            transmissions: transmissions_out,
+            receptions: receptions_in,
+            watch: SimulatedRadioWatch::new(SimulatedRadio::new(SimulatedRadioConfig::default())),
+        },
+    );
+    (transmissions_in, receptions_out)
+}
+
+#[test]
+fn test_medium_order_and_loss() {
+    let executor = AsyncStdExecutor::new();
+    let received = |seed| {
+        let medium = VirtualMedium::new(
+            Some(LinkConfig {
+                loss: 0.5,
+                latency: Duration::from_millis(5),
+                ..Default::default()
+            }),
+            seed,
+        );
+        let (sender, _) = attach_node(&executor, &medium);
+        let (_, mut receiver) = attach_node(&executor, &medium);
+        for data in 0..50 {
+            sender
+                .unbounded_send(SimulatedTransmission {
+                    channel: 26,
+                    data: vec![data],
+                })
+                .unwrap();
+        }
+        async_std::task::block_on(async {
+            let mut received = vec![];
+            while let Ok(Some(reception)) =
+                async_std::future::timeout(Duration::from_millis(100), receiver.next()).await
+            {
+                received.push(reception.packet.data[0]);
+            }
+            received
+        })
+    };
+    // Frames arrive in the order they were sent, and the same seed loses the same frames.
+    let first = received(1);
+    assert!(!first.is_empty() && first.len() < 50);
+    assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(received(1), first);
+    assert_ne!(received(2), first);
+}
+
+fn open_async(port: serialport::TTYPort) -> (async_std::fs::File, async_std::fs::File) {
+    let port_out = port.try_clone_native().unwrap();
+    unsafe {
+        (
+            async_std::fs::File::from_raw_fd(port.into_raw_fd()),
+            async_std::fs::File::from_raw_fd(port_out.into_raw_fd()),
+        )
+    }
+}
+
+#[test]
+fn test_emulator_over_pty() {
+    let executor = AsyncStdExecutor::new();
+    let (master, slave) = serialport::TTYPort::pair().unwrap();
+    let path = slave.name().unwrap();
+    let (read, write) = open_async(master);
+    let mut air = start_emulator(
+        executor.clone(),
+        read,
+        write,
+        SimulatedRadioConfig {
+            extended_address: 0x0123456789ABCDEF,
+            ..Default::default()
+        },
+    );
+    let port = serialport::TTYPort::open(&serialport::new(&path, 115200)).unwrap();
+    let (read, write) = open_async(port);
+    let (mut requests, mut responses, stats) = start_radio(executor.clone(), read, write);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        requests
+            .send(RadioRequest::GetParam(
+                UniqueKey::new(),
+                RadioParam::LongAddress,
+                RadioParamType::U64,
+            ))
+            .await
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::GetParam(_, RadioParam::LongAddress, Ok(value)) => {
+                assert_eq!(value, RadioParamValue::U64(0x0123456789ABCDEF))
+            }
+            r => panic!("Unexpected response {:?}", r),
+        }
+        requests
+            .send(RadioRequest::SetParam(
+                UniqueKey::new(),
+                RadioParam::Channel,
+                27_u16.into(),
+            ))
+            .await
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::SetParam(_, _, Err(RadioError::UnexpectedRetvalError(2, _))) => (),
+            r => panic!("Unexpected response {:?}", r),
+        }
+        requests
+            .send(RadioRequest::SetPower(UniqueKey::new(), true))
+            .await
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::SetPower(_, true, Ok(())) => (),
+            r => panic!("Unexpected response {:?}", r),
+        }
+        requests
+            .send(RadioRequest::SendPacket(UniqueKey::new(), vec![1, 2, 3]))
+            .await
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::SendPacket(_, Ok(())) => (),
+            r => panic!("Unexpected response {:?}", r),
+        }
+        assert_eq!(air.transmissions.next().await.unwrap().data, vec![1, 2, 3]);
+
+        let frame = Frame {
+            version: FrameVersion::Ieee2003,
+            frame_pending: false,
+            acknowledge_request: false,
+            sequence_number: Some(0x42),
+            destination: Some(FullAddress {
+                pan_id: PANID::broadcast(),
+                address: Address::Short(ShortAddress::broadcast()),
+            }),
+            source: None,
+            security: None,
+            header_ies: vec![],
+            payload_ies: vec![],
+            frame_type: FrameType::Data(Payload(vec![4, 5, 6])),
+        };
+        air.receptions
+            .unbounded_send(SimulatedReception {
+                channel: 26,
+                packet: RadioPacket {
+                    data: pack_frame(&frame),
+                    rssi: 0xC0,
+                    link_quality: 200,
+                },
+            })
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::OnPacket(packet) => {
+                assert_eq!(packet.data, pack_frame(&frame));
+                assert_eq!(packet.rssi, 0xC0);
+                assert_eq!(packet.link_quality, 200);
+            }
+            r => panic!("Unexpected response {:?}", r),
+        }
+        assert_eq!(stats.frames_received(), 5);
+        assert_eq!(stats.frames_dropped(), 0);
+    }))
+    .unwrap();
+    // Closing the radio would drop its async_std::fs::File with a blocking read in flight, which
+    // waits for that read to finish and thereby stalls an executor thread for the other tests.
+    std::mem::forget((requests, responses));
+}
+
+#[test]
+fn test_request_timeout() {
+    let executor = AsyncStdExecutor::new();
+    // The other end of the socket stays silent, like a dongle that hung.
+    let (port, _dongle) = async_std::os::unix::net::UnixStream::pair().unwrap();
+    let (mut requests, mut responses, _) = start_radio(executor.clone(), port.clone(), port);
+    async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), async {
+        let key = UniqueKey::new();
+        requests
+            .send(RadioRequest::GetParam(
+                key,
+                RadioParam::Channel,
+                RadioParamType::U16,
+            ))
+            .await
+            .unwrap();
+        match responses.next().await.unwrap() {
+            RadioResponse::GetParam(
+                response_key,
+                RadioParam::Channel,
+                Err(RadioError::Timeout),
+            ) => {
+                assert_eq!(response_key, key)
+            }
+            r => panic!("Unexpected response {:?}", r),
+        }
+    }))
+    .unwrap();
+}
+
+#[test]
+fn test_raw_radio_resync() {
+    let first = RawRadioMessage {
+        command_id: RawRadioCommand::Ok,
+        request_id: 0x1234,
+        data: vec![0x00, 0x00],
+    };
+    let second = RawRadioMessage {
+        command_id: RawRadioCommand::OnPacket,
+        request_id: 0,
+        data: vec![0x02, 0x00, 0x42, 0xC0, 0xFF],
+    };
+    let third = RawRadioMessage {
+        command_id: RawRadioCommand::Err,
+        request_id: 0x5678,
+        data: vec![],
+    };
+    let mut input = vec![0x00, b'Z', b'P'];
+    input.append(&mut first.clone().into());
+    // Unknown command
+    input.extend_from_slice(b"ZPB\x42\x00\x01\x00\x02ab");
+    input.append(&mut second.clone().into());
+    // Garbled length
+    input.extend_from_slice(b"ZPB\x80\x00\x01\xFF\xFF");
+    input.append(&mut third.clone().into());
+
+    let stats = Arc::new(RawRadioStats::default());
+    let stream = RawRadioStream::new(futures::io::Cursor::new(input), stats.clone());
+    let messages: Vec<_> = async_std::task::block_on(stream.collect());
+    assert_eq!(
+        messages,
+        vec![
+            Ok(first),
+            Err(RawRadioError::UnknownCommand(0x42)),
+            Ok(second),
+            Err(RawRadioError::InvalidLength(0xFFFF)),
+            Ok(third),
+        ]
+    );
+    assert_eq!(stats.frames_received(), 3);
+    assert_eq!(stats.frames_dropped(), 2);
+    assert_eq!(stats.bytes_skipped(), 3 + 10 + 8);
+}
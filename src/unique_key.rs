@@ -13,3 +13,9 @@ impl UniqueKey {
         UniqueKey(ProcessUniqueId::new())
     }
 }
+
+impl Default for UniqueKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}